@@ -0,0 +1,294 @@
+use std::ops::RangeBounds;
+use iter::Iter;
+use label::{Label, LastLabel};
+use map::TreeMap;
+use measure::Measure;
+use tree_array::{bounds, TreeArray};
+
+// A position registered in a sequence, which follows the values around it as values get
+// inserted and removed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Anchor(u64);
+
+// Where an anchor goes when values are inserted at its position: it stays before them with a
+// left affinity, and moves after them with a right affinity.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Affinity {
+    Left,
+    Right,
+}
+
+// A set of anchors into a sequence, kept up to date by telling it of the insertions and removals
+// made to the sequence.
+// Anchors of each affinity are kept in order of position in a tree array, which holds the gap
+// from each anchor to the previous one, so that an insertion only widens one gap. Each anchor
+// also gets a label, increasing along the tree array, so that it is found by walking down the
+// tree by label, summing gaps on the way to its position. Walks splay the mark they reach to the
+// root, so that resolving an anchor takes logarithmic time amortized.
+// Labels are handed out by the order maintenance of `insert_labelled`, as for handles.
+pub struct Anchors {
+    marks: [TreeArray<Mark, Span>; 2],
+    labels: TreeMap<u64, (Affinity, u64)>,
+    next_id: u64,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Mark {
+    gap: usize,
+    label: u64,
+    id: u64,
+}
+
+// Summary of a run of marks: the sum of their gaps, and the label of the last one.
+#[derive(Clone, Copy, Debug, Default)]
+struct Span {
+    gaps: usize,
+    last: Option<u64>,
+}
+
+impl Label for Mark {
+    fn label(&self) -> u64 {
+        self.label
+    }
+
+    fn set_label(&mut self, label: u64) {
+        self.label = label;
+    }
+}
+
+impl LastLabel for Span {
+    fn last_label(&self) -> Option<u64> {
+        self.last
+    }
+}
+
+impl Measure<Mark> for Span {
+    fn identity() -> Span {
+        Span::default()
+    }
+
+    fn measure(mark: &Mark) -> Span {
+        Span { gaps: mark.gap, last: Some(mark.label) }
+    }
+
+    fn combine(&self, other: &Span) -> Span {
+        Span { gaps: self.gaps + other.gaps, last: other.last.or(self.last) }
+    }
+}
+
+impl Anchors {
+    pub fn new() -> Anchors {
+        let marks = [TreeArray::default(), TreeArray::default()];
+        Anchors { marks, labels: TreeMap::new(), next_id: 0 }
+    }
+
+    // Number of anchors.
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    // Registers an anchor at a given position.
+    pub fn add(&mut self, index: usize, affinity: Affinity) -> Anchor {
+        let id = self.next_id;
+        self.next_id += 1;
+        let marks = &mut self.marks[affinity as usize];
+        let (k, before) = marks.locate_by_measure_mut(|span| span.gaps > index)
+            .unwrap_or((marks.len(), marks.measure()));
+        let gap = index - before.gaps;
+        if let Some(&next) = marks.get(k) {
+            marks.set(k, Mark { gap: next.gap - gap, ..next });
+        }
+        let labels = &mut self.labels;
+        marks.insert_labelled(k, Mark { gap, label: 0, id }, |mark| {
+            labels.insert(mark.id, (affinity, mark.label));
+        });
+        Anchor(id)
+    }
+
+    // Current position of an anchor, or None if it was removed.
+    pub fn resolve(&mut self, anchor: Anchor) -> Option<usize> {
+        let &(affinity, label) = self.labels.get(&anchor.0)?;
+        let marks = &mut self.marks[affinity as usize];
+        let (k, before) = marks.locate_label(label)?;
+        Some(before.gaps + marks[k].gap)
+    }
+
+    // Unregisters an anchor. Returns whether it was registered.
+    pub fn remove(&mut self, anchor: Anchor) -> bool {
+        let (affinity, label) = match self.labels.remove(&anchor.0) {
+            Some(found) => found,
+            None => return false,
+        };
+        let marks = &mut self.marks[affinity as usize];
+        let (k, _) = marks.locate_label(label).unwrap();
+        let mark = marks.remove(k).unwrap();
+        if let Some(&next) = marks.get(k) {
+            marks.set(k, Mark { gap: next.gap + mark.gap, ..next });
+        }
+        true
+    }
+
+    // Shifts the anchors after `count` values were inserted at a given index.
+    pub fn insert(&mut self, index: usize, count: usize) {
+        for (i, marks) in self.marks.iter_mut().enumerate() {
+            let found = if i == Affinity::Left as usize {
+                marks.locate_by_measure_mut(|span| span.gaps > index)
+            } else {
+                marks.locate_by_measure_mut(|span| span.gaps >= index)
+            };
+            if let Some((k, _)) = found {
+                let mark = marks[k];
+                marks.set(k, Mark { gap: mark.gap + count, ..mark });
+            }
+        }
+    }
+
+    // Shifts the anchors after a range of values was removed. Anchors within the range move to
+    // its start, which takes time in the number of them.
+    pub fn remove_range<R>(&mut self, range: R) where R: RangeBounds<usize> {
+        let (start, end) = bounds(range, usize::MAX);
+        for marks in self.marks.iter_mut() {
+            let (k, before) = match marks.locate_by_measure_mut(|span| span.gaps > start) {
+                Some(found) => found,
+                None => continue,
+            };
+            let after = marks.locate_by_measure_mut(|span| span.gaps > end);
+            let next = after.map_or(marks.len(), |(k, _)| k);
+            let inside: TreeArray<Mark, Span> = marks.take(k..next).into_iter().enumerate()
+                .map(|(i, mark)| Mark { gap: if i == 0 { start - before.gaps } else { 0 }, ..mark })
+                .collect();
+            marks.insert_all(k, inside);
+            if let Some((next_k, prefix)) = after {
+                let mark = marks[next_k];
+                let position = prefix.gaps + mark.gap - (end - start);
+                let previous = if k < next { start } else { before.gaps };
+                marks.set(next_k, Mark { gap: position - previous, ..mark });
+            }
+        }
+    }
+
+    // Depth of the deepest tree, to check that resolving anchors keeps them shallow.
+    #[cfg(test)]
+    pub(crate) fn depth(&self) -> usize {
+        self.marks.iter().map(|marks| marks.depth()).max().unwrap().max(self.labels.depth())
+    }
+}
+
+impl Default for Anchors {
+    fn default() -> Anchors {
+        Anchors::new()
+    }
+}
+
+// A tree array along with anchors into it, which its insertions and removals keep up to date.
+pub struct AnchoredTreeArray<V> {
+    values: TreeArray<V>,
+    anchors: Anchors,
+}
+
+impl<V> AnchoredTreeArray<V> {
+    pub fn new() -> AnchoredTreeArray<V> {
+        AnchoredTreeArray { values: TreeArray::new(), anchors: Anchors::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&V> {
+        self.values.get(index)
+    }
+
+    pub fn iter(&self) -> Iter<'_, V> {
+        self.values.iter()
+    }
+
+    // The values, without the anchors.
+    pub fn values(&self) -> &TreeArray<V> {
+        &self.values
+    }
+
+    pub fn anchors(&self) -> &Anchors {
+        &self.anchors
+    }
+
+    // Panics if the index is out of bounds.
+    pub fn set(&mut self, index: usize, value: V) {
+        self.values.set(index, value);
+    }
+
+    // Panics if the index is greater than the length.
+    pub fn insert(&mut self, index: usize, value: V) {
+        self.values.insert(index, value);
+        self.anchors.insert(index, 1);
+    }
+
+    // Inserts all values of another array at a given index.
+    // Panics if the index is greater than the length.
+    pub fn insert_all(&mut self, index: usize, other: TreeArray<V>) {
+        let count = other.len();
+        self.values.insert_all(index, other);
+        self.anchors.insert(index, count);
+    }
+
+    pub fn push(&mut self, value: V) {
+        let len = self.len();
+        self.insert(len, value);
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<V> {
+        let value = self.values.remove(index)?;
+        self.anchors.remove_range(index..index + 1);
+        Some(value)
+    }
+
+    pub fn pop(&mut self) -> Option<V> {
+        let len = self.len();
+        self.remove(len.checked_sub(1)?)
+    }
+
+    // Panics if the range is out of bounds.
+    pub fn remove_range<R>(&mut self, range: R) where R: RangeBounds<usize> {
+        let (start, end) = bounds(range, self.len());
+        self.values.remove_range(start..end);
+        self.anchors.remove_range(start..end);
+    }
+
+    // Registers an anchor at a given position, from zero to the length included.
+    // Panics if the position is past the length.
+    pub fn anchor(&mut self, index: usize, affinity: Affinity) -> Anchor {
+        let len = self.len();
+        assert!(index <= len, "anchor index (is {}) should be <= len (is {})", index, len);
+        self.anchors.add(index, affinity)
+    }
+
+    // Current position of an anchor, or None if it was removed.
+    pub fn resolve(&mut self, anchor: Anchor) -> Option<usize> {
+        self.anchors.resolve(anchor)
+    }
+
+    // Unregisters an anchor. Returns whether it was registered.
+    pub fn remove_anchor(&mut self, anchor: Anchor) -> bool {
+        self.anchors.remove(anchor)
+    }
+}
+
+impl<V> Default for AnchoredTreeArray<V> {
+    fn default() -> AnchoredTreeArray<V> {
+        AnchoredTreeArray::new()
+    }
+}
+
+impl<V> From<TreeArray<V>> for AnchoredTreeArray<V> {
+    fn from(values: TreeArray<V>) -> AnchoredTreeArray<V> {
+        AnchoredTreeArray { values, anchors: Anchors::new() }
+    }
+}
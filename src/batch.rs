@@ -0,0 +1,101 @@
+use std::mem;
+use error::Error;
+use measure::Measure;
+use tree_array::TreeArray;
+
+// Edits to a tree array, collected to be made all at once. The indices of all edits refer to the
+// array as it was when the batch was started, whatever the edits collected before them, as when
+// many cursors each edit where they stand.
+// Edits are sorted by index and made in a single pass over the array, from left to right: the
+// values up to each index edited are split off those left and joined to those done, and the edits
+// at that index are made where both meet, at the end of one tree and at the start of the other,
+// without walking down from the root of the whole array for each edit.
+pub struct EditBatch<'a, V, M = ()> {
+    array: &'a mut TreeArray<V, M>,
+    edits: Vec<(usize, Op<V>)>,
+}
+
+enum Op<V> {
+    Insert(V),
+    Remove,
+    Set(V),
+}
+
+impl<V, M> TreeArray<V, M> where M: Measure<V> {
+    // Starts a batch of edits to the array, made when the batch is applied.
+    pub fn edit_batch(&mut self) -> EditBatch<'_, V, M> {
+        EditBatch { array: self, edits: Vec::new() }
+    }
+}
+
+impl<'a, V, M> EditBatch<'a, V, M> where M: Measure<V> {
+    // Inserts a value before the one at an index, or at the end for the length. Values inserted
+    // at the same index keep the order in which they were collected.
+    pub fn insert(&mut self, index: usize, value: V) {
+        self.edits.push((index, Op::Insert(value)));
+    }
+
+    // Removes the value at an index. Of the removals and replacements of a value, the last one
+    // collected is made.
+    pub fn remove(&mut self, index: usize) {
+        self.edits.push((index, Op::Remove));
+    }
+
+    // Replaces the value at an index.
+    pub fn set(&mut self, index: usize, value: V) {
+        self.edits.push((index, Op::Set(value)));
+    }
+
+    // Number of edits collected.
+    pub fn len(&self) -> usize {
+        self.edits.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.edits.is_empty()
+    }
+
+    // Makes the edits collected. If an index is out of bounds, the error tells which, and the
+    // array is left as it was.
+    pub fn apply(self) -> Result<(), Error> {
+        let EditBatch { array, mut edits } = self;
+        let len = array.len();
+        for &(index, ref op) in &edits {
+            let end = match *op {
+                Op::Insert(_) => len + 1,
+                Op::Remove | Op::Set(_) => len,
+            };
+            if index >= end {
+                return Err(Error::OutOfBounds { index, len });
+            }
+        }
+        edits.sort_by_key(|&(index, _)| index);
+        // `offset` is the index, in the array as it was, of the first value left.
+        let (mut done, mut left, mut offset) = (TreeArray::default(), mem::take(array), 0);
+        let mut edits = edits.into_iter().peekable();
+        while let Some(&(index, _)) = edits.peek() {
+            let rest = left.split_off(index - offset);
+            done.append(&mut mem::replace(&mut left, rest));
+            offset = index;
+            // Values inserted at the index go in the order collected, and the last removal or
+            // replacement collected is made.
+            let mut last = None;
+            while let Some((_, op)) = edits.next_if(|&(i, _)| i == index) {
+                match op {
+                    Op::Insert(value) => done.push(value),
+                    op => last = Some(op),
+                }
+            }
+            if let Some(op) = last {
+                left.pop_front();
+                offset += 1;
+                if let Op::Set(value) = op {
+                    done.push(value);
+                }
+            }
+        }
+        done.append(&mut left);
+        *array = done;
+        Ok(())
+    }
+}
@@ -0,0 +1,289 @@
+use std::fmt;
+use std::iter::FromIterator;
+use measure::Measure;
+use tree_array::TreeArray;
+
+// Most bits packed in a block.
+const BLOCK: u32 = 64;
+
+// A dynamic bit vector: bits packed in blocks of up to 64, in a tree array measured by the number
+// of bits and of ones in each subtree. Besides inserting and removing bits anywhere in
+// logarithmic time, it counts the ones before an index (rank), and finds the index of the k-th
+// one (select), by walking down the tree. Lookups splay the block they find to the root, where it
+// is read, so that they take logarithmic time amortized, which is why they need a mutable
+// reference.
+pub struct TreeBits {
+    blocks: TreeArray<Block, Count>,
+}
+
+// Bits of a block, the first one being the least significant.
+#[derive(Clone, Copy, Debug)]
+struct Block {
+    bits: u64,
+    len: u32,
+}
+
+// Summary of a run of blocks.
+#[derive(Clone, Copy, Debug, Default)]
+struct Count {
+    bits: usize,
+    ones: usize,
+}
+
+impl Measure<Block> for Count {
+    fn identity() -> Count {
+        Count::default()
+    }
+
+    fn measure(block: &Block) -> Count {
+        Count { bits: block.len as usize, ones: block.bits.count_ones() as usize }
+    }
+
+    fn combine(&self, other: &Count) -> Count {
+        Count { bits: self.bits + other.bits, ones: self.ones + other.ones }
+    }
+}
+
+// Mask of the first `n` bits of a block.
+fn mask(n: u32) -> u64 {
+    match n {
+        BLOCK => !0,
+        _ => (1 << n) - 1,
+    }
+}
+
+impl Block {
+    fn get(&self, i: u32) -> bool {
+        self.bits >> i & 1 == 1
+    }
+
+    fn insert(&mut self, i: u32, bit: bool) {
+        let high = (self.bits & !mask(i)) << 1;
+        self.bits = self.bits & mask(i) | (bit as u64) << i | high;
+        self.len += 1;
+    }
+
+    fn remove(&mut self, i: u32) -> bool {
+        let bit = self.get(i);
+        let high = self.bits.checked_shr(i + 1).unwrap_or(0) << i;
+        self.bits = self.bits & mask(i) | high;
+        self.len -= 1;
+        bit
+    }
+
+    // Splits off the bits from `i` on into a block of their own.
+    fn split_off(&mut self, i: u32) -> Block {
+        let tail = Block { bits: self.bits.checked_shr(i).unwrap_or(0), len: self.len - i };
+        self.bits &= mask(i);
+        self.len = i;
+        tail
+    }
+}
+
+impl TreeBits {
+    pub fn new() -> TreeBits {
+        TreeBits { blocks: TreeArray::default() }
+    }
+
+    // Number of bits, in constant time.
+    pub fn len(&self) -> usize {
+        self.blocks.measure().bits
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Number of ones, in constant time.
+    pub fn count_ones(&self) -> usize {
+        self.blocks.measure().ones
+    }
+
+    pub fn get(&mut self, index: usize) -> Option<bool> {
+        let (_, before) = self.locate(index)?;
+        Some(self.root().get((index - before.bits) as u32))
+    }
+
+    // Panics if the index is out of bounds.
+    pub fn set(&mut self, index: usize, bit: bool) {
+        let (k, before) = self.locate(index).expect("bit index out of bounds");
+        let mut block = self.root();
+        let i = (index - before.bits) as u32;
+        block.bits = block.bits & !(1 << i) | (bit as u64) << i;
+        self.blocks.set(k, block);
+    }
+
+    // Number of ones before an index. Panics if the index is past the length.
+    pub fn rank1(&mut self, index: usize) -> usize {
+        let len = self.len();
+        assert!(index <= len, "bit index (is {}) should be <= len (is {})", index, len);
+        match self.locate(index) {
+            Some((_, before)) => {
+                let block = self.root();
+                before.ones + (block.bits & mask((index - before.bits) as u32)).count_ones() as usize
+            },
+            None => self.count_ones(),
+        }
+    }
+
+    // Number of zeros before an index. Panics if the index is past the length.
+    pub fn rank0(&mut self, index: usize) -> usize {
+        index - self.rank1(index)
+    }
+
+    // Index of the one preceded by `k` ones, or None if there are not that many ones.
+    pub fn select1(&mut self, k: usize) -> Option<usize> {
+        let (_, before) = self.blocks.locate_by_measure_mut(|count| count.ones > k)?;
+        let block = self.root();
+        Some(before.bits + nth_one(block.bits, k - before.ones) as usize)
+    }
+
+    // Index of the zero preceded by `k` zeros, or None if there are not that many zeros.
+    pub fn select0(&mut self, k: usize) -> Option<usize> {
+        let (_, before) = self.blocks.locate_by_measure_mut(|count| count.bits - count.ones > k)?;
+        let block = self.root();
+        let zeros = !block.bits & mask(block.len);
+        Some(before.bits + nth_one(zeros, k - (before.bits - before.ones)) as usize)
+    }
+
+    // Inserts a bit at an index. Full blocks are split in half to make room.
+    // Panics if the index is past the length.
+    pub fn insert_bit(&mut self, index: usize, bit: bool) {
+        let len = self.len();
+        assert!(index <= len, "bit index (is {}) should be <= len (is {})", index, len);
+        let (k, before) = match self.locate(index) {
+            Some(found) => found,
+            None if self.blocks.is_empty() => {
+                self.blocks.push(Block { bits: bit as u64, len: 1 });
+                return;
+            },
+            None => {
+                let k = self.blocks.len() - 1;
+                let last = *self.blocks.splay_get(k).unwrap();
+                (k, Count { bits: len - last.len as usize, ones: 0 })
+            },
+        };
+        let mut block = self.root();
+        let i = (index - before.bits) as u32;
+        if block.len < BLOCK {
+            block.insert(i, bit);
+            self.blocks.set(k, block);
+            return;
+        }
+        let mut tail = block.split_off(BLOCK / 2);
+        if i < BLOCK / 2 {
+            block.insert(i, bit);
+        } else {
+            tail.insert(i - BLOCK / 2, bit);
+        }
+        self.blocks.set(k, block);
+        self.blocks.insert(k + 1, tail);
+    }
+
+    // Removes the bit at an index and returns it, or None if the index is out of bounds.
+    // Blocks are merged with their neighbour once they fit in one.
+    pub fn remove_bit(&mut self, index: usize) -> Option<bool> {
+        let (k, before) = self.locate(index)?;
+        let mut block = self.root();
+        let bit = block.remove((index - before.bits) as u32);
+        self.blocks.set(k, block);
+        self.merge(k + 1);
+        self.merge(k);
+        Some(bit)
+    }
+
+    pub fn push(&mut self, bit: bool) {
+        let len = self.len();
+        self.insert_bit(len, bit);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = bool> + '_ {
+        self.blocks.iter().flat_map(|block| (0..block.len).map(move |i| block.get(i)))
+    }
+
+    // Finds the block holding the bit at an index, along with the summary of the blocks before.
+    // The block is splayed to the root.
+    fn locate(&mut self, index: usize) -> Option<(usize, Count)> {
+        self.blocks.locate_by_measure_mut(|count| count.bits > index)
+    }
+
+    // Block at the root of the tree, where the last lookup splayed the block it found.
+    fn root(&self) -> Block {
+        self.blocks.root.as_ref().expect("no block at the root").value
+    }
+
+    // Depth of the tree of blocks, to check that lookups keep it shallow.
+    #[cfg(test)]
+    pub(crate) fn depth(&self) -> usize {
+        self.blocks.depth()
+    }
+
+    // Merges the block at an index into the one before it, if they fit in a single block.
+    fn merge(&mut self, k: usize) {
+        if k == 0 || k >= self.blocks.len() || self.blocks[k - 1].len + self.blocks[k].len > BLOCK {
+            return;
+        }
+        let tail = self.blocks.remove(k).unwrap();
+        let mut head = self.blocks[k - 1];
+        head.bits |= tail.bits.checked_shl(head.len).unwrap_or(0);
+        head.len += tail.len;
+        self.blocks.set(k - 1, head);
+    }
+}
+
+// Position of the one preceded by `n` ones in a block, which has more ones than that.
+fn nth_one(mut bits: u64, n: usize) -> u32 {
+    for _ in 0..n {
+        bits &= bits - 1;
+    }
+    bits.trailing_zeros()
+}
+
+impl Default for TreeBits {
+    fn default() -> TreeBits {
+        TreeBits::new()
+    }
+}
+
+impl Clone for TreeBits {
+    fn clone(&self) -> TreeBits {
+        TreeBits { blocks: self.blocks.clone() }
+    }
+}
+
+// Bits are packed into full blocks.
+impl FromIterator<bool> for TreeBits {
+    fn from_iter<I>(iter: I) -> TreeBits where I: IntoIterator<Item = bool> {
+        let mut blocks = TreeArray::default();
+        let mut block = Block { bits: 0, len: 0 };
+        for bit in iter {
+            if block.len == BLOCK {
+                blocks.push(block);
+                block = Block { bits: 0, len: 0 };
+            }
+            block.insert(block.len, bit);
+        }
+        if block.len > 0 {
+            blocks.push(block);
+        }
+        TreeBits { blocks }
+    }
+}
+
+impl PartialEq for TreeBits {
+    fn eq(&self, other: &TreeBits) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+impl Eq for TreeBits {}
+
+// Formats as a string of zeros and ones, like `0110`.
+impl fmt::Debug for TreeBits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for bit in self.iter() {
+            f.write_str(if bit { "1" } else { "0" })?;
+        }
+        Ok(())
+    }
+}
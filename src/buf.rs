@@ -0,0 +1,123 @@
+use std::io::IoSlice;
+use bytes::buf::UninitSlice;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use measure::{TotalWidth, Width};
+use tree_array::TreeArray;
+
+// Most bytes gathered by an appender before they become a chunk.
+const CHUNK: usize = 4096;
+
+// A rope of bytes: shared chunks of bytes in a tree array, indexed by offset.
+// It reads as a `Buf`, handing out its chunks as they are, and takes new bytes at its end through
+// an appender, so that it can serve as a scatter/gather buffer.
+pub type ByteRope = TreeArray<Bytes, TotalWidth>;
+
+impl Width for Bytes {
+    fn width(&self) -> usize {
+        self.len()
+    }
+}
+
+// Reading consumes the bytes from the front, one chunk at a time.
+impl Buf for TreeArray<Bytes, TotalWidth> {
+    fn remaining(&self) -> usize {
+        self.total_width()
+    }
+
+    fn chunk(&self) -> &[u8] {
+        self.iter().find(|chunk| !chunk.is_empty()).map_or(&[], |chunk| chunk.as_ref())
+    }
+
+    fn chunks_vectored<'a>(&'a self, dst: &mut [IoSlice<'a>]) -> usize {
+        let mut n = 0;
+        for (slot, chunk) in dst.iter_mut().zip(self.iter().filter(|chunk| !chunk.is_empty())) {
+            *slot = IoSlice::new(chunk);
+            n += 1;
+        }
+        n
+    }
+
+    fn advance(&mut self, mut cnt: usize) {
+        assert!(cnt <= self.remaining(), "cannot advance past the remaining bytes");
+        while cnt > 0 {
+            let mut chunk = self.pop_front().unwrap();
+            if chunk.len() > cnt {
+                chunk.advance(cnt);
+                self.push_front(chunk);
+                return;
+            }
+            cnt -= chunk.len();
+        }
+    }
+
+    // Bytes within the first chunk are shared rather than copied. The chunk is splayed to the
+    // root, so that the advance past it, which follows, takes little time.
+    fn copy_to_bytes(&mut self, len: usize) -> Bytes {
+        match self.splay_first() {
+            Some(chunk) if chunk.len() >= len => {
+                let bytes = chunk.slice(..len);
+                self.advance(len);
+                bytes
+            },
+            _ => {
+                assert!(len <= self.remaining(), "cannot copy past the remaining bytes");
+                let mut bytes = BytesMut::with_capacity(len);
+                while bytes.len() < len {
+                    let n = (len - bytes.len()).min(self.chunk().len());
+                    bytes.extend_from_slice(&self.chunk()[..n]);
+                    self.advance(n);
+                }
+                bytes.freeze()
+            },
+        }
+    }
+}
+
+impl TreeArray<Bytes, TotalWidth> {
+    // Appends bytes at the end, gathered into chunks of a few kilobytes.
+    pub fn appender(&mut self) -> Appender<'_> {
+        Appender { rope: self, buf: BytesMut::new() }
+    }
+}
+
+// Writer of bytes at the end of a rope. The bytes reach the rope once a chunk is full, or when
+// the appender is flushed or dropped.
+pub struct Appender<'a> {
+    rope: &'a mut ByteRope,
+    buf: BytesMut,
+}
+
+impl<'a> Appender<'a> {
+    // Appends the bytes gathered so far to the rope.
+    pub fn flush(&mut self) {
+        if !self.buf.is_empty() {
+            self.rope.push(self.buf.split().freeze());
+        }
+    }
+}
+
+unsafe impl<'a> BufMut for Appender<'a> {
+    fn remaining_mut(&self) -> usize {
+        self.buf.remaining_mut()
+    }
+
+    unsafe fn advance_mut(&mut self, cnt: usize) {
+        self.buf.advance_mut(cnt);
+        if self.buf.len() >= CHUNK {
+            self.flush();
+        }
+    }
+
+    fn chunk_mut(&mut self) -> &mut UninitSlice {
+        if self.buf.capacity() == self.buf.len() {
+            self.buf.reserve(CHUNK - self.buf.len() % CHUNK);
+        }
+        self.buf.chunk_mut()
+    }
+}
+
+impl<'a> Drop for Appender<'a> {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
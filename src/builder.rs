@@ -0,0 +1,65 @@
+use measure::Measure;
+use tree_array::{build, TreeArray};
+
+// Number of values held by each chunk of a builder.
+const CHUNK: usize = 1024;
+
+// Builds a tree array out of values pushed one by one, for when they come from a stream whose
+// length is not known upfront. Values are buffered in fixed-size chunks, so that pushing never
+// moves the values already pushed, and the tree is built perfectly balanced in one go at the end.
+pub struct TreeArrayBuilder<V> {
+    chunks: Vec<Vec<V>>,
+    len: usize,
+}
+
+impl<V> TreeArrayBuilder<V> {
+    pub fn new() -> TreeArrayBuilder<V> {
+        TreeArrayBuilder { chunks: Vec::new(), len: 0 }
+    }
+
+    // Appends a value, in amortized constant time.
+    pub fn push(&mut self, value: V) {
+        match self.chunks.last_mut() {
+            Some(chunk) if chunk.len() < CHUNK => chunk.push(value),
+            _ => {
+                let mut chunk = Vec::with_capacity(CHUNK);
+                chunk.push(value);
+                self.chunks.push(chunk);
+            },
+        }
+        self.len += 1;
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // Builds the tree array out of the values pushed so far, in linear time.
+    pub fn finish(self) -> TreeArray<V> {
+        self.build()
+    }
+
+    // Builds a tree array of any measure, for collecting values into measured arrays.
+    pub(crate) fn build<M>(self) -> TreeArray<V, M> where M: Measure<V> {
+        let len = self.len;
+        TreeArray { root: build(&mut self.chunks.into_iter().flatten(), len) }
+    }
+}
+
+impl<V> Default for TreeArrayBuilder<V> {
+    fn default() -> TreeArrayBuilder<V> {
+        TreeArrayBuilder::new()
+    }
+}
+
+impl<V> Extend<V> for TreeArrayBuilder<V> {
+    fn extend<I>(&mut self, iter: I) where I: IntoIterator<Item = V> {
+        for value in iter {
+            self.push(value);
+        }
+    }
+}
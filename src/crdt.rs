@@ -0,0 +1,204 @@
+use std::mem;
+use handle::{Handle, HandleTreeArray};
+use map::TreeMap;
+use measure::Measure;
+
+// Identifies an element of a replicated sequence: a Lamport timestamp, greater than those of all
+// elements its replica knew of when inserting it, along with the replica that inserted it.
+// Stamps are ordered by timestamp, then by replica.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Stamp {
+    pub counter: u64,
+    pub replica: u64,
+}
+
+// Causal metadata of an element: its stamp, and the stamp of the element it was inserted right
+// after, if any, which its replica had seen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Meta {
+    pub stamp: Stamp,
+    pub after: Option<Stamp>,
+}
+
+// An edit made on a replica, to be sent to the others.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Operation<V> {
+    Insert { stamp: Stamp, after: Option<Stamp>, value: V },
+    Delete { stamp: Stamp },
+}
+
+// A sequence replicated across replicas, each editing its own copy and applying the edits of the
+// others as they come, in any order: once all replicas applied the same edits, they hold the same
+// values. It is a replicated growable array (RGA).
+// Each element is inserted right after another one, its origin, or at the start. Elements with the
+// same origin are ordered by decreasing stamp, each followed by the elements inserted after it,
+// which have greater stamps: an element is placed right after its origin, past those with greater
+// stamps. Removed elements stay as tombstones, as later insertions may refer to them.
+// Elements are kept in a tree array with handles, found by stamp in logarithmic time, which
+// counts those not removed, so that indices of the values are found in logarithmic time too.
+pub struct Rga<V> {
+    replica: u64,
+    // Greatest timestamp seen.
+    clock: u64,
+    elements: HandleTreeArray<Element<V>, Visible>,
+    handles: TreeMap<Stamp, Handle>,
+    // Edits of other replicas waiting for the elements they refer to.
+    pending: Vec<Operation<V>>,
+}
+
+struct Element<V> {
+    // None once removed.
+    value: Option<V>,
+    meta: Meta,
+}
+
+// Number of elements not removed.
+#[derive(Clone, Copy, Debug, Default)]
+struct Visible(usize);
+
+impl<V> Measure<Element<V>> for Visible {
+    fn identity() -> Visible {
+        Visible(0)
+    }
+
+    fn measure(element: &Element<V>) -> Visible {
+        Visible(element.value.is_some() as usize)
+    }
+
+    fn combine(&self, other: &Visible) -> Visible {
+        Visible(self.0 + other.0)
+    }
+}
+
+impl<V> Rga<V> {
+    // An empty sequence, edited by a replica which no other one shares the identifier of.
+    pub fn new(replica: u64) -> Rga<V> {
+        Rga { replica, clock: 0, elements: HandleTreeArray::new(), handles: TreeMap::new(),
+              pending: Vec::new() }
+    }
+
+    pub fn replica(&self) -> u64 {
+        self.replica
+    }
+
+    pub fn len(&self) -> usize {
+        self.elements.measure().0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<&V> {
+        self.elements.get(self.element_at(index)?)?.value.as_ref()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &V> {
+        self.elements.iter().filter_map(|element| element.value.as_ref())
+    }
+
+    // Causal metadata of the value at an index.
+    pub fn meta(&self, index: usize) -> Option<Meta> {
+        Some(self.elements.get(self.element_at(index)?)?.meta)
+    }
+
+    // Index of the value of an element, or None if it was removed or is not known yet.
+    pub fn index_of(&mut self, stamp: Stamp) -> Option<usize> {
+        let k = self.find(stamp)?;
+        self.elements.get(k)?.value.as_ref()?;
+        Some(self.elements.summary_before(k).0)
+    }
+
+    // Number of edits of other replicas waiting for the elements they refer to.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    // Inserts a value at an index, giving back the edit to send to the other replicas.
+    // Panics if the index is greater than the length.
+    pub fn local_insert(&mut self, index: usize, value: V) -> Operation<V> where V: Clone {
+        let len = self.len();
+        if index > len {
+            panic!("insertion index (is {}) should be <= len (is {})", index, len);
+        }
+        let after = match index {
+            0 => None,
+            _ => self.meta(index - 1).map(|meta| meta.stamp),
+        };
+        let stamp = Stamp { counter: self.clock + 1, replica: self.replica };
+        let op = Operation::Insert { stamp, after, value: value.clone() };
+        self.insert(Meta { stamp, after }, value);
+        op
+    }
+
+    // Removes the value at an index, giving back the edit to send to the other replicas, or None
+    // if the index is out of bounds.
+    pub fn local_delete(&mut self, index: usize) -> Option<Operation<V>> {
+        let k = self.element_at(index)?;
+        let stamp = self.elements.get(k)?.meta.stamp;
+        self.elements.modify(k, |element| element.value = None);
+        Some(Operation::Delete { stamp })
+    }
+
+    // Applies an edit of another replica. Edits may come more than once, and in any order: those
+    // referring to elements not known yet wait for them to be inserted.
+    pub fn apply_remote(&mut self, op: Operation<V>) {
+        self.pending.push(op);
+        // Each edit applied may let others waiting for it be applied in turn.
+        let mut applied = true;
+        while applied {
+            applied = false;
+            for op in mem::take(&mut self.pending) {
+                match self.integrate(op) {
+                    Ok(()) => applied = true,
+                    Err(op) => self.pending.push(op),
+                }
+            }
+        }
+    }
+
+    // Applies an edit, or gives it back if the element it refers to is not known yet.
+    fn integrate(&mut self, op: Operation<V>) -> Result<(), Operation<V>> {
+        match op {
+            Operation::Insert { stamp, .. } if self.handles.contains_key(&stamp) => Ok(()),
+            Operation::Insert { stamp, after: Some(after), value } if self.find(after).is_none() =>
+                Err(Operation::Insert { stamp, after: Some(after), value }),
+            Operation::Insert { stamp, after, value } => {
+                self.insert(Meta { stamp, after }, value);
+                Ok(())
+            },
+            Operation::Delete { stamp } => match self.find(stamp) {
+                Some(k) => {
+                    self.elements.modify(k, |element| element.value = None);
+                    Ok(())
+                },
+                None => Err(Operation::Delete { stamp }),
+            },
+        }
+    }
+
+    // Inserts an element right after its origin, past the elements with greater stamps.
+    // The origin must be known.
+    fn insert(&mut self, meta: Meta, value: V) {
+        let mut k = match meta.after {
+            Some(after) => self.find(after).expect("unknown origin") + 1,
+            None => 0,
+        };
+        while self.elements.get(k).is_some_and(|element| element.meta.stamp > meta.stamp) {
+            k += 1;
+        }
+        let handle = self.elements.insert_with_handle(k, Element { value: Some(value), meta });
+        self.handles.insert(meta.stamp, handle);
+        self.clock = self.clock.max(meta.stamp.counter);
+    }
+
+    // Position of an element among all of them, removed ones included.
+    fn find(&mut self, stamp: Stamp) -> Option<usize> {
+        self.elements.index_of(*self.handles.get(&stamp)?)
+    }
+
+    // Position among all elements of the value at an index.
+    fn element_at(&self, index: usize) -> Option<usize> {
+        self.elements.position_by_measure(|visible| visible.0 > index)
+    }
+}
@@ -0,0 +1,258 @@
+use std::ptr;
+use measure::Measure;
+use tree_array::{size_of, Node, Tree, TreeArray};
+
+// A cursor over a tree array, pointing at a value, or at a "ghost" position past the end of
+// the array (like `LinkedList`'s cursors), from which moving wraps around.
+// It keeps the path from the root to the current node, so that moving to a neighbour only walks
+// the few nodes between them: going through the whole array takes linear time.
+pub struct Cursor<'a, V: 'a, M: 'a = ()> {
+    tree: &'a TreeArray<V, M>,
+    // Nodes from the root down to the current one, with whether a pending reversal above mirrors
+    // them. It is empty on the ghost position.
+    path: Vec<(&'a Node<V, M>, bool)>,
+    index: usize,
+}
+
+impl<'a, V, M> Cursor<'a, V, M> where M: Measure<V> {
+    pub(crate) fn new(tree: &'a TreeArray<V, M>, index: usize) -> Cursor<'a, V, M> {
+        let len = tree.len();
+        assert!(index <= len, "cursor index (is {}) should be <= len (is {})", index, len);
+        let mut cursor = Cursor { tree, path: Vec::new(), index };
+        if index == len {
+            return cursor;
+        }
+        let (mut cur, mut flip) = (&tree.root, false);
+        let mut rel = index;
+        while let Some(node) = cur {
+            cursor.path.push((node, flip));
+            let (left, right, f) = node.sides(flip);
+            let node_idx = size_of(left);
+            flip = f;
+            if rel < node_idx {
+                cur = left;
+            } else if rel > node_idx {
+                rel -= node_idx + 1;
+                cur = right;
+            } else {
+                break;
+            }
+        }
+        cursor
+    }
+
+    // Index of the current value, or None on the ghost position.
+    pub fn index(&self) -> Option<usize> {
+        if self.path.is_empty() { None } else { Some(self.index) }
+    }
+
+    // The value the cursor points to, or None on the ghost position.
+    pub fn current(&self) -> Option<&'a V> {
+        self.path.last().map(|(node, _)| &node.value)
+    }
+
+    // Moves to the next value. From the last value, it moves to the ghost position, and from
+    // there, to the first value.
+    pub fn move_next(&mut self) {
+        let (node, flip) = match self.path.last() {
+            Some(&last) => last,
+            None => {
+                self.index = 0;
+                self.push_spine(self.tree.root.as_deref(), false, false);
+                return;
+            },
+        };
+        self.index += 1;
+        if let (_, Some(right), flip) = node.sides(flip) {
+            self.push_spine(Some(right), flip, false);
+            return;
+        }
+        // Climb up until we come from a left child: its parent is next.
+        while let Some((child, _)) = self.path.pop() {
+            match self.path.last() {
+                Some(&(parent, flip)) if is_child(parent.sides(flip).0, child) => return,
+                _ => {},
+            }
+        }
+    }
+
+    // Moves to the previous value. From the first value, it moves to the ghost position, and
+    // from there, to the last value.
+    pub fn move_prev(&mut self) {
+        let (node, flip) = match self.path.last() {
+            Some(&last) => last,
+            None => {
+                if !self.tree.is_empty() {
+                    self.index = self.tree.len() - 1;
+                    self.push_spine(self.tree.root.as_deref(), false, true);
+                }
+                return;
+            },
+        };
+        if let (Some(left), _, flip) = node.sides(flip) {
+            self.index -= 1;
+            self.push_spine(Some(left), flip, true);
+            return;
+        }
+        while let Some((child, _)) = self.path.pop() {
+            match self.path.last() {
+                Some(&(parent, flip)) if is_child(parent.sides(flip).1, child) => {
+                    self.index -= 1;
+                    return;
+                },
+                _ => {},
+            }
+        }
+        // We were on the first value.
+        self.index = self.tree.len();
+    }
+
+    // The value after the current one, without moving. On the ghost position, it is the first
+    // value.
+    pub fn peek_next(&self) -> Option<&'a V> {
+        let (node, flip) = match self.path.last() {
+            Some(&last) => last,
+            None => return self.tree.first(),
+        };
+        if let (_, Some(right), flip) = node.sides(flip) {
+            let (mut cur, mut flip) = (&**right, flip);
+            while let (Some(left), _, f) = cur.sides(flip) {
+                cur = left;
+                flip = f;
+            }
+            return Some(&cur.value);
+        }
+        for i in (1..self.path.len()).rev() {
+            let (parent, flip) = self.path[i - 1];
+            if is_child(parent.sides(flip).0, self.path[i].0) {
+                return Some(&parent.value);
+            }
+        }
+        None
+    }
+
+    // The value before the current one, without moving. On the ghost position, it is the last
+    // value.
+    pub fn peek_prev(&self) -> Option<&'a V> {
+        let (node, flip) = match self.path.last() {
+            Some(&last) => last,
+            None => return self.tree.last(),
+        };
+        if let (Some(left), _, flip) = node.sides(flip) {
+            let (mut cur, mut flip) = (&**left, flip);
+            while let (_, Some(right), f) = cur.sides(flip) {
+                cur = right;
+                flip = f;
+            }
+            return Some(&cur.value);
+        }
+        for i in (1..self.path.len()).rev() {
+            let (parent, flip) = self.path[i - 1];
+            if is_child(parent.sides(flip).1, self.path[i].0) {
+                return Some(&parent.value);
+            }
+        }
+        None
+    }
+
+    // Walks down from `node` along its left spine, or its right spine if `rightwards`, in index
+    // order, pushing the nodes met on the path.
+    fn push_spine(&mut self, node: Option<&'a Node<V, M>>, flip: bool, rightwards: bool) {
+        let (mut cur, mut flip) = (node, flip);
+        while let Some(node) = cur {
+            self.path.push((node, flip));
+            let (left, right, f) = node.sides(flip);
+            cur = if rightwards { right.as_deref() } else { left.as_deref() };
+            flip = f;
+        }
+    }
+}
+
+// Tells whether `node` is the one held in a given child slot.
+fn is_child<V, M>(slot: &Tree<V, M>, node: &Node<V, M>) -> bool {
+    match slot {
+        Some(child) => ptr::eq(&**child, node),
+        None => false,
+    }
+}
+
+// A cursor over a tree array that can edit it around the current position.
+// It only remembers the current index: each access splays the current node to the root, so that
+// working around a moving point stays cheap, and edits never leave the cursor stale.
+pub struct CursorMut<'a, V: 'a> {
+    tree: &'a mut TreeArray<V>,
+    // The current index, which is the length of the array on the ghost position.
+    index: usize,
+}
+
+impl<'a, V> CursorMut<'a, V> {
+    pub(crate) fn new(tree: &'a mut TreeArray<V>, index: usize) -> CursorMut<'a, V> {
+        let len = tree.len();
+        assert!(index <= len, "cursor index (is {}) should be <= len (is {})", index, len);
+        CursorMut { tree, index }
+    }
+
+    // Index of the current value, or None on the ghost position.
+    pub fn index(&self) -> Option<usize> {
+        if self.index < self.tree.len() { Some(self.index) } else { None }
+    }
+
+    // The value the cursor points to, or None on the ghost position.
+    pub fn current(&mut self) -> Option<&mut V> {
+        self.tree.get_mut(self.index)
+    }
+
+    // Moves to the next value. From the last value, it moves to the ghost position, and from
+    // there, to the first value.
+    pub fn move_next(&mut self) {
+        self.index = if self.index == self.tree.len() { 0 } else { self.index + 1 };
+    }
+
+    // Moves to the previous value. From the first value, it moves to the ghost position, and
+    // from there, to the last value.
+    pub fn move_prev(&mut self) {
+        self.index = if self.index == 0 { self.tree.len() } else { self.index - 1 };
+    }
+
+    // The value after the current one, without moving.
+    pub fn peek_next(&mut self) -> Option<&mut V> {
+        let next = if self.index == self.tree.len() { 0 } else { self.index + 1 };
+        self.tree.get_mut(next)
+    }
+
+    // The value before the current one, without moving.
+    pub fn peek_prev(&mut self) -> Option<&mut V> {
+        let prev = if self.index == 0 { self.tree.len() } else { self.index - 1 };
+        self.tree.get_mut(prev)
+    }
+
+    // Inserts a value before the current one; the cursor keeps pointing at the same value.
+    // On the ghost position, the value is appended at the end of the array.
+    pub fn insert_before(&mut self, value: V) {
+        self.tree.insert(self.index, value);
+        self.index += 1;
+    }
+
+    // Inserts a value after the current one; the cursor keeps pointing at the same value.
+    // On the ghost position, the value is inserted at the start of the array.
+    pub fn insert_after(&mut self, value: V) {
+        if self.index == self.tree.len() {
+            self.tree.insert(0, value);
+            self.index += 1;
+        } else {
+            self.tree.insert(self.index + 1, value);
+        }
+    }
+
+    // Removes the current value and returns it. The cursor then points at the value that
+    // followed it, or at the ghost position if it was the last one.
+    // Nothing happens on the ghost position, and None is returned.
+    pub fn remove_current(&mut self) -> Option<V> {
+        self.tree.remove(self.index)
+    }
+
+    // A read-only cursor at the same position.
+    pub fn as_cursor(&self) -> Cursor<'_, V> {
+        Cursor::new(self.tree, self.index)
+    }
+}
@@ -0,0 +1,237 @@
+use std::ops::Range;
+use error::Error;
+use measure::Measure;
+use merkle::{ContentBytes, ContentHash};
+use tree_array::TreeArray;
+
+// Number of values of the window looked for in the other array to line up both arrays, when the
+// differing ranges are too long to be compared value by value.
+const WINDOW: usize = 16;
+
+// Largest number of pairs of values compared value by value, for a longest common subsequence.
+const TABLE: usize = 1 << 16;
+
+// An edit to a sequence. An edit script is a list of them, applied in order, the index of each
+// referring to the sequence as left by the edits before it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Edit<V> {
+    // Inserts a value at an index.
+    Insert(usize, V),
+    // Removes the value at an index.
+    Delete(usize),
+    // Puts a value in place of the one at an index.
+    Replace(usize, V),
+}
+
+impl<V, M> TreeArray<V, M> where V: Clone, M: Measure<V> {
+    // Applies an edit script, such as one given by `diff`, its edits in order.
+    // Whether each index is in bounds only depends on the length of the array as left by the
+    // edits before, so the whole script is checked before any edit is made: if an index is out
+    // of bounds, the error tells which and for what length, and the array is left as it was.
+    pub fn apply(&mut self, edits: &[Edit<V>]) -> Result<(), Error> {
+        let mut len = self.len();
+        for edit in edits {
+            match *edit {
+                Edit::Insert(index, _) if index <= len => len += 1,
+                Edit::Delete(index) if index < len => len -= 1,
+                Edit::Replace(index, _) if index < len => {},
+                Edit::Insert(index, _) | Edit::Delete(index) | Edit::Replace(index, _) =>
+                    return Err(Error::OutOfBounds { index, len }),
+            }
+        }
+        for edit in edits {
+            match *edit {
+                Edit::Insert(index, ref value) => self.insert(index, value.clone()),
+                Edit::Delete(index) => {
+                    self.remove(index);
+                },
+                Edit::Replace(index, ref value) => self.set(index, value.clone()),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<V> TreeArray<V, ContentHash> where V: ContentBytes + PartialEq + Clone {
+    // Edit script turning this array into another one.
+    // Ranges of both arrays holding the same values are told apart by their content hashes, read
+    // off the trees, so that the common start and end of both arrays are skipped by comparing a
+    // logarithmic number of hashes. The differing ranges left are compared value by value when
+    // short; long ones are first lined up on a window of values from the middle of one found in
+    // the other, found by the hashes of the values kept on the nodes, and each side is compared
+    // in turn.
+    // Ranges with the same hash are taken to hold the same values: with 61-bit hashes, a
+    // collision is unlikely, but would make the script wrong. `diff_verified` rules it out.
+    // The script is short rather than shortest: it is only shortest when the differing ranges
+    // are short.
+    pub fn diff(&self, other: &TreeArray<V, ContentHash>) -> Vec<Edit<V>> {
+        let mut differ = Differ { old: self, new: other, verify: false, edits: Vec::new() };
+        differ.diff(0..self.len(), 0..other.len());
+        differ.edits
+    }
+
+    // Same as `diff`, but values found the same by their hashes are compared before being taken
+    // as such, so that the script is right even when hashes collide. Skipping a common start or
+    // end then takes time linear in its length.
+    pub fn diff_verified(&self, other: &TreeArray<V, ContentHash>) -> Vec<Edit<V>> {
+        let mut differ = Differ { old: self, new: other, verify: true, edits: Vec::new() };
+        differ.diff(0..self.len(), 0..other.len());
+        differ.edits
+    }
+}
+
+struct Differ<'a, V> {
+    old: &'a TreeArray<V, ContentHash>,
+    new: &'a TreeArray<V, ContentHash>,
+    // Whether values found the same by their hashes are compared.
+    verify: bool,
+    edits: Vec<Edit<V>>,
+}
+
+impl<'a, V> Differ<'a, V> where V: ContentBytes + PartialEq + Clone {
+    // Adds the edits turning a range of the old array into a range of the new one.
+    // Ranges are handled from left to right, so that what comes before a range of the new array
+    // is already in place, and the range starts at the same index in the array being edited.
+    fn diff(&mut self, mut old: Range<usize>, mut new: Range<usize>) {
+        let prefix = self.common(old.start, new.start, old.len().min(new.len()), false);
+        old.start += prefix;
+        new.start += prefix;
+        let suffix = self.common(old.end, new.end, old.len().min(new.len()), true);
+        old.end -= suffix;
+        new.end -= suffix;
+        if old.is_empty() || new.is_empty() || old.len().saturating_mul(new.len()) <= TABLE {
+            return self.compare(old, new);
+        }
+        let width = WINDOW.min(old.len()).min(new.len());
+        let middle = old.start + (old.len() - width) / 2;
+        match self.find(middle, width, new.clone()) {
+            Some(found) => {
+                self.diff(old.start..middle, new.start..found);
+                self.diff(middle + width..old.end, found + width..new.end);
+            },
+            None => self.compare_in_place(old, new),
+        }
+    }
+
+    // Length of the longest run of values, up to `max`, that is the same from `old` in the old
+    // array and from `new` in the new one, or up to them if `backward` is set.
+    // The length is found by doubling, then by halving, comparing content hashes of ranges. When
+    // verifying, the run found is then checked value by value, in time linear in its length.
+    fn common(&self, old: usize, new: usize, max: usize, backward: bool) -> usize {
+        let same = |len: usize| {
+            if backward {
+                self.old.summary_between(old - len, old) == self.new.summary_between(new - len, new)
+            } else {
+                self.old.summary_between(old, old + len) == self.new.summary_between(new, new + len)
+            }
+        };
+        let (mut low, mut high) = (0, 1);
+        while high <= max && same(high) {
+            low = high;
+            high *= 2;
+        }
+        let mut high = high.min(max + 1);
+        while high - low > 1 {
+            let mid = low + (high - low) / 2;
+            if same(mid) {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+        if !self.verify {
+            return low;
+        }
+        // Different values may hash the same: the run found is checked value by value, and cut
+        // at the first values that differ.
+        let differ = |(x, y): (&V, &V)| x != y;
+        let cut = if backward {
+            self.old.iter_range(old - low..old).rev()
+                .zip(self.new.iter_range(new - low..new).rev())
+                .position(differ)
+        } else {
+            self.old.iter_range(old..old + low).zip(self.new.iter_range(new..new + low))
+                .position(differ)
+        };
+        cut.unwrap_or(low)
+    }
+
+    // Finds where the values of the old array from `start` on, `width` of them, are found in a
+    // range of the new array, as close to its middle as can be. The hashes of the values are
+    // those kept on their nodes, rather than hashed again at each call.
+    fn find(&self, start: usize, width: usize, new: Range<usize>) -> Option<usize> {
+        let hashes = |array: &TreeArray<V, ContentHash>, range: Range<usize>| -> Vec<u64> {
+            array.iter_range(range).measures().map(ContentHash::value).collect()
+        };
+        let window = hashes(self.old, start..start + width);
+        let middle = (new.len() - width) / 2;
+        hashes(self.new, new.clone()).windows(width).enumerate()
+            .filter(|&(_, w)| w == &window[..])
+            .map(|(i, _)| i)
+            .filter(|&i| !self.verify || self.old.iter_range(start..start + width)
+                .eq(self.new.iter_range(new.start + i..new.start + i + width)))
+            .min_by_key(|&i| i.abs_diff(middle))
+            .map(|i| new.start + i)
+    }
+
+    // Adds the edits of a longest common subsequence of two ranges, compared value by value.
+    fn compare(&mut self, old: Range<usize>, new: Range<usize>) {
+        let a: Vec<&V> = self.old.iter_range(old).collect();
+        let b: Vec<&V> = self.new.iter_range(new.clone()).collect();
+        let (n, m) = (a.len(), b.len());
+        // Length of the longest common subsequence of a[i..] and b[j..], at i * (m + 1) + j.
+        let mut lengths = vec![0u32; (n + 1) * (m + 1)];
+        for i in (0..n).rev() {
+            for j in (0..m).rev() {
+                lengths[i * (m + 1) + j] = if a[i] == b[j] {
+                    lengths[(i + 1) * (m + 1) + j + 1] + 1
+                } else {
+                    lengths[(i + 1) * (m + 1) + j].max(lengths[i * (m + 1) + j + 1])
+                };
+            }
+        }
+        let at = |i: usize, j: usize| lengths[i * (m + 1) + j];
+        let (mut i, mut j) = (0, 0);
+        while i < n || j < m {
+            let index = new.start + j;
+            if i == n {
+                self.edits.push(Edit::Insert(index, b[j].clone()));
+                j += 1;
+            } else if j == m {
+                self.edits.push(Edit::Delete(index));
+                i += 1;
+            } else if a[i] == b[j] {
+                i += 1;
+                j += 1;
+            } else if at(i + 1, j + 1) == at(i, j) {
+                self.edits.push(Edit::Replace(index, b[j].clone()));
+                i += 1;
+                j += 1;
+            } else if at(i + 1, j) == at(i, j) {
+                self.edits.push(Edit::Delete(index));
+                i += 1;
+            } else {
+                self.edits.push(Edit::Insert(index, b[j].clone()));
+                j += 1;
+            }
+        }
+    }
+
+    // Adds the edits replacing values of a range one for one, when nothing lines them up, and
+    // then removing or inserting those in excess.
+    fn compare_in_place(&mut self, old: Range<usize>, new: Range<usize>) {
+        let (a, b) = (self.old.iter_range(old.clone()), self.new.iter_range(new.clone()));
+        for (index, (x, y)) in new.clone().zip(a.zip(b)) {
+            if x != y {
+                self.edits.push(Edit::Replace(index, y.clone()));
+            }
+        }
+        let index = new.start + old.len().min(new.len());
+        for _ in new.len()..old.len() {
+            self.edits.push(Edit::Delete(index));
+        }
+        for (i, value) in self.new.iter_range(index..new.end).enumerate() {
+            self.edits.push(Edit::Insert(index + i, value.clone()));
+        }
+    }
+}
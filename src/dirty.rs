@@ -0,0 +1,148 @@
+use std::iter::FromIterator;
+use std::ops::RangeBounds;
+use measure::Measure;
+use tree_array::TreeArray;
+
+// A tree array whose values are each marked dirty or clean, for incremental renderers and
+// recomputations to find what changed since they last looked.
+// Values become dirty as they are inserted or set, and whole ranges are marked dirty or clean in
+// logarithmic time: the mark is left at the root of the range, and handed down as later
+// operations walk down the tree. Each subtree counts its dirty values, so that the next dirty
+// value is found by walking down the tree.
+pub struct DirtyTreeArray<V> {
+    entries: TreeArray<Entry<V>, Dirt>,
+}
+
+struct Entry<V> {
+    value: V,
+    dirty: bool,
+}
+
+// Summary of a subtree: its length and how many of its values are dirty, along with the mark
+// yet to be set on all of them, already counted.
+#[derive(Clone, Copy, Debug, Default)]
+struct Dirt {
+    len: usize,
+    dirty: usize,
+    mark: Option<bool>,
+}
+
+impl Dirt {
+    // Records that all values of the subtree are to be marked dirty or clean.
+    fn mark(&mut self, dirty: bool) {
+        self.dirty = if dirty { self.len } else { 0 };
+        self.mark = Some(dirty);
+    }
+}
+
+impl<V> Measure<Entry<V>> for Dirt {
+    fn identity() -> Dirt {
+        Dirt::default()
+    }
+
+    fn measure(entry: &Entry<V>) -> Dirt {
+        Dirt { len: 1, dirty: entry.dirty as usize, mark: None }
+    }
+
+    fn combine(&self, other: &Dirt) -> Dirt {
+        Dirt { len: self.len + other.len, dirty: self.dirty + other.dirty, mark: None }
+    }
+
+    fn push_down(&mut self, entry: &mut Entry<V>, left: Option<&mut Dirt>,
+                 right: Option<&mut Dirt>) -> bool {
+        match self.mark.take() {
+            Some(dirty) => {
+                entry.dirty = dirty;
+                for child in left.into_iter().chain(right) {
+                    child.mark(dirty);
+                }
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+impl<V> DirtyTreeArray<V> {
+    pub fn new() -> DirtyTreeArray<V> {
+        DirtyTreeArray { entries: TreeArray::default() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Fetches a value, whether it is dirty or not.
+    pub fn get(&self, index: usize) -> Option<&V> {
+        self.entries.get(index).map(|entry| &entry.value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &V> {
+        self.entries.iter().map(|entry| &entry.value)
+    }
+
+    // Inserts a dirty value. Panics if the index is greater than the length.
+    pub fn insert(&mut self, index: usize, value: V) {
+        self.entries.insert(index, Entry { value, dirty: true });
+    }
+
+    pub fn push(&mut self, value: V) {
+        self.entries.push(Entry { value, dirty: true });
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<V> {
+        self.entries.remove(index).map(|entry| entry.value)
+    }
+
+    // Overwrites a value, which becomes dirty. Panics if the index is out of bounds.
+    pub fn set(&mut self, index: usize, value: V) {
+        self.entries.set(index, Entry { value, dirty: true });
+    }
+
+    // Tells whether the value at an index is dirty, or None if there is no such value.
+    pub fn is_dirty(&mut self, index: usize) -> Option<bool> {
+        self.entries.splay_get(index).map(|entry| entry.dirty)
+    }
+
+    // Number of dirty values, in constant time.
+    pub fn dirty_count(&self) -> usize {
+        self.entries.measure().dirty
+    }
+
+    // Marks all values in a range dirty. Panics if the range is out of bounds.
+    pub fn mark_dirty<R>(&mut self, range: R) where R: RangeBounds<usize> {
+        self.entries.update_range(range, |root| root.summary.mark(true));
+    }
+
+    // Marks all values in a range clean. Panics if the range is out of bounds.
+    pub fn clean_range<R>(&mut self, range: R) where R: RangeBounds<usize> {
+        self.entries.update_range(range, |root| root.summary.mark(false));
+    }
+
+    // Index of the first dirty value at or after a given index.
+    pub fn first_dirty_after(&mut self, index: usize) -> Option<usize> {
+        if index >= self.len() {
+            return None;
+        }
+        let before = self.entries.measure_range(..index).dirty;
+        self.entries.locate_by_measure_mut(|dirt| dirt.dirty > before).map(|(i, _)| i)
+    }
+}
+
+impl<V> Default for DirtyTreeArray<V> {
+    fn default() -> DirtyTreeArray<V> {
+        DirtyTreeArray::new()
+    }
+}
+
+// Collected values start out dirty.
+impl<V> FromIterator<V> for DirtyTreeArray<V> {
+    fn from_iter<I>(iter: I) -> DirtyTreeArray<V> where I: IntoIterator<Item = V> {
+        let entries = iter.into_iter().map(|value| Entry { value, dirty: true }).collect();
+        DirtyTreeArray { entries }
+    }
+}
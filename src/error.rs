@@ -0,0 +1,20 @@
+use std::error;
+use std::fmt;
+
+// Errors reported by the fallible operations of the crate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    // An index was past the end of the array it was used on.
+    OutOfBounds { index: usize, len: usize },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::OutOfBounds { index, len } =>
+                write!(f, "index {} is out of bounds for length {}", index, len),
+        }
+    }
+}
+
+impl error::Error for Error {}
@@ -0,0 +1,238 @@
+use std::iter::FromIterator;
+use std::ops::RangeBounds;
+use label::{Label, LastLabel};
+use map::TreeMap;
+use measure::Measure;
+use tree_array::TreeArray;
+
+// A reference to a value of a `HandleTreeArray`, which stays valid as values are inserted and
+// removed around it, until the value itself is removed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Handle(u64);
+
+// A tree array whose values can be given handles, to find them again wherever edits moved them.
+// Each value gets a label, increasing along the array, so that a value is found by walking down
+// the tree by label, counting values on the way to its index. Handles map to labels. Both walks
+// splay what they reach to the root, so that a handle is found in logarithmic time amortized.
+// Labels are handed out by the order maintenance of `insert_labelled`.
+// Values may also be summarized by a measure, as in a tree array.
+pub struct HandleTreeArray<V, M = ()> {
+    entries: TreeArray<Entry<V>, Labelled<M>>,
+    labels: TreeMap<u64, u64>,
+    next_id: u64,
+}
+
+struct Entry<V> {
+    value: V,
+    label: u64,
+    // The handle given to the value, if any.
+    id: Option<u64>,
+}
+
+// Summary of a run of entries: the label of the last one, and the summary of their values.
+#[derive(Clone, Copy, Debug)]
+struct Labelled<M> {
+    last: Option<u64>,
+    summary: M,
+}
+
+impl<V> Label for Entry<V> {
+    fn label(&self) -> u64 {
+        self.label
+    }
+
+    fn set_label(&mut self, label: u64) {
+        self.label = label;
+    }
+}
+
+impl<M> LastLabel for Labelled<M> {
+    fn last_label(&self) -> Option<u64> {
+        self.last
+    }
+}
+
+impl<V, M> Measure<Entry<V>> for Labelled<M> where M: Measure<V> {
+    fn identity() -> Labelled<M> {
+        Labelled { last: None, summary: M::identity() }
+    }
+
+    fn measure(entry: &Entry<V>) -> Labelled<M> {
+        Labelled { last: Some(entry.label), summary: M::measure(&entry.value) }
+    }
+
+    fn combine(&self, other: &Labelled<M>) -> Labelled<M> {
+        Labelled { last: other.last.or(self.last), summary: self.summary.combine(&other.summary) }
+    }
+}
+
+impl<V, M> HandleTreeArray<V, M> where M: Measure<V> {
+    pub fn new() -> HandleTreeArray<V, M> {
+        HandleTreeArray { entries: TreeArray::default(), labels: TreeMap::new(), next_id: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&V> {
+        self.entries.get(index).map(|entry| &entry.value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &V> {
+        self.entries.iter().map(|entry| &entry.value)
+    }
+
+    // Summary of all values.
+    pub fn measure(&self) -> M {
+        self.entries.measure().summary
+    }
+
+    // Finds the index of the first value at which the predicate holds of the summary of all values
+    // up to and including it, as `TreeArray::position_by_measure` does.
+    pub fn position_by_measure<F>(&self, mut predicate: F) -> Option<usize>
+        where F: FnMut(&M) -> bool {
+        self.entries.position_by_measure(|labelled| predicate(&labelled.summary))
+    }
+
+    // Summary of the values before an index.
+    // Panics if the index is greater than the length.
+    #[cfg(feature = "crdt")]
+    pub(crate) fn summary_before(&self, index: usize) -> M {
+        self.entries.summary_between(0, index).summary
+    }
+
+    // Replaces the value at an index, which keeps its handle.
+    // Panics if the index is out of bounds.
+    pub fn set(&mut self, index: usize, value: V) {
+        let len = self.len();
+        if self.modify(index, |old| *old = value).is_none() {
+            panic!("set index (is {}) should be < len (is {})", index, len);
+        }
+    }
+
+    // Applies `f` to the value at an index, which keeps its handle, bringing its summary up to
+    // date. The result is None if nothing is found at that index.
+    pub(crate) fn modify<R, F>(&mut self, index: usize, f: F) -> Option<R>
+        where F: FnOnce(&mut V) -> R {
+        self.entries.modify(index, |entry| f(&mut entry.value))
+    }
+
+    // Panics if the index is greater than the length.
+    pub fn insert(&mut self, index: usize, value: V) {
+        self.insert_entry(index, value, None);
+    }
+
+    // Inserts a value, giving back a handle to it.
+    // Panics if the index is greater than the length.
+    pub fn insert_with_handle(&mut self, index: usize, value: V) -> Handle {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.insert_entry(index, value, Some(id));
+        Handle(id)
+    }
+
+    pub fn push(&mut self, value: V) {
+        let len = self.len();
+        self.insert(len, value);
+    }
+
+    pub fn push_with_handle(&mut self, value: V) -> Handle {
+        let len = self.len();
+        self.insert_with_handle(len, value)
+    }
+
+    // Handle to the value at an index, given to it now if it had none.
+    pub fn handle_at(&mut self, index: usize) -> Option<Handle> {
+        let next_id = self.next_id;
+        let (id, label) = self.entries.modify(index, |entry| {
+            (*entry.id.get_or_insert(next_id), entry.label)
+        })?;
+        if id == next_id {
+            self.next_id += 1;
+            self.labels.insert(id, label);
+        }
+        Some(Handle(id))
+    }
+
+    // Current index of the value a handle refers to, or None if it was removed.
+    pub fn index_of(&mut self, handle: Handle) -> Option<usize> {
+        let &label = self.labels.get(&handle.0)?;
+        let (index, _) = self.entries.locate_label(label)?;
+        Some(index)
+    }
+
+    pub fn get_by_handle(&mut self, handle: Handle) -> Option<&V> {
+        let index = self.index_of(handle)?;
+        self.get(index)
+    }
+
+    // Removes the value at an index. Its handle, if any, no longer refers to anything.
+    pub fn remove(&mut self, index: usize) -> Option<V> {
+        let entry = self.entries.remove(index)?;
+        if let Some(id) = entry.id {
+            self.labels.remove(&id);
+        }
+        Some(entry.value)
+    }
+
+    pub fn remove_by_handle(&mut self, handle: Handle) -> Option<V> {
+        let index = self.index_of(handle)?;
+        self.remove(index)
+    }
+
+    pub fn pop(&mut self) -> Option<V> {
+        let len = self.len();
+        self.remove(len.checked_sub(1)?)
+    }
+
+    // Panics if the range is out of bounds.
+    pub fn remove_range<R>(&mut self, range: R) where R: RangeBounds<usize> {
+        for entry in self.entries.drain(range) {
+            if let Some(id) = entry.id {
+                self.labels.remove(&id);
+            }
+        }
+    }
+
+    fn insert_entry(&mut self, index: usize, value: V, id: Option<u64>) {
+        let labels = &mut self.labels;
+        self.entries.insert_labelled(index, Entry { value, label: 0, id }, |entry| {
+            if let Some(id) = entry.id {
+                labels.insert(id, entry.label);
+            }
+        });
+    }
+
+    // Depth of the deepest tree, to check that finding handles keeps them shallow.
+    #[cfg(test)]
+    pub(crate) fn depth(&self) -> usize {
+        self.entries.depth().max(self.labels.depth())
+    }
+}
+
+impl<V, M> Default for HandleTreeArray<V, M> where M: Measure<V> {
+    fn default() -> HandleTreeArray<V, M> {
+        HandleTreeArray::new()
+    }
+}
+
+impl<V, M> FromIterator<V> for HandleTreeArray<V, M> where M: Measure<V> {
+    fn from_iter<I>(iter: I) -> HandleTreeArray<V, M> where I: IntoIterator<Item = V> {
+        let mut array = HandleTreeArray::new();
+        for value in iter {
+            array.push(value);
+        }
+        array
+    }
+}
+
+impl<V, M> From<TreeArray<V, M>> for HandleTreeArray<V, M> where M: Measure<V> {
+    fn from(values: TreeArray<V, M>) -> HandleTreeArray<V, M> {
+        values.into_iter().collect()
+    }
+}
@@ -0,0 +1,297 @@
+use std::ops::RangeBounds;
+use iter::Iter;
+use tree_array::{bounds, TreeArray};
+
+// A state of an array with a history, to get back to with `revert_to`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Version(u64);
+
+// A tree array keeping a journal of its edits, so that they can be undone and redone.
+// Each edit records what it takes to reverse it: the range of values it inserted, or the values
+// it removed or replaced, which are split out of the tree as they are, in logarithmic time, so
+// that no edit ever copies the array. Undoing an edit reverses it, and records the reverse of
+// that in turn, to redo it.
+// Edits made within a transaction are undone and redone together, as a single edit.
+pub struct UndoTreeArray<V> {
+    values: TreeArray<V>,
+    done: Vec<Step<V>>,
+    undone: Vec<Step<V>>,
+    // Edits of the open transaction, along with how many times it was begun and not committed.
+    transaction: Option<(Vec<Change<V>>, usize)>,
+    // Version before all edits of the journal.
+    base: u64,
+    next_id: u64,
+}
+
+// Edits undone and redone together, with the version they lead to.
+struct Step<V> {
+    id: u64,
+    changes: Vec<Change<V>>,
+}
+
+// An edit, along with what it removed.
+enum Change<V> {
+    Insert { index: usize, len: usize },
+    Remove { index: usize, values: TreeArray<V> },
+    Set { index: usize, value: V },
+}
+
+impl<V> Change<V> {
+    // Makes the edit, and gives the edit reversing it.
+    fn make(self, array: &mut TreeArray<V>) -> Change<V> {
+        match self {
+            Change::Insert { index, len } =>
+                Change::Remove { index, values: array.take(index..index + len) },
+            Change::Remove { index, values } => {
+                let len = values.len();
+                array.insert_all(index, values);
+                Change::Insert { index, len }
+            },
+            Change::Set { index, value } => {
+                let value = array.replace(index, value).expect("recorded index out of bounds");
+                Change::Set { index, value }
+            },
+        }
+    }
+}
+
+impl<V> UndoTreeArray<V> {
+    pub fn new() -> UndoTreeArray<V> {
+        UndoTreeArray::from(TreeArray::new())
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&V> {
+        self.values.get(index)
+    }
+
+    pub fn iter(&self) -> Iter<'_, V> {
+        self.values.iter()
+    }
+
+    // The values, without their history.
+    pub fn values(&self) -> &TreeArray<V> {
+        &self.values
+    }
+
+    // Panics if the index is out of bounds.
+    pub fn set(&mut self, index: usize, value: V) {
+        let len = self.len();
+        match self.values.replace(index, value) {
+            Some(value) => {
+                self.record(Change::Set { index, value });
+            },
+            None => panic!("set index (is {}) should be < len (is {})", index, len),
+        }
+    }
+
+    // Panics if the index is greater than the length.
+    pub fn insert(&mut self, index: usize, value: V) {
+        self.values.insert(index, value);
+        self.record(Change::Insert { index, len: 1 });
+    }
+
+    // Inserts all values of another array at a given index.
+    // Panics if the index is greater than the length.
+    pub fn insert_all(&mut self, index: usize, other: TreeArray<V>) {
+        let len = other.len();
+        self.values.insert_all(index, other);
+        self.record(Change::Insert { index, len });
+    }
+
+    pub fn push(&mut self, value: V) {
+        let len = self.len();
+        self.insert(len, value);
+    }
+
+    // Removes a range of values. They are moved to the history, to undo the removal, and are
+    // given back as they are kept there.
+    // Panics if the range is out of bounds.
+    pub fn remove_range<R>(&mut self, range: R) -> &TreeArray<V> where R: RangeBounds<usize> {
+        let (start, end) = bounds(range, self.len());
+        let values = self.values.take(start..end);
+        match self.record(Change::Remove { index: start, values }) {
+            Change::Remove { values, .. } => values,
+            _ => unreachable!("a removal was recorded as another edit"),
+        }
+    }
+
+    // Removes the value at an index, giving back a copy of it, since the history keeps the value
+    // itself. Values that cannot be cloned are removed with `remove_range`.
+    pub fn remove_cloned(&mut self, index: usize) -> Option<V> where V: Clone {
+        if index >= self.len() {
+            return None;
+        }
+        self.remove_range(index..index + 1).first().cloned()
+    }
+
+    pub fn pop_cloned(&mut self) -> Option<V> where V: Clone {
+        let len = self.len();
+        self.remove_cloned(len.checked_sub(1)?)
+    }
+
+    // The current version, to get back to it later. Edits of an open transaction only make a
+    // version once it is committed.
+    pub fn snapshot(&self) -> Version {
+        Version(self.done.last().map_or(self.base, |step| step.id))
+    }
+
+    // Opens a transaction, whose edits are then undone as one, until it is committed. Nested
+    // transactions are part of the outermost one, committed along with it.
+    pub fn begin_transaction(&mut self) {
+        match self.transaction {
+            Some((_, ref mut depth)) => *depth += 1,
+            None => self.transaction = Some((Vec::new(), 1)),
+        }
+    }
+
+    // Closes the transaction opened last. Once the outermost one is closed, its edits, if any,
+    // are recorded as a single edit.
+    // Panics if no transaction is open.
+    pub fn commit(&mut self) {
+        match self.transaction.take() {
+            Some((changes, depth)) if depth > 1 => self.transaction = Some((changes, depth - 1)),
+            Some((changes, _)) => if !changes.is_empty() {
+                self.push_step(changes);
+            },
+            None => panic!("no transaction to commit"),
+        }
+    }
+
+    // Undoes the edits of the open transaction, nested ones included, and closes it, leaving the
+    // array and its history as they were when it was opened. Returns whether one was open.
+    pub fn rollback(&mut self) -> bool {
+        match self.transaction.take() {
+            Some((changes, _)) => {
+                self.reverse(Step { id: 0, changes });
+                true
+            },
+            None => false,
+        }
+    }
+
+    // Whether a transaction is open.
+    pub fn in_transaction(&self) -> bool {
+        self.transaction.is_some()
+    }
+
+    // Undoes the last edit not undone yet. Returns whether there was one.
+    // An open transaction is committed first, as are those opened in it.
+    pub fn undo(&mut self) -> bool {
+        self.close_transaction();
+        match self.done.pop() {
+            Some(step) => {
+                let step = self.reverse(step);
+                self.undone.push(step);
+                true
+            },
+            None => false,
+        }
+    }
+
+    // Redoes the last edit undone, unless edits were made since. Returns whether there was one.
+    pub fn redo(&mut self) -> bool {
+        self.close_transaction();
+        match self.undone.pop() {
+            Some(step) => {
+                let step = self.reverse(step);
+                self.done.push(step);
+                true
+            },
+            None => false,
+        }
+    }
+
+    // Undoes or redoes edits up to a version. Versions are lost once undone, when edits are made
+    // instead of redoing them: returns whether the version could be got back to, leaving the
+    // array as it was otherwise.
+    pub fn revert_to(&mut self, version: Version) -> bool {
+        self.close_transaction();
+        if version.0 == self.base || self.done.iter().any(|step| step.id == version.0) {
+            while self.snapshot() != version {
+                self.undo();
+            }
+            true
+        } else if self.undone.iter().any(|step| step.id == version.0) {
+            while self.snapshot() != version {
+                self.redo();
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    // Forgets all edits, which can no longer be undone nor redone, closing any open transaction
+    // without undoing its edits.
+    pub fn clear_history(&mut self) {
+        self.transaction = None;
+        self.base = self.snapshot().0;
+        self.done.clear();
+        self.undone.clear();
+    }
+
+    // Makes the reverse of the edits of a step, in reverse order, and gives the step reversing
+    // that, which leads to the same version. The edits of a step are listed in the order they
+    // were made.
+    fn reverse(&mut self, step: Step<V>) -> Step<V> {
+        let values = &mut self.values;
+        let changes = step.changes.into_iter().rev().map(|change| change.make(values)).collect();
+        Step { id: step.id, changes }
+    }
+
+    // Commits the open transaction, if any, along with those opened in it.
+    fn close_transaction(&mut self) {
+        if let Some((_, ref mut depth)) = self.transaction {
+            *depth = 1;
+            self.commit();
+        }
+    }
+
+    // Records an edit, made just now, as the last one, or as part of the open transaction, and
+    // gives it back as recorded.
+    fn record(&mut self, change: Change<V>) -> &Change<V> {
+        let changes = match self.transaction {
+            Some((ref mut changes, _)) => changes,
+            None => {
+                self.push_step(Vec::new());
+                &mut self.done.last_mut().unwrap().changes
+            },
+        };
+        changes.push(change);
+        changes.last().unwrap()
+    }
+
+    // Records edits as the last step, dropping those undone.
+    fn push_step(&mut self, changes: Vec<Change<V>>) {
+        self.undone.clear();
+        self.next_id += 1;
+        self.done.push(Step { id: self.next_id, changes });
+    }
+}
+
+impl<V> Default for UndoTreeArray<V> {
+    fn default() -> UndoTreeArray<V> {
+        UndoTreeArray::new()
+    }
+}
+
+impl<V> From<TreeArray<V>> for UndoTreeArray<V> {
+    fn from(values: TreeArray<V>) -> UndoTreeArray<V> {
+        UndoTreeArray { values, done: Vec::new(), undone: Vec::new(), transaction: None, base: 0,
+                        next_id: 0 }
+    }
+}
+
+impl<V> From<UndoTreeArray<V>> for TreeArray<V> {
+    fn from(array: UndoTreeArray<V>) -> TreeArray<V> {
+        array.values
+    }
+}
@@ -0,0 +1,101 @@
+use std::cmp;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use tree_array::TreeArray;
+
+// Byte arrays can be streamed into and out of, like `io::Cursor` does for vectors.
+impl TreeArray<u8> {
+    // Reads the bytes from the start.
+    pub fn reader(&self) -> Reader<'_> {
+        Reader { tree: self, position: 0 }
+    }
+
+    // Writes bytes from the start, overwriting those already there, and extending the array
+    // past its end.
+    pub fn writer(&mut self) -> Writer<'_> {
+        Writer { tree: self, position: 0 }
+    }
+}
+
+// Reader over the bytes of a tree array, from a position which can be moved around.
+pub struct Reader<'a> {
+    tree: &'a TreeArray<u8>,
+    position: u64,
+}
+
+impl<'a> Reader<'a> {
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+}
+
+impl<'a> Read for Reader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.tree.len();
+        let start = cmp::min(self.position, len as u64) as usize;
+        let end = cmp::min(start + buf.len(), len);
+        for (dst, src) in buf.iter_mut().zip(self.tree.iter_range(start..end)) {
+            *dst = *src;
+        }
+        self.position += (end - start) as u64;
+        Ok(end - start)
+    }
+}
+
+impl<'a> Seek for Reader<'a> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.position = seek(self.position, self.tree.len(), pos)?;
+        Ok(self.position)
+    }
+}
+
+// Writer into a tree array, from a position which can be moved around. Bytes are overwritten up
+// to the end of the array, and appended past it. Writing after seeking past the end fills the gap
+// with zeros.
+pub struct Writer<'a> {
+    tree: &'a mut TreeArray<u8>,
+    position: u64,
+}
+
+impl<'a> Writer<'a> {
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+}
+
+impl<'a> Write for Writer<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let start = self.position as usize;
+        if start > self.tree.len() {
+            self.tree.resize(start, 0);
+        }
+        let end = cmp::min(start + buf.len(), self.tree.len());
+        self.tree.remove_range(start..end);
+        self.tree.insert_slice(start, buf);
+        self.position += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> Seek for Writer<'a> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.position = seek(self.position, self.tree.len(), pos)?;
+        Ok(self.position)
+    }
+}
+
+// Resolves a seek from a current position in an array of length `len`.
+// Seeking past the end is allowed, but not before the start.
+fn seek(position: u64, len: usize, pos: SeekFrom) -> io::Result<u64> {
+    let (base, offset) = match pos {
+        SeekFrom::Start(n) => return Ok(n),
+        SeekFrom::End(offset) => (len as u64, offset),
+        SeekFrom::Current(offset) => (position, offset),
+    };
+    base.checked_add_signed(offset).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "invalid seek to a negative or overflowing position")
+    })
+}
@@ -0,0 +1,648 @@
+use std::collections::VecDeque;
+use std::iter::{self, FromIterator, FusedIterator, Sum};
+use std::num::NonZeroUsize;
+use builder::TreeArrayBuilder;
+use measure::Measure;
+use tree_array::{build, drop_tree, join, size_of, Node, Tree, TreeArray};
+
+// The iterators walk the tree with an explicit deque rather than recursing, since splay trees
+// can be arbitrarily deep. It holds, in index order, what remains to be yielded: either a single
+// value, or a whole subtree yet to be expanded. Each end expands the subtrees it meets into their
+// left subtree, value, and right subtree, so that both ends can be walked independently.
+// Subtrees come with whether a pending reversal above them mirrors them, since a shared borrow
+// cannot push reversals down. Values come with their node, which also holds their measure.
+enum Pending<'a, V: 'a, M: 'a> {
+    Value(&'a Node<V, M>),
+    Subtree(&'a Node<V, M>, bool),
+}
+
+// Iterator over the values of a tree array, in index order.
+// It knows how many values remain, since each subtree root holds its size.
+pub struct Iter<'a, V: 'a, M: 'a = ()> {
+    root: &'a Tree<V, M>,
+    deque: VecDeque<Pending<'a, V, M>>,
+    remaining: usize,
+}
+
+impl<'a, V, M> Iter<'a, V, M> {
+    pub(crate) fn new(root: &'a Tree<V, M>) -> Iter<'a, V, M> {
+        let mut deque = VecDeque::new();
+        let mut remaining = 0;
+        if let Some(root) = root {
+            remaining = root.size;
+            deque.push_back(Pending::Subtree(&**root, false));
+        }
+        Iter { root, deque, remaining }
+    }
+
+    // Moves the iterator so that it next yields the value at `index` in the whole array, and
+    // iterates from there to the end of the array. It walks down from the root, in logarithmic
+    // time on a balanced tree, whatever the current position is.
+    pub fn seek(&mut self, index: usize) {
+        self.deque.clear();
+        self.remaining = 0;
+        let (mut cur, mut flip) = (self.root, false);
+        let mut index = index;
+        // Walking down, whatever lies right of the path is queued, closest values first.
+        while let Some(node) = cur {
+            let (left, right, f) = node.sides(flip);
+            let node_idx = size_of(left);
+            flip = f;
+            if index <= node_idx {
+                if let Some(right) = right {
+                    self.deque.push_front(Pending::Subtree(right, flip));
+                }
+                self.deque.push_front(Pending::Value(node));
+                self.remaining += node.size - node_idx;
+                if index == node_idx {
+                    break;
+                }
+                cur = left;
+            } else {
+                index -= node_idx + 1;
+                cur = right;
+            }
+        }
+    }
+
+    // Drops the next `n` values from the front, skipping whole subtrees when they fit.
+    // Returns how many values could not be skipped, as the iterator ran out.
+    fn skip_front(&mut self, n: usize) -> usize {
+        let mut n = n;
+        while n > 0 {
+            match self.deque.pop_front() {
+                None => break,
+                Some(Pending::Value(_)) => {
+                    self.remaining -= 1;
+                    n -= 1;
+                },
+                Some(Pending::Subtree(node, _)) if node.size <= n => {
+                    self.remaining -= node.size;
+                    n -= node.size;
+                },
+                Some(Pending::Subtree(node, flip)) => {
+                    let (left, right, flip) = node.sides(flip);
+                    if let Some(right) = right {
+                        self.deque.push_front(Pending::Subtree(right, flip));
+                    }
+                    self.deque.push_front(Pending::Value(node));
+                    if let Some(left) = left {
+                        self.deque.push_front(Pending::Subtree(left, flip));
+                    }
+                },
+            }
+        }
+        n
+    }
+
+    // Drops the next `n` values from the back, skipping whole subtrees when they fit.
+    fn skip_back(&mut self, n: usize) -> usize {
+        let mut n = n;
+        while n > 0 {
+            match self.deque.pop_back() {
+                None => break,
+                Some(Pending::Value(_)) => {
+                    self.remaining -= 1;
+                    n -= 1;
+                },
+                Some(Pending::Subtree(node, _)) if node.size <= n => {
+                    self.remaining -= node.size;
+                    n -= node.size;
+                },
+                Some(Pending::Subtree(node, flip)) => {
+                    let (left, right, flip) = node.sides(flip);
+                    if let Some(left) = left {
+                        self.deque.push_back(Pending::Subtree(left, flip));
+                    }
+                    self.deque.push_back(Pending::Value(node));
+                    if let Some(right) = right {
+                        self.deque.push_back(Pending::Subtree(right, flip));
+                    }
+                },
+            }
+        }
+        n
+    }
+
+    // Node of the next value, in index order.
+    fn next_node(&mut self) -> Option<&'a Node<V, M>> {
+        loop {
+            match self.deque.pop_front()? {
+                Pending::Value(node) => {
+                    self.remaining -= 1;
+                    return Some(node);
+                },
+                Pending::Subtree(node, flip) => {
+                    let (left, right, flip) = node.sides(flip);
+                    if let Some(right) = right {
+                        self.deque.push_front(Pending::Subtree(right, flip));
+                    }
+                    self.deque.push_front(Pending::Value(node));
+                    if let Some(left) = left {
+                        self.deque.push_front(Pending::Subtree(left, flip));
+                    }
+                },
+            }
+        }
+    }
+
+    // Iterates over the measures of the values left, as kept on their nodes, rather than
+    // measuring the values again.
+    pub(crate) fn measures(mut self) -> impl Iterator<Item = &'a M> {
+        iter::from_fn(move || self.next_node().map(|node| &node.own))
+    }
+}
+
+impl<'a, V, M> Iterator for Iter<'a, V, M> {
+    type Item = &'a V;
+
+    fn next(&mut self) -> Option<&'a V> {
+        self.next_node().map(|node| &node.value)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+
+    fn nth(&mut self, n: usize) -> Option<&'a V> {
+        self.skip_front(n);
+        self.next()
+    }
+
+    fn advance_by(&mut self, n: usize) -> Result<(), NonZeroUsize> {
+        NonZeroUsize::new(self.skip_front(n)).map_or(Ok(()), Err)
+    }
+}
+
+impl<'a, V, M> DoubleEndedIterator for Iter<'a, V, M> {
+    fn nth_back(&mut self, n: usize) -> Option<&'a V> {
+        self.skip_back(n);
+        self.next_back()
+    }
+
+    fn advance_back_by(&mut self, n: usize) -> Result<(), NonZeroUsize> {
+        NonZeroUsize::new(self.skip_back(n)).map_or(Ok(()), Err)
+    }
+
+    fn next_back(&mut self) -> Option<&'a V> {
+        loop {
+            match self.deque.pop_back()? {
+                Pending::Value(node) => {
+                    self.remaining -= 1;
+                    return Some(&node.value);
+                },
+                Pending::Subtree(node, flip) => {
+                    let (left, right, flip) = node.sides(flip);
+                    if let Some(left) = left {
+                        self.deque.push_back(Pending::Subtree(left, flip));
+                    }
+                    self.deque.push_back(Pending::Value(node));
+                    if let Some(right) = right {
+                        self.deque.push_back(Pending::Subtree(right, flip));
+                    }
+                },
+            }
+        }
+    }
+}
+
+impl<'a, V, M> ExactSizeIterator for Iter<'a, V, M> {}
+
+impl<'a, V, M> FusedIterator for Iter<'a, V, M> {}
+
+// Iterator over the indices at which a pattern of values starts in a tree array, in increasing
+// order, overlapping matches included. It streams over the values once, as Knuth-Morris-Pratt
+// does: upon a mismatch, the table tells how much of the pattern still matches, so that no value
+// is read twice. An empty pattern matches at every index, up to the length of the array.
+pub struct Matches<'a, 'b, V: 'a + 'b, M: 'a = ()> {
+    values: Iter<'a, V, M>,
+    pattern: &'b [V],
+    // For each prefix of the pattern, the length of its longest proper prefix that is also one
+    // of its suffixes.
+    fallback: Vec<usize>,
+    // How many values of the pattern match the values just read.
+    matched: usize,
+    // How many values were read.
+    index: usize,
+    done: bool,
+}
+
+impl<'a, 'b, V, M> Matches<'a, 'b, V, M> where V: PartialEq {
+    pub(crate) fn new(values: Iter<'a, V, M>, pattern: &'b [V]) -> Matches<'a, 'b, V, M> {
+        let mut fallback = vec![0; pattern.len()];
+        let mut k = 0;
+        for i in 1..pattern.len() {
+            while k > 0 && pattern[i] != pattern[k] {
+                k = fallback[k - 1];
+            }
+            if pattern[i] == pattern[k] {
+                k += 1;
+            }
+            fallback[i] = k;
+        }
+        Matches { values, pattern, fallback, matched: 0, index: 0, done: false }
+    }
+}
+
+impl<'a, 'b, V, M> Iterator for Matches<'a, 'b, V, M> where V: PartialEq {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.done {
+            return None;
+        }
+        if self.pattern.is_empty() {
+            let index = self.index;
+            match self.values.next() {
+                Some(_) => self.index += 1,
+                None => self.done = true,
+            }
+            return Some(index);
+        }
+        for value in self.values.by_ref() {
+            self.index += 1;
+            while self.matched > 0 && *value != self.pattern[self.matched] {
+                self.matched = self.fallback[self.matched - 1];
+            }
+            if *value == self.pattern[self.matched] {
+                self.matched += 1;
+            }
+            if self.matched == self.pattern.len() {
+                self.matched = self.fallback[self.matched - 1];
+                return Some(self.index - self.pattern.len());
+            }
+        }
+        self.done = true;
+        None
+    }
+}
+
+impl<'a, 'b, V, M> FusedIterator for Matches<'a, 'b, V, M> where V: PartialEq {}
+
+enum PendingMut<'a, V: 'a> {
+    Value(&'a mut V),
+    Subtree(&'a mut Node<V>),
+}
+
+// Iterator over mutable references to the values of a tree array, in index order.
+// Expanding a subtree pushes its pending reversal down, then splits the borrow of its root
+// between its value and its two subtrees, so no two references handed out can alias.
+pub struct IterMut<'a, V: 'a> {
+    deque: VecDeque<PendingMut<'a, V>>,
+    remaining: usize,
+}
+
+impl<'a, V> IterMut<'a, V> {
+    pub(crate) fn new(root: &'a mut Tree<V>) -> IterMut<'a, V> {
+        let mut deque = VecDeque::new();
+        let mut remaining = 0;
+        if let Some(root) = root {
+            remaining = root.size;
+            deque.push_back(PendingMut::Subtree(&mut **root));
+        }
+        IterMut { deque, remaining }
+    }
+
+    // Drops the next `n` references from the front, skipping whole subtrees when they fit.
+    // Returns how many values could not be skipped, as the iterator ran out.
+    fn skip_front(&mut self, n: usize) -> usize {
+        let mut n = n;
+        while n > 0 {
+            match self.deque.pop_front() {
+                None => break,
+                Some(PendingMut::Value(_)) => {
+                    self.remaining -= 1;
+                    n -= 1;
+                },
+                Some(PendingMut::Subtree(node)) if node.size <= n => {
+                    self.remaining -= node.size;
+                    n -= node.size;
+                },
+                Some(PendingMut::Subtree(node)) => {
+                    node.push_down();
+                    let Node { value, left, right, .. } = node;
+                    if let Some(right) = right {
+                        self.deque.push_front(PendingMut::Subtree(right));
+                    }
+                    self.deque.push_front(PendingMut::Value(value));
+                    if let Some(left) = left {
+                        self.deque.push_front(PendingMut::Subtree(left));
+                    }
+                },
+            }
+        }
+        n
+    }
+
+    // Drops the next `n` references from the back, skipping whole subtrees when they fit.
+    fn skip_back(&mut self, n: usize) -> usize {
+        let mut n = n;
+        while n > 0 {
+            match self.deque.pop_back() {
+                None => break,
+                Some(PendingMut::Value(_)) => {
+                    self.remaining -= 1;
+                    n -= 1;
+                },
+                Some(PendingMut::Subtree(node)) if node.size <= n => {
+                    self.remaining -= node.size;
+                    n -= node.size;
+                },
+                Some(PendingMut::Subtree(node)) => {
+                    node.push_down();
+                    let Node { value, left, right, .. } = node;
+                    if let Some(left) = left {
+                        self.deque.push_back(PendingMut::Subtree(left));
+                    }
+                    self.deque.push_back(PendingMut::Value(value));
+                    if let Some(right) = right {
+                        self.deque.push_back(PendingMut::Subtree(right));
+                    }
+                },
+            }
+        }
+        n
+    }
+}
+
+impl<'a, V> Iterator for IterMut<'a, V> {
+    type Item = &'a mut V;
+
+    fn next(&mut self) -> Option<&'a mut V> {
+        loop {
+            match self.deque.pop_front()? {
+                PendingMut::Value(value) => {
+                    self.remaining -= 1;
+                    return Some(value);
+                },
+                PendingMut::Subtree(node) => {
+                    node.push_down();
+                    let Node { value, left, right, .. } = node;
+                    if let Some(right) = right {
+                        self.deque.push_front(PendingMut::Subtree(right));
+                    }
+                    self.deque.push_front(PendingMut::Value(value));
+                    if let Some(left) = left {
+                        self.deque.push_front(PendingMut::Subtree(left));
+                    }
+                },
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+
+    fn nth(&mut self, n: usize) -> Option<&'a mut V> {
+        self.skip_front(n);
+        self.next()
+    }
+
+    fn advance_by(&mut self, n: usize) -> Result<(), NonZeroUsize> {
+        NonZeroUsize::new(self.skip_front(n)).map_or(Ok(()), Err)
+    }
+}
+
+impl<'a, V> DoubleEndedIterator for IterMut<'a, V> {
+    fn nth_back(&mut self, n: usize) -> Option<&'a mut V> {
+        self.skip_back(n);
+        self.next_back()
+    }
+
+    fn advance_back_by(&mut self, n: usize) -> Result<(), NonZeroUsize> {
+        NonZeroUsize::new(self.skip_back(n)).map_or(Ok(()), Err)
+    }
+
+    fn next_back(&mut self) -> Option<&'a mut V> {
+        loop {
+            match self.deque.pop_back()? {
+                PendingMut::Value(value) => {
+                    self.remaining -= 1;
+                    return Some(value);
+                },
+                PendingMut::Subtree(node) => {
+                    node.push_down();
+                    let Node { value, left, right, .. } = node;
+                    if let Some(left) = left {
+                        self.deque.push_back(PendingMut::Subtree(left));
+                    }
+                    self.deque.push_back(PendingMut::Value(value));
+                    if let Some(right) = right {
+                        self.deque.push_back(PendingMut::Subtree(right));
+                    }
+                },
+            }
+        }
+    }
+}
+
+impl<'a, V> ExactSizeIterator for IterMut<'a, V> {}
+
+impl<'a, V> FusedIterator for IterMut<'a, V> {}
+
+enum PendingOwned<V, M> {
+    Value(V),
+    Subtree(Box<Node<V, M>>),
+}
+
+// Owning iterator over the values of a tree array, in index order.
+// Subtrees are taken apart as they get expanded, so that nothing is ever dropped recursively.
+pub struct IntoIter<V, M = ()> {
+    deque: VecDeque<PendingOwned<V, M>>,
+    remaining: usize,
+}
+
+impl<V, M> IntoIter<V, M> {
+    pub(crate) fn new(root: Tree<V, M>) -> IntoIter<V, M> {
+        let mut deque = VecDeque::new();
+        let mut remaining = 0;
+        if let Some(root) = root {
+            remaining = root.size;
+            deque.push_back(PendingOwned::Subtree(root));
+        }
+        IntoIter { deque, remaining }
+    }
+}
+
+impl<V, M> Iterator for IntoIter<V, M> where M: Measure<V> {
+    type Item = V;
+
+    fn next(&mut self) -> Option<V> {
+        loop {
+            match self.deque.pop_front()? {
+                PendingOwned::Value(value) => {
+                    self.remaining -= 1;
+                    return Some(value);
+                },
+                PendingOwned::Subtree(mut node) => {
+                    node.push_down();
+                    let Node { value, left, right, .. } = *node;
+                    if let Some(right) = right {
+                        self.deque.push_front(PendingOwned::Subtree(right));
+                    }
+                    self.deque.push_front(PendingOwned::Value(value));
+                    if let Some(left) = left {
+                        self.deque.push_front(PendingOwned::Subtree(left));
+                    }
+                },
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<V, M> DoubleEndedIterator for IntoIter<V, M> where M: Measure<V> {
+    fn next_back(&mut self) -> Option<V> {
+        loop {
+            match self.deque.pop_back()? {
+                PendingOwned::Value(value) => {
+                    self.remaining -= 1;
+                    return Some(value);
+                },
+                PendingOwned::Subtree(mut node) => {
+                    node.push_down();
+                    let Node { value, left, right, .. } = *node;
+                    if let Some(left) = left {
+                        self.deque.push_back(PendingOwned::Subtree(left));
+                    }
+                    self.deque.push_back(PendingOwned::Value(value));
+                    if let Some(right) = right {
+                        self.deque.push_back(PendingOwned::Subtree(right));
+                    }
+                },
+            }
+        }
+    }
+}
+
+impl<V, M> ExactSizeIterator for IntoIter<V, M> where M: Measure<V> {}
+
+impl<V, M> FusedIterator for IntoIter<V, M> where M: Measure<V> {}
+
+impl<V, M> Drop for IntoIter<V, M> {
+    fn drop(&mut self) {
+        for pending in self.deque.drain(..) {
+            if let PendingOwned::Subtree(node) = pending {
+                drop_tree(Some(node));
+            }
+        }
+    }
+}
+
+// Collecting is the preferred way to build a tree array: it builds a perfectly balanced tree in
+// linear time, rather than inserting values one by one. The values are buffered first, since the
+// size of the tree must be known upfront, in the chunks of a builder, so that they are not moved
+// again as the buffer grows.
+impl<V, M> FromIterator<V> for TreeArray<V, M> where M: Measure<V> {
+    fn from_iter<I>(iter: I) -> TreeArray<V, M> where I: IntoIterator<Item = V> {
+        let mut builder = TreeArrayBuilder::new();
+        builder.extend(iter);
+        builder.build()
+    }
+}
+
+// Summing tree arrays concatenates them, each one joined to the end of the previous ones in
+// logarithmic time.
+impl<V, M> Sum<TreeArray<V, M>> for TreeArray<V, M> where M: Measure<V> {
+    fn sum<I>(iter: I) -> TreeArray<V, M> where I: Iterator<Item = TreeArray<V, M>> {
+        let mut total = TreeArray::default();
+        for mut tree in iter {
+            total.append(&mut tree);
+        }
+        total
+    }
+}
+
+// Extending builds a balanced tree out of the new values, buffered in the chunks of a builder,
+// and joins it at the end of the array, rather than appending values one by one.
+impl<V, M> Extend<V> for TreeArray<V, M> where M: Measure<V> {
+    fn extend<I>(&mut self, iter: I) where I: IntoIterator<Item = V> {
+        let mut builder = TreeArrayBuilder::new();
+        builder.extend(iter);
+        let mut tail: TreeArray<V, M> = builder.build();
+        self.root = join(self.root.take(), tail.root.take());
+    }
+}
+
+impl<'a, V, M> Extend<&'a V> for TreeArray<V, M> where V: Copy + 'a, M: Measure<V> {
+    fn extend<I>(&mut self, iter: I) where I: IntoIterator<Item = &'a V> {
+        self.extend(iter.into_iter().cloned());
+    }
+}
+
+impl<V, M> IntoIterator for TreeArray<V, M> where M: Measure<V> {
+    type Item = V;
+    type IntoIter = IntoIter<V, M>;
+
+    fn into_iter(mut self) -> IntoIter<V, M> {
+        IntoIter::new(self.root.take())
+    }
+}
+
+impl<'a, V, M> IntoIterator for &'a TreeArray<V, M> where M: Measure<V> {
+    type Item = &'a V;
+    type IntoIter = Iter<'a, V, M>;
+
+    fn into_iter(self) -> Iter<'a, V, M> {
+        self.iter()
+    }
+}
+
+impl<'a, V> IntoIterator for &'a mut TreeArray<V> {
+    type Item = &'a mut V;
+    type IntoIter = IterMut<'a, V>;
+
+    fn into_iter(self) -> IterMut<'a, V> {
+        self.iter_mut()
+    }
+}
+
+// Iterator removing and yielding the values of a tree array for which a predicate holds.
+// The tree is taken apart in a single in-order pass; kept values are collected in order, and
+// rebuilt into a balanced tree when the iterator is dropped. Values it did not get to are kept.
+pub struct ExtractIf<'a, V: 'a, F, M: 'a = ()>
+    where F: FnMut(&mut V) -> bool, M: Measure<V> {
+    tree: &'a mut TreeArray<V, M>,
+    source: IntoIter<V, M>,
+    kept: Vec<V>,
+    predicate: F,
+}
+
+impl<'a, V, F, M> ExtractIf<'a, V, F, M> where F: FnMut(&mut V) -> bool, M: Measure<V> {
+    pub(crate) fn new(tree: &'a mut TreeArray<V, M>, predicate: F) -> ExtractIf<'a, V, F, M> {
+        let source = IntoIter::new(tree.root.take());
+        let kept = Vec::with_capacity(source.len());
+        ExtractIf { tree, source, kept, predicate }
+    }
+}
+
+impl<'a, V, F, M> Iterator for ExtractIf<'a, V, F, M>
+    where F: FnMut(&mut V) -> bool, M: Measure<V> {
+    type Item = V;
+
+    fn next(&mut self) -> Option<V> {
+        for mut value in self.source.by_ref() {
+            if (self.predicate)(&mut value) {
+                return Some(value);
+            }
+            self.kept.push(value);
+        }
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.source.len()))
+    }
+}
+
+impl<'a, V, F, M> Drop for ExtractIf<'a, V, F, M> where F: FnMut(&mut V) -> bool, M: Measure<V> {
+    fn drop(&mut self) {
+        self.kept.extend(self.source.by_ref());
+        let len = self.kept.len();
+        self.tree.root = build(&mut self.kept.drain(..), len);
+    }
+}
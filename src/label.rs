@@ -0,0 +1,91 @@
+use measure::Measure;
+use tree_array::TreeArray;
+
+// Spacing between the labels of entries added at the end.
+const STEP: u64 = 1 << 32;
+
+// A range of 2^i labels is full once it holds more than (2 / DENSITY)^i entries: the larger the
+// range, the emptier it is kept, so that labelling a range anew leaves its parts far from full.
+const DENSITY: f64 = 1.4;
+
+// An entry of a tree array kept in order of labels, increasing along the array.
+// The traits are public within a private module, so that they can bound the methods below
+// without being reachable from outside the crate.
+pub trait Label {
+    fn label(&self) -> u64;
+    fn set_label(&mut self, label: u64);
+}
+
+// Summary of a run of labelled entries, which knows the label of the last one.
+pub trait LastLabel {
+    fn last_label(&self) -> Option<u64>;
+}
+
+// Order maintenance: each entry is labelled halfway between its neighbours, so that entries are
+// found by walking down the tree by label. When there is no room left between them, the entries
+// around it are labelled anew, evenly over the smallest aligned range of labels that is not full,
+// which relabels a logarithmic number of entries per insertion on average.
+impl<E, S> TreeArray<E, S> where E: Label, S: Measure<E> + LastLabel {
+    // Inserts an entry at an index, and labels it. Entries given a label are handed to `record`:
+    // the one inserted, and those labelled anew along with it.
+    // Panics if the index is greater than the length.
+    pub(crate) fn insert_labelled<F>(&mut self, index: usize, mut entry: E, mut record: F)
+        where F: FnMut(&E) {
+        let prev = match index {
+            0 => 0,
+            _ => self.splay_get(index - 1).map_or(0, Label::label),
+        };
+        let next = self.splay_get(index).map_or(u64::MAX, Label::label);
+        let room = next - prev;
+        entry.set_label(prev + (room / 2).min(STEP));
+        if room >= 2 {
+            record(&entry);
+            self.insert(index, entry);
+        } else {
+            self.insert(index, entry);
+            self.relabel(index, record);
+        }
+    }
+
+    // Finds the entry with a given label, splayed to the root, along with the summary of the
+    // entries before it. It may be past the entry, if there is none with that label.
+    pub(crate) fn locate_label(&mut self, label: u64) -> Option<(usize, S)> {
+        self.locate_by_measure_mut(|summary| summary.last_label() >= Some(label))
+    }
+
+    // Labels anew the entries around one just inserted with the label of the entry before it.
+    // Ranges of labels of growing sizes are tried, each aligned on its size, until one is found
+    // that is not full, or up to the range of all labels.
+    fn relabel<F>(&mut self, index: usize, mut record: F) where F: FnMut(&E) {
+        let label = self[index].label() as u128;
+        let (mut bits, mut width) = (0, 1u128);
+        let (base, start, end) = loop {
+            bits += 1;
+            width *= 2;
+            let base = label & !(width - 1);
+            let (start, end) = (self.label_position(base), self.label_position(base + width));
+            if bits == 64 || (end - start) as f64 <= (2.0 / DENSITY).powi(bits) {
+                break (base, start, end);
+            }
+        };
+        let spacing = width / ((end - start) as u128 + 1);
+        let entries: TreeArray<E, S> = self.take(start..end).into_iter().enumerate()
+            .map(|(i, mut entry)| {
+                entry.set_label((base + spacing * (i as u128 + 1)) as u64);
+                record(&entry);
+                entry
+            })
+            .collect();
+        self.insert_all(start, entries);
+    }
+
+    // Index of the first entry whose label is at least a given one, or the length if there is
+    // none.
+    fn label_position(&mut self, label: u128) -> usize {
+        let found = match label {
+            label if label > u64::MAX as u128 => None,
+            label => self.locate_label(label as u64),
+        };
+        found.map_or(self.len(), |(index, _)| index)
+    }
+}
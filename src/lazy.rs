@@ -0,0 +1,192 @@
+use std::fmt;
+use std::iter::FromIterator;
+use std::mem;
+use std::ops::{Add, RangeBounds};
+use measure::Measure;
+use tree_array::TreeArray;
+
+// A tree array of numbers which can be updated a whole range at a time, in logarithmic time,
+// by adding to its values or overwriting them, and which sums ranges just as fast.
+// An update only marks the root of the range, and is handed down to the nodes below as later
+// operations walk down the tree. Values are therefore only read through operations that walk
+// down to them, or that go through all of them anyway.
+pub struct LazyTreeArray<V> where V: Clone + Default + Add<Output = V> {
+    tree: TreeArray<V, Pending<V>>,
+}
+
+// Summary of a subtree: its length and the sum of its values, along with the updates yet to be
+// made to each of its values, already counted in the sum: first overwriting it with `assign`,
+// then adding `add` to it.
+#[derive(Clone)]
+struct Pending<V> {
+    len: usize,
+    sum: V,
+    assign: Option<V>,
+    add: Option<V>,
+}
+
+impl<V> Pending<V> where V: Clone + Default + Add<Output = V> {
+    // Records that `delta` is to be added to every value of the subtree.
+    fn add(&mut self, delta: &V) {
+        self.sum = mem::take(&mut self.sum) + times(delta, self.len);
+        self.add = Some(match self.add.take() {
+            Some(add) => add + delta.clone(),
+            None => delta.clone(),
+        });
+    }
+
+    // Records that every value of the subtree is to be overwritten with `value`, which supersedes
+    // earlier updates.
+    fn assign(&mut self, value: &V) {
+        self.sum = times(value, self.len);
+        self.assign = Some(value.clone());
+        self.add = None;
+    }
+}
+
+impl<V> Measure<V> for Pending<V> where V: Clone + Default + Add<Output = V> {
+    fn identity() -> Pending<V> {
+        Pending { len: 0, sum: V::default(), assign: None, add: None }
+    }
+
+    fn measure(value: &V) -> Pending<V> {
+        Pending { len: 1, sum: value.clone(), assign: None, add: None }
+    }
+
+    fn combine(&self, other: &Pending<V>) -> Pending<V> {
+        let sum = self.sum.clone() + other.sum.clone();
+        Pending { len: self.len + other.len, sum, assign: None, add: None }
+    }
+
+    fn push_down(&mut self, value: &mut V, mut left: Option<&mut Pending<V>>,
+                 mut right: Option<&mut Pending<V>>) -> bool {
+        let changed = self.assign.is_some() || self.add.is_some();
+        if let Some(assign) = self.assign.take() {
+            for child in left.as_deref_mut().into_iter().chain(right.as_deref_mut()) {
+                child.assign(&assign);
+            }
+            *value = assign;
+        }
+        if let Some(add) = self.add.take() {
+            for child in left.into_iter().chain(right) {
+                child.add(&add);
+            }
+            *value = mem::take(value) + add;
+        }
+        changed
+    }
+}
+
+// Sum of `n` times a value, with as many additions as there are bits in `n`.
+fn times<V>(value: &V, mut n: usize) -> V where V: Clone + Default + Add<Output = V> {
+    let mut total = V::default();
+    let mut power = value.clone();
+    while n > 0 {
+        if n & 1 == 1 {
+            total = total + power.clone();
+        }
+        n >>= 1;
+        if n > 0 {
+            power = power.clone() + power;
+        }
+    }
+    total
+}
+
+impl<V> LazyTreeArray<V> where V: Clone + Default + Add<Output = V> {
+    pub fn new() -> LazyTreeArray<V> {
+        LazyTreeArray { tree: TreeArray::default() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    // Gets the value at an index, splaying it to the root on the way, like `splay_get`.
+    pub fn get(&mut self, index: usize) -> Option<&V> {
+        self.tree.splay_get(index)
+    }
+
+    // Panics if the index is out of bounds.
+    pub fn set(&mut self, index: usize, value: V) {
+        self.tree.set(index, value);
+    }
+
+    // Panics if the index is greater than the length.
+    pub fn insert(&mut self, index: usize, value: V) {
+        self.tree.insert(index, value);
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<V> {
+        self.tree.remove(index)
+    }
+
+    pub fn push(&mut self, value: V) {
+        self.tree.push(value);
+    }
+
+    pub fn pop(&mut self) -> Option<V> {
+        self.tree.pop()
+    }
+
+    // Adds `delta` to every value in a range, in logarithmic time.
+    // Panics if the range is out of bounds.
+    pub fn add_to_range<R>(&mut self, range: R, delta: V) where R: RangeBounds<usize> {
+        self.tree.update_range(range, |root| root.summary.add(&delta));
+    }
+
+    // Overwrites every value in a range with `value`, in logarithmic time.
+    // Panics if the range is out of bounds.
+    pub fn assign_range<R>(&mut self, range: R, value: V) where R: RangeBounds<usize> {
+        self.tree.update_range(range, |root| root.summary.assign(&value));
+    }
+
+    // Sum of the values in a range. Panics if the range is out of bounds.
+    pub fn sum_range<R>(&mut self, range: R) -> V where R: RangeBounds<usize> {
+        self.tree.measure_range(range).sum
+    }
+
+    // Sum of all values, in constant time.
+    pub fn sum(&self) -> V {
+        self.tree.measure().sum
+    }
+
+    pub fn into_vec(self) -> Vec<V> {
+        self.tree.into_vec()
+    }
+
+    // The values are brought up to date on a copy of the tree.
+    pub fn to_vec(&self) -> Vec<V> {
+        self.tree.clone().into_vec()
+    }
+}
+
+impl<V> Default for LazyTreeArray<V> where V: Clone + Default + Add<Output = V> {
+    fn default() -> LazyTreeArray<V> {
+        LazyTreeArray::new()
+    }
+}
+
+impl<V> Clone for LazyTreeArray<V> where V: Clone + Default + Add<Output = V> {
+    fn clone(&self) -> LazyTreeArray<V> {
+        LazyTreeArray { tree: self.tree.clone() }
+    }
+}
+
+impl<V> FromIterator<V> for LazyTreeArray<V> where V: Clone + Default + Add<Output = V> {
+    fn from_iter<I>(iter: I) -> LazyTreeArray<V> where I: IntoIterator<Item = V> {
+        LazyTreeArray { tree: iter.into_iter().collect() }
+    }
+}
+
+// Formats as a list, like `[1, 2, 3]`.
+impl<V> fmt::Debug for LazyTreeArray<V>
+    where V: Clone + Default + Add<Output = V> + fmt::Debug {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.to_vec()).finish()
+    }
+}
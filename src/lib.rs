@@ -1,11 +1,91 @@
 #![feature(test)]
+#![feature(iter_advance_by)]
 extern crate test;
+#[cfg(feature = "blake3")]
+extern crate blake3;
+#[cfg(feature = "bytes")]
+extern crate bytes;
+#[cfg(loom)]
+extern crate loom;
+#[cfg(feature = "unicode-segmentation")]
+extern crate unicode_segmentation;
 
+// Creates a tree array, with the same syntax as `vec!`: either a list of values, or a value
+// and a length for that many clones of it. The tree is built balanced in one go.
+#[macro_export]
+macro_rules! tree_array {
+    () => (
+        $crate::TreeArray::new()
+    );
+    ($value:expr; $n:expr) => ({
+        let mut tree = $crate::TreeArray::new();
+        tree.resize($n, $value);
+        tree
+    });
+    ($($value:expr),+ $(,)*) => (
+        $crate::TreeArray::from(vec![$($value),+])
+    );
+}
+
+#[cfg(test)]
 use std::collections::LinkedList;
+pub use self::anchor::{Affinity, Anchor, AnchoredTreeArray, Anchors};
+pub use self::batch::EditBatch;
+pub use self::bits::TreeBits;
+#[cfg(feature = "bytes")]
+pub use self::buf::{Appender, ByteRope};
+pub use self::builder::TreeArrayBuilder;
+pub use self::cursor::{Cursor, CursorMut};
+#[cfg(feature = "crdt")]
+pub use self::crdt::{Meta, Operation, Rga, Stamp};
+pub use self::diff::Edit;
+pub use self::dirty::DirtyTreeArray;
+pub use self::error::Error;
+pub use self::handle::{Handle, HandleTreeArray};
+pub use self::history::{UndoTreeArray, Version};
+pub use self::io::{Reader, Writer};
+pub use self::iter::{ExtractIf, IntoIter, Iter, IterMut, Matches};
+pub use self::lazy::LazyTreeArray;
+pub use self::map::{MapIter, TreeMap};
+pub use self::measure::{AnyFlags, Flagged, Measure, MinMax, Sum, TotalWidth, Width};
+pub use self::merkle::{ContentBytes, ContentHash};
+pub use self::persistent::{FrozenTreeArray, PersistentIter, PersistentTreeArray, SnapshotIter};
+pub use self::runs::RleTreeArray;
+pub use self::slice::{SplitAtMut, TreeSlice};
+pub use self::sorted::SortedTreeArray;
 pub use self::tree_array::TreeArray;
+pub use self::tree_string::TreeString;
 
+pub mod anchor;
+pub mod batch;
+pub mod bits;
+#[cfg(feature = "bytes")]
+pub mod buf;
+pub mod builder;
+pub mod cursor;
+#[cfg(feature = "crdt")]
+pub mod crdt;
+pub mod diff;
+pub mod dirty;
+pub mod error;
+pub mod handle;
+pub mod history;
+pub mod io;
+pub mod iter;
+mod label;
+pub mod lazy;
+pub mod map;
+pub mod measure;
+pub mod merkle;
+pub mod persistent;
+pub mod runs;
+pub mod slice;
+pub mod sorted;
 pub mod tree_array;
+pub mod tree_string;
 
+// Only the tests and benchmarks insert into linked lists.
+#[cfg(test)]
 fn list_insert(l: &mut LinkedList<i32>, position: usize, item: i32) {
     let mut tail = l.split_off(position);
     tail.push_front(item);
@@ -15,8 +95,73 @@ fn list_insert(l: &mut LinkedList<i32>, position: usize, item: i32) {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::borrow::Cow;
+    use std::num::NonZeroUsize;
     use test::Bencher;
 
+    // Small xorshift generator, so that randomized tests are reproducible
+    // without pulling in a dependency.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+    }
+
+    // Measure keeping all values, in order, to check summaries against a model.
+    #[derive(Clone, Debug, PartialEq)]
+    struct Concat(Vec<usize>);
+
+    impl Measure<usize> for Concat {
+        fn identity() -> Concat {
+            Concat(Vec::new())
+        }
+
+        fn measure(value: &usize) -> Concat {
+            Concat(vec![*value])
+        }
+
+        fn combine(&self, other: &Concat) -> Concat {
+            Concat(self.0.iter().chain(&other.0).cloned().collect())
+        }
+
+        fn reverse(&self) -> Concat {
+            Concat(self.0.iter().rev().cloned().collect())
+        }
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Total(usize);
+
+    impl Measure<usize> for Total {
+        fn identity() -> Total {
+            Total(0)
+        }
+
+        fn measure(value: &usize) -> Total {
+            Total(*value)
+        }
+
+        fn combine(&self, other: &Total) -> Total {
+            Total(self.0 + other.0)
+        }
+    }
+
+    // Flags of a number: bit 0 if it is even, bit 1 if it is a multiple of 7.
+    impl Flagged for u32 {
+        fn flags(&self) -> u64 {
+            self.is_multiple_of(2) as u64 | (self.is_multiple_of(7) as u64) << 1
+        }
+    }
+
     #[bench]
     fn bench_insert_vec(b: &mut Bencher) {
         let mut v = Vec::new();
@@ -40,16 +185,26 @@ mod tests {
         println!("l[0] = {}", l.front().unwrap())
     }
 
+    #[bench]
+    fn bench_insert_tree_array(b: &mut Bencher) {
+        let mut l = LinkedList::new();
+        for i in 0..1_000_000 {
+            l.push_back(i);
+        }
+        let mut t = TreeArray::from(l);
+        // Like the list, insert in the middle.
+        b.iter(|| t.insert(500_000, 1));
+        println!("t[0] = {}", t[0])
+    }
+
     #[test]
     fn test_insert_vec() {
         let mut v = Vec::new();
         v.push(2);
         v.insert(0, 1);
         assert_eq!(v.len(), 2);
-        let mut i = 1;
-        for e in &v {
+        for (i, e) in (1..).zip(&v) {
             assert_eq!(*e, i);
-            i += 1;
         }
     }
 
@@ -59,10 +214,8 @@ mod tests {
         l.push_back(2);
         list_insert(&mut l, 0, 1);
         assert_eq!(l.len(), 2);
-        let mut i = 1;
-        for e in &l {
+        for (i, e) in (1..).zip(&l) {
             assert_eq!(*e, i);
-            i += 1;
         }
     }
 
@@ -78,10 +231,3251 @@ mod tests {
         }
         assert_eq!(t.len(), 2);
 
-        //let mut i = 1;
-        //for e in &t {
-        //    assert_eq!(*e, i);
-        //    i += 1;
-        //}
+        for (i, e) in (1..).zip(&t) {
+            assert_eq!(*e, i);
+        }
+    }
+
+    #[test]
+    fn test_insert_get_tree_array_at_any_index() {
+        let mut t = TreeArray::new();
+        for i in 0..1000 {
+            t.insert(i, i);
+        }
+        assert_eq!(t.len(), 1000);
+        for i in 0..1000 {
+            assert_eq!(t.get(i), Some(&i));
+        }
+        for i in (0..1000).rev() {
+            assert_eq!(t.get(i), Some(&i));
+        }
+        assert_eq!(t.get(1000), None);
+    }
+
+    #[test]
+    fn test_random_insert_get_tree_array() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        let mut t = TreeArray::new();
+        let mut model = Vec::new();
+        for i in 0..5000 {
+            let index = rng.below(model.len() + 1);
+            t.insert(index, i);
+            model.insert(index, i);
+            assert_eq!(t.len(), model.len());
+            let probe = rng.below(model.len());
+            assert_eq!(t.get(probe), Some(&model[probe]));
+        }
+        for (i, v) in model.iter().enumerate() {
+            assert_eq!(t.get(i), Some(v));
+        }
+        assert_eq!(t.get(model.len()), None);
+    }
+
+    #[test]
+    fn test_remove_tree_array() {
+        let mut t = TreeArray::new();
+        for i in 0..10 {
+            t.insert(i, i);
+        }
+        assert_eq!(t.remove(10), None);
+        assert_eq!(t.remove(3), Some(3));
+        assert_eq!(t.remove(0), Some(0));
+        assert_eq!(t.remove(7), Some(9));
+        assert_eq!(t.len(), 7);
+        for (i, v) in [1, 2, 4, 5, 6, 7, 8].iter().enumerate() {
+            assert_eq!(t.get(i), Some(v));
+        }
+        while !t.is_empty() {
+            t.remove(0);
+        }
+        assert_eq!(t.remove(0), None);
+    }
+
+    #[test]
+    fn test_random_insert_remove_tree_array() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        let mut t = TreeArray::new();
+        let mut model = Vec::new();
+        for i in 0..5000 {
+            if model.is_empty() || rng.below(3) > 0 {
+                let index = rng.below(model.len() + 1);
+                t.insert(index, i);
+                model.insert(index, i);
+            } else {
+                let index = rng.below(model.len());
+                assert_eq!(t.remove(index), Some(model.remove(index)));
+            }
+            assert_eq!(t.len(), model.len());
+        }
+        for (i, v) in model.iter().enumerate() {
+            assert_eq!(t.get(i), Some(v));
+        }
+    }
+
+    #[test]
+    fn test_push_pop_tree_array() {
+        let mut t = TreeArray::new();
+        assert_eq!(t.pop(), None);
+        for i in 0..100 {
+            t.push(i);
+        }
+        assert_eq!(t.len(), 100);
+        assert_eq!(t.get(42), Some(&42));
+        for i in (50..100).rev() {
+            assert_eq!(t.pop(), Some(i));
+        }
+        t.push(1000);
+        assert_eq!(t.len(), 51);
+        assert_eq!(t.get(50), Some(&1000));
+        assert_eq!(t.get(49), Some(&49));
+    }
+
+    #[test]
+    fn test_deque_tree_array() {
+        let mut t = TreeArray::new();
+        let mut model = std::collections::VecDeque::new();
+        let mut rng = Rng(0x1234_5678_9abc_def1);
+        for i in 0..2000 {
+            match rng.below(5) {
+                0 => { t.push(i); model.push_back(i); },
+                1 => { t.push_front(i); model.push_front(i); },
+                2 => assert_eq!(t.pop(), model.pop_back()),
+                3 => assert_eq!(t.pop_front(), model.pop_front()),
+                _ => {
+                    let index = rng.below(model.len() + 1);
+                    t.insert(index, i);
+                    model.insert(index, i);
+                },
+            }
+            assert_eq!(t.len(), model.len());
+        }
+        for (i, v) in model.iter().enumerate() {
+            assert_eq!(t.get(i), Some(v));
+        }
+    }
+
+    #[test]
+    fn test_tree_array_without_display() {
+        #[derive(Debug, PartialEq)]
+        struct Point { x: i32, y: i32 }
+        let mut t = TreeArray::new();
+        t.push(Point { x: 1, y: 2 });
+        t.push_front(Point { x: 0, y: 0 });
+        assert_eq!(t.get(1), Some(&Point { x: 1, y: 2 }));
+        let mut pairs = TreeArray::new();
+        pairs.push((1, "one"));
+        assert_eq!(pairs.pop(), Some((1, "one")));
+    }
+
+    #[test]
+    fn test_debug_tree_array() {
+        let mut t = TreeArray::new();
+        assert_eq!(format!("{:?}", t), "[]");
+        assert_eq!(format!("{:#?}", t), "TreeArray {}");
+        t.push(2);
+        t.push_front(1);
+        t.push(3);
+        assert_eq!(format!("{:?}", t), "[1, 2, 3]");
+        assert_eq!(format!("{:#?}", t), "TreeArray {
+    [3] size=3
+        left: [2] size=2
+            left: [1] size=1
+}");
+        let t: TreeArray<&str> = vec!["a", "b", "c"].into_iter().collect();
+        assert_eq!(format!("{:#?}", t), "TreeArray {
+    [\"b\"] size=3
+        left: [\"a\"] size=1
+        right: [\"c\"] size=1
+}");
+    }
+
+    #[test]
+    fn test_get_mut_tree_array() {
+        let mut t = TreeArray::new();
+        for i in 0..100 {
+            t.push(i);
+        }
+        assert_eq!(t.get_mut(100), None);
+        for i in 0..100 {
+            *t.get_mut(i).unwrap() *= 2;
+        }
+        assert_eq!(t.len(), 100);
+        for i in 0..100 {
+            assert_eq!(t.get(i), Some(&(2 * i)));
+        }
+    }
+
+    #[test]
+    fn test_get_shared_tree_array() {
+        let mut t = TreeArray::new();
+        let mut model = Vec::new();
+        let mut rng = Rng(0x0dd_ba11_cafe);
+        for i in 0..1000 {
+            let index = rng.below(model.len() + 1);
+            t.insert(index, i);
+            model.insert(index, i);
+        }
+        let shared = &t;
+        for (i, v) in model.iter().enumerate() {
+            assert_eq!(shared.get(i), Some(v));
+        }
+        assert_eq!(shared.get(model.len()), None);
+        assert_eq!(t.splay_get(500), Some(&model[500]));
+        assert_eq!(t.splay_get(model.len()), None);
+        assert_eq!(t.get(500), Some(&model[500]));
+    }
+
+    #[test]
+    fn test_set_replace_tree_array() {
+        let mut t = TreeArray::new();
+        for i in 0..10 {
+            t.push(i);
+        }
+        t.set(3, 30);
+        assert_eq!(t.get(3), Some(&30));
+        assert_eq!(t.replace(9, 90), Some(9));
+        assert_eq!(t.get(9), Some(&90));
+        assert_eq!(t.replace(10, 100), None);
+        assert_eq!(t.len(), 10);
+    }
+
+    #[test]
+    #[should_panic(expected = "set index (is 3) should be < len (is 3)")]
+    fn test_set_out_of_bounds_tree_array() {
+        let mut t = TreeArray::new();
+        for i in 0..3 {
+            t.push(i);
+        }
+        t.set(3, 3);
+    }
+
+    #[test]
+    fn test_first_last_tree_array() {
+        let mut t = TreeArray::new();
+        assert_eq!(t.first(), None);
+        assert_eq!(t.last(), None);
+        assert_eq!(t.first_mut(), None);
+        assert_eq!(t.last_mut(), None);
+        let mut rng = Rng(0xfeed_beef);
+        for i in 0..500 {
+            let index = rng.below(t.len() + 1);
+            t.insert(index, i + 1);
+        }
+        t.push_front(0);
+        t.push(1000);
+        assert_eq!(t.first(), Some(&0));
+        assert_eq!(t.last(), Some(&1000));
+        *t.first_mut().unwrap() = -1;
+        *t.last_mut().unwrap() = 2000;
+        assert_eq!(t.get(0), Some(&-1));
+        assert_eq!(t.get(501), Some(&2000));
+        assert_eq!(t.first(), Some(&-1));
+        assert_eq!(t.last(), Some(&2000));
+        assert_eq!(t.splay_first(), Some(&-1));
+        assert_eq!(t.splay_last(), Some(&2000));
+        assert_eq!(TreeArray::<i32>::new().splay_last(), None);
+    }
+
+    #[test]
+    fn test_splay_first_last_tree_array() {
+        let n = 1 << 14;
+        let mut t = TreeArray::new();
+        for i in 0..n {
+            t.push(i);
+        }
+        for _ in 0..100 {
+            assert_eq!(t.splay_first(), Some(&0));
+            assert_eq!(t.splay_last(), Some(&(n - 1)));
+        }
+        // Walking down a spine without splaying would leave it as long as the array.
+        let spine = |mut node: &Option<Box<tree_array::Node<usize>>>, left: bool| {
+            let mut len = 0;
+            while let Some(n) = node {
+                len += 1;
+                node = if left { &n.left } else { &n.right };
+            }
+            len
+        };
+        assert!(spine(&t.root, true) < 16 && spine(&t.root, false) < 16);
+    }
+
+    #[test]
+    fn test_clear_is_empty_tree_array() {
+        let mut t = TreeArray::new();
+        assert!(t.is_empty());
+        // Pushing leaves a tree that is a single left spine.
+        for i in 0..1_000_000 {
+            t.push(i);
+        }
+        assert!(!t.is_empty());
+        t.clear();
+        assert!(t.is_empty());
+        assert_eq!(t.len(), 0);
+        assert_eq!(t.get(0), None);
+        t.push(1);
+        assert_eq!(t.get(0), Some(&1));
+    }
+
+    #[test]
+    fn test_drop_deep_tree_array() {
+        let mut t = TreeArray::new();
+        for i in 0..1_000_000 {
+            t.push_front(i);
+        }
+        drop(t);
+    }
+
+    #[test]
+    fn test_truncate_tree_array() {
+        let mut t = TreeArray::new();
+        for i in 0..100 {
+            t.push(i);
+        }
+        t.truncate(200);
+        assert_eq!(t.len(), 100);
+        t.truncate(42);
+        assert_eq!(t.len(), 42);
+        assert_eq!(t.last(), Some(&41));
+        assert_eq!(t.get(42), None);
+        for i in 0..42 {
+            assert_eq!(t.get(i), Some(&i));
+        }
+        t.push(7);
+        assert_eq!(t.get(42), Some(&7));
+        t.truncate(0);
+        assert!(t.is_empty());
+    }
+
+    #[test]
+    fn test_swap_tree_array() {
+        let mut rng = Rng(0x5eed_5a4b);
+        let mut t = TreeArray::new();
+        let mut model = Vec::new();
+        for i in 0..300 {
+            t.push(i);
+            model.push(i);
+        }
+        for _ in 0..2000 {
+            let a = rng.below(model.len());
+            let b = rng.below(model.len());
+            t.swap(a, b);
+            model.swap(a, b);
+            assert_eq!(t.len(), model.len());
+        }
+        for (i, v) in model.iter().enumerate() {
+            assert_eq!(t.get(i), Some(v));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "swap indices (are 1 and 2) should be < len (is 2)")]
+    fn test_swap_out_of_bounds_tree_array() {
+        let mut t = TreeArray::new();
+        t.push(0);
+        t.push(1);
+        t.swap(1, 2);
+    }
+
+    #[test]
+    fn test_swap_remove_tree_array() {
+        let mut rng = Rng(0xabad_1dea);
+        let mut t = TreeArray::new();
+        let mut model = Vec::new();
+        for i in 0..500 {
+            t.push(i);
+            model.push(i);
+        }
+        assert_eq!(t.swap_remove(500), None);
+        while !model.is_empty() {
+            let index = rng.below(model.len());
+            assert_eq!(t.swap_remove(index), Some(model.swap_remove(index)));
+            assert_eq!(t.len(), model.len());
+            if let Some(v) = model.get(index) {
+                assert_eq!(t.get(index), Some(v));
+            }
+        }
+        assert!(t.is_empty());
+    }
+
+    #[test]
+    fn test_try_insert_tree_array() {
+        let mut t = TreeArray::new();
+        assert_eq!(t.try_insert(0, 'b'), Ok(()));
+        assert_eq!(t.try_insert(0, 'a'), Ok(()));
+        assert_eq!(t.try_insert(3, 'd'), Err(Error::OutOfBounds { index: 3, len: 2 }));
+        assert_eq!(t.try_insert(2, 'c'), Ok(()));
+        assert_eq!(t.len(), 3);
+        assert_eq!(t.get(2), Some(&'c'));
+        assert_eq!(Error::OutOfBounds { index: 3, len: 2 }.to_string(),
+                   "index 3 is out of bounds for length 2");
+    }
+
+    #[test]
+    #[should_panic(expected = "insertion index (is 2) should be <= len (is 1)")]
+    fn test_insert_out_of_bounds_tree_array() {
+        let mut t = TreeArray::new();
+        t.push(0);
+        t.insert(2, 2);
+    }
+
+    #[test]
+    fn test_pop_if_remove_if_tree_array() {
+        let mut t = TreeArray::new();
+        assert_eq!(t.pop_if(|_| true), None);
+        for i in 0..10 {
+            t.push(i);
+        }
+        assert_eq!(t.pop_if(|v| *v % 2 == 0), None);
+        assert_eq!(t.pop_if(|v| *v % 2 == 1), Some(9));
+        assert_eq!(t.remove_if(3, |v| *v > 5), None);
+        assert_eq!(t.remove_if(3, |v| *v == 3), Some(3));
+        assert_eq!(t.remove_if(8, |_| true), None);
+        assert_eq!(t.remove_if(0, |v| { *v = 10; false }), None);
+        assert_eq!(t.len(), 8);
+        assert_eq!(t.get(0), Some(&10));
+        assert_eq!(t.get(3), Some(&4));
+    }
+
+    #[test]
+    fn test_get_many_mut_tree_array() {
+        let mut rng = Rng(0x7777_1234);
+        let mut t = TreeArray::new();
+        for i in 0..200 {
+            let index = rng.below(t.len() + 1);
+            t.insert(index, i);
+        }
+        let before: Vec<i32> = (0..200).map(|i| *t.get(i).unwrap()).collect();
+        match t.get_many_mut([150, 3, 77]) {
+            Some([a, b, c]) => {
+                assert_eq!((*a, *b, *c), (before[150], before[3], before[77]));
+                *a += 1000;
+                *b += 2000;
+                *c += 3000;
+            },
+            None => panic!("Failed to access disjoint indices"),
+        }
+        assert_eq!(t.get(150), Some(&(before[150] + 1000)));
+        assert_eq!(t.get(3), Some(&(before[3] + 2000)));
+        assert_eq!(t.get(77), Some(&(before[77] + 3000)));
+        assert!(t.get_many_mut([1, 2, 1]).is_none());
+        assert!(t.get_many_mut([1, 200]).is_none());
+        assert!(t.get_many_mut([]).is_some());
+    }
+
+    #[test]
+    fn test_resize_tree_array() {
+        let mut t = TreeArray::new();
+        t.resize(5, 'a');
+        assert_eq!(t.len(), 5);
+        t.push('b');
+        t.resize(1000, 'c');
+        assert_eq!(t.len(), 1000);
+        assert_eq!(t.get(4), Some(&'a'));
+        assert_eq!(t.get(5), Some(&'b'));
+        assert_eq!(t.get(6), Some(&'c'));
+        assert_eq!(t.get(999), Some(&'c'));
+        t.resize(3, 'd');
+        assert_eq!(t.len(), 3);
+        assert_eq!(t.last(), Some(&'a'));
+
+        let mut n = TreeArray::new();
+        let mut next = 0;
+        n.resize_with(100, || { next += 1; next });
+        for i in 0..100 {
+            assert_eq!(n.get(i), Some(&(i + 1)));
+        }
+        n.insert(50, 0);
+        assert_eq!(n.get(51), Some(&51));
+    }
+
+    #[test]
+    fn test_fill_tree_array() {
+        let mut rng = Rng(0xf111_f111);
+        let mut t = TreeArray::new();
+        for i in 0..300 {
+            let index = rng.below(t.len() + 1);
+            t.insert(index, i);
+        }
+        t.fill(7);
+        assert_eq!(t.len(), 300);
+        for i in 0..300 {
+            assert_eq!(t.get(i), Some(&7));
+        }
+        let mut next = 0;
+        t.fill_with(|| { next += 1; next });
+        for i in 0..300 {
+            assert_eq!(t.get(i), Some(&(i as i32 + 1)));
+        }
+        let mut empty: TreeArray<i32> = TreeArray::new();
+        empty.fill(1);
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn test_contains_position_tree_array() {
+        let mut rng = Rng(0xc0ff_ee00);
+        let mut t = TreeArray::new();
+        let mut model = Vec::new();
+        for _ in 0..300 {
+            let v = rng.below(50);
+            let index = rng.below(model.len() + 1);
+            t.insert(index, v);
+            model.insert(index, v);
+        }
+        for x in 0..60 {
+            assert_eq!(t.contains(&x), model.contains(&x));
+            assert_eq!(t.position(|&v| v == x), model.iter().position(|&v| v == x));
+            assert_eq!(t.rposition(|&v| v == x), model.iter().rposition(|&v| v == x));
+        }
+        let empty: TreeArray<usize> = TreeArray::new();
+        assert!(!empty.contains(&0));
+        assert_eq!(empty.rposition(|_| true), None);
+    }
+
+    #[test]
+    fn test_index_of_tree_array() {
+        let mut rng = Rng(0x1d1d_1d1d);
+        let mut t = TreeArray::new();
+        let mut model = Vec::new();
+        for _ in 0..300 {
+            let v = rng.below(20);
+            let index = rng.below(model.len() + 1);
+            t.insert(index, v);
+            model.insert(index, v);
+        }
+        for x in 0..25 {
+            let expected: Vec<usize> = (0..model.len()).filter(|&i| model[i] == x).collect();
+            assert_eq!(t.indices_of(|&v| v == x), expected);
+            assert_eq!(t.index_of(|&v| v == x), expected.first().cloned());
+        }
+    }
+
+    #[test]
+    fn test_iter_tree_array() {
+        let mut rng = Rng(0x17e7_a7e5);
+        let mut t = TreeArray::new();
+        let mut model = Vec::new();
+        assert_eq!(t.iter().next(), None);
+        for i in 0..1000 {
+            let index = rng.below(model.len() + 1);
+            t.insert(index, i);
+            model.insert(index, i);
+        }
+        assert_eq!(t.iter().size_hint(), (1000, Some(1000)));
+        assert!(t.iter().eq(model.iter()));
+        let mut deep = TreeArray::new();
+        for i in 0..1_000_000 {
+            deep.push(i);
+        }
+        assert_eq!(deep.iter().count(), 1_000_000);
+        assert!(deep.iter().zip(0..).all(|(&v, i)| v == i));
+    }
+
+    #[test]
+    fn test_iter_mut_tree_array() {
+        let mut rng = Rng(0x17e7_0a7e);
+        let mut t = TreeArray::new();
+        let mut model = Vec::new();
+        for i in 0..1000 {
+            let index = rng.below(model.len() + 1);
+            t.insert(index, i);
+            model.insert(index, i);
+        }
+        assert_eq!(t.iter_mut().size_hint(), (1000, Some(1000)));
+        for v in t.iter_mut() {
+            *v = *v * 3 + 1;
+        }
+        for v in model.iter_mut() {
+            *v = *v * 3 + 1;
+        }
+        assert!(t.iter().eq(model.iter()));
+        let mut refs: Vec<&mut i32> = t.iter_mut().collect();
+        *refs[0] = -1;
+        *refs[999] = -2;
+        assert_eq!(t.first(), Some(&-1));
+        assert_eq!(t.last(), Some(&-2));
+    }
+
+    #[test]
+    fn test_into_iter_tree_array() {
+        let mut t = TreeArray::new();
+        for i in 0..100 {
+            t.push_front(i);
+        }
+        for v in &mut t {
+            *v += 1;
+        }
+        let mut expected = 100;
+        for v in &t {
+            assert_eq!(*v, expected);
+            expected -= 1;
+        }
+        let owned: Vec<i32> = t.into_iter().collect();
+        assert_eq!(owned, (1..101).rev().collect::<Vec<i32>>());
+
+        let mut deep = TreeArray::new();
+        for i in 0..1_000_000 {
+            deep.push(i);
+        }
+        let mut iter = deep.into_iter();
+        assert_eq!(iter.size_hint(), (1_000_000, Some(1_000_000)));
+        assert_eq!(iter.next(), Some(0));
+        // Dropping a partially consumed iterator must not recurse down the tree.
+        drop(iter);
+    }
+
+    #[test]
+    fn test_double_ended_iter_tree_array() {
+        let mut rng = Rng(0xdeed_0e0d);
+        let mut t = TreeArray::new();
+        let mut model = Vec::new();
+        for i in 0..500 {
+            let index = rng.below(model.len() + 1);
+            t.insert(index, i);
+            model.insert(index, i);
+        }
+        assert!(t.iter().rev().eq(model.iter().rev()));
+        // Alternate both ends until they meet.
+        let mut iter = t.iter();
+        let mut model_iter = model.iter();
+        for _ in 0..600 {
+            if rng.below(2) == 0 {
+                assert_eq!(iter.next(), model_iter.next());
+            } else {
+                assert_eq!(iter.next_back(), model_iter.next_back());
+            }
+        }
+        assert_eq!(iter.next(), None);
+
+        for v in t.iter_mut().rev().take(10) {
+            *v = -1;
+        }
+        for v in model.iter_mut().rev().take(10) {
+            *v = -1;
+        }
+        let mut iter = t.iter_mut();
+        let mut model_iter = model.iter_mut();
+        for _ in 0..600 {
+            if rng.below(2) == 0 {
+                assert_eq!(iter.next(), model_iter.next());
+            } else {
+                assert_eq!(iter.next_back(), model_iter.next_back());
+            }
+        }
+
+        let mut iter = t.into_iter();
+        let mut model_iter = model.into_iter();
+        for _ in 0..300 {
+            if rng.below(2) == 0 {
+                assert_eq!(iter.next(), model_iter.next());
+            } else {
+                assert_eq!(iter.next_back(), model_iter.next_back());
+            }
+        }
+    }
+
+    #[test]
+    fn test_exact_size_iter_tree_array() {
+        let mut t = TreeArray::new();
+        for i in 0..50 {
+            t.push(i);
+        }
+        let mut iter = t.iter();
+        assert_eq!(iter.len(), 50);
+        iter.next();
+        iter.next_back();
+        assert_eq!(iter.len(), 48);
+        assert_eq!(iter.by_ref().count(), 48);
+        assert_eq!(iter.len(), 0);
+        assert_eq!(iter.next(), None);
+        assert_eq!(iter.next_back(), None);
+
+        let mut iter_mut = t.iter_mut();
+        iter_mut.next();
+        assert_eq!(iter_mut.len(), 49);
+
+        let pairs: Vec<(&i32, i32)> = t.iter().zip((0..50).rev()).rev().collect();
+        assert_eq!(pairs.len(), 50);
+        assert_eq!(pairs[0], (&49, 0));
+
+        let mut into_iter = t.into_iter();
+        into_iter.next_back();
+        assert_eq!(into_iter.len(), 49);
+    }
+
+    #[test]
+    fn test_seek_nth_iter_tree_array() {
+        let mut rng = Rng(0x5ee4_0000);
+        let mut t = TreeArray::new();
+        let mut model = Vec::new();
+        for i in 0..1000 {
+            let index = rng.below(model.len() + 1);
+            t.insert(index, i);
+            model.insert(index, i);
+        }
+        let mut iter = t.iter();
+        let mut model_iter = model.iter();
+        for _ in 0..100 {
+            let n = rng.below(20);
+            if rng.below(2) == 0 {
+                assert_eq!(iter.nth(n), model_iter.nth(n));
+            } else {
+                assert_eq!(iter.nth_back(n), model_iter.nth_back(n));
+            }
+            assert_eq!(iter.len(), model_iter.len());
+        }
+        let mut iter = t.iter();
+        assert_eq!(iter.advance_by(990), Ok(()));
+        assert_eq!(iter.len(), 10);
+        assert_eq!(iter.advance_by(15), Err(NonZeroUsize::new(5).unwrap()));
+        assert_eq!(iter.next(), None);
+        for _ in 0..100 {
+            let index = rng.below(1001);
+            iter.seek(index);
+            assert_eq!(iter.len(), 1000 - index);
+            assert_eq!(iter.next(), model.get(index));
+            assert_eq!(iter.next_back(), model[index..].last().filter(|_| index < 999));
+        }
+
+        let mut iter_mut = t.iter_mut();
+        assert_eq!(iter_mut.advance_back_by(500), Ok(()));
+        *iter_mut.nth(499).unwrap() = -1;
+        assert_eq!(iter_mut.next(), None);
+        assert_eq!(t.get(499), Some(&-1));
+    }
+
+    #[test]
+    fn test_iter_range_tree_array() {
+        let mut rng = Rng(0x4a46_e000);
+        let mut t = TreeArray::new();
+        let mut model = Vec::new();
+        for i in 0..300 {
+            let index = rng.below(model.len() + 1);
+            t.insert(index, i);
+            model.insert(index, i);
+        }
+        for _ in 0..200 {
+            let a = rng.below(301);
+            let b = a + rng.below(301 - a);
+            assert!(t.iter_range(a..b).eq(model[a..b].iter()));
+            assert_eq!(t.iter_range(a..b).len(), b - a);
+            assert!(t.iter_range(a..b).rev().eq(model[a..b].iter().rev()));
+        }
+        assert!(t.iter_range(..).eq(model.iter()));
+        assert!(t.iter_range(..10).eq(model[..10].iter()));
+        assert!(t.iter_range(290..).eq(model[290..].iter()));
+        assert!(t.iter_range(5..=9).eq(model[5..=9].iter()));
+        assert_eq!(t.iter_range(300..).next(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "range end (is 4) should be <= len (is 3)")]
+    fn test_iter_range_out_of_bounds_tree_array() {
+        let mut t = TreeArray::new();
+        t.resize(3, 0);
+        t.iter_range(1..4);
+    }
+
+    #[test]
+    fn test_cursor_tree_array() {
+        let mut rng = Rng(0xc505_0e00);
+        let mut t = TreeArray::new();
+        let mut model = Vec::new();
+        for i in 0..300 {
+            let index = rng.below(model.len() + 1);
+            t.insert(index, i);
+            model.insert(index, i);
+        }
+        let mut cursor = t.cursor_at(0);
+        for (i, v) in model.iter().enumerate() {
+            assert_eq!(cursor.index(), Some(i));
+            assert_eq!(cursor.current(), Some(v));
+            assert_eq!(cursor.peek_next(), model.get(i + 1));
+            assert_eq!(cursor.peek_prev(), if i == 0 { None } else { model.get(i - 1) });
+            cursor.move_next();
+        }
+        assert_eq!(cursor.index(), None);
+        assert_eq!(cursor.current(), None);
+        assert_eq!(cursor.peek_next(), model.first());
+        assert_eq!(cursor.peek_prev(), model.last());
+        for (i, v) in model.iter().enumerate().rev() {
+            cursor.move_prev();
+            assert_eq!(cursor.index(), Some(i));
+            assert_eq!(cursor.current(), Some(v));
+        }
+        cursor.move_prev();
+        assert_eq!(cursor.index(), None);
+        cursor.move_next();
+        assert_eq!(cursor.index(), Some(0));
+
+        // Random walk.
+        let mut cursor = t.cursor_at(150);
+        let mut index = 150;
+        for _ in 0..1000 {
+            if rng.below(2) == 0 {
+                cursor.move_next();
+                index = if index == 300 { 0 } else { index + 1 };
+            } else {
+                cursor.move_prev();
+                index = if index == 0 { 300 } else { index - 1 };
+            }
+            assert_eq!(cursor.current(), model.get(index));
+        }
+
+        let empty: TreeArray<i32> = TreeArray::new();
+        let mut cursor = empty.cursor_at(0);
+        cursor.move_next();
+        assert_eq!(cursor.current(), None);
+        cursor.move_prev();
+        assert_eq!(cursor.current(), None);
+    }
+
+    #[test]
+    fn test_cursor_mut_tree_array() {
+        let mut t = TreeArray::new();
+        for i in 0..5 {
+            t.push(i * 10);
+        }
+        {
+            let mut cursor = t.cursor_mut_at(2);
+            assert_eq!(cursor.current(), Some(&mut 20));
+            cursor.insert_before(15);
+            cursor.insert_after(25);
+            assert_eq!(cursor.index(), Some(3));
+            assert_eq!(cursor.current(), Some(&mut 20));
+            assert_eq!(cursor.peek_prev(), Some(&mut 15));
+            assert_eq!(cursor.peek_next(), Some(&mut 25));
+            assert_eq!(cursor.remove_current(), Some(20));
+            assert_eq!(cursor.current(), Some(&mut 25));
+            cursor.move_next();
+            cursor.move_next();
+            *cursor.current().unwrap() += 1;
+            assert_eq!(cursor.as_cursor().current(), Some(&41));
+            assert_eq!(cursor.remove_current(), Some(41));
+            assert_eq!(cursor.index(), None);
+            assert_eq!(cursor.remove_current(), None);
+            cursor.insert_before(50);
+            cursor.insert_after(-10);
+            assert_eq!(cursor.index(), None);
+            cursor.move_next();
+            assert_eq!(cursor.current(), Some(&mut -10));
+        }
+        let values: Vec<i32> = t.iter().cloned().collect();
+        assert_eq!(values, vec![-10, 0, 10, 15, 25, 30, 50]);
+
+        // An editor-like random session against a Vec model.
+        let mut rng = Rng(0xed17_0a11);
+        let mut model = values;
+        let mut cursor = t.cursor_mut_at(0);
+        let mut index = 0;
+        for i in 0..2000 {
+            match rng.below(5) {
+                0 => { cursor.insert_before(i); model.insert(index, i); index += 1; },
+                1 => {
+                    cursor.insert_after(i);
+                    if index == model.len() { model.insert(0, i); index += 1; }
+                    else { model.insert(index + 1, i); }
+                },
+                2 => {
+                    let removed = if index < model.len() { Some(model.remove(index)) } else { None };
+                    assert_eq!(cursor.remove_current(), removed);
+                },
+                3 => { cursor.move_next(); index = if index == model.len() { 0 } else { index + 1 }; },
+                _ => { cursor.move_prev(); index = if index == 0 { model.len() } else { index - 1 }; },
+            }
+            assert_eq!(cursor.current().map(|v| *v), model.get(index).cloned());
+        }
+    }
+
+    #[test]
+    fn test_drain_tree_array() {
+        let mut rng = Rng(0xd4a1_0000);
+        let mut t = TreeArray::new();
+        let mut model = Vec::new();
+        for i in 0..1000 {
+            t.push(i);
+            model.push(i);
+        }
+        while !model.is_empty() {
+            let a = rng.below(model.len() + 1);
+            let b = (a + rng.below(50)).min(model.len());
+            let drained: Vec<i32> = t.drain(a..b).collect();
+            assert_eq!(drained, model.drain(a..b).collect::<Vec<i32>>());
+            assert_eq!(t.len(), model.len());
+            if rng.below(10) == 0 {
+                // Dropping the iterator without consuming it still removes the range.
+                let end = model.len().min(3);
+                t.drain(..end);
+                model.drain(..end);
+            }
+        }
+        assert!(t.is_empty());
+        t.resize(10, 1);
+        let mut drain = t.drain(2..8);
+        assert_eq!(drain.len(), 6);
+        assert_eq!(drain.next_back(), Some(1));
+        drop(drain);
+        assert_eq!(t.len(), 4);
+    }
+
+    #[test]
+    fn test_extract_if_tree_array() {
+        let mut rng = Rng(0xe874_ac70);
+        let mut t = TreeArray::new();
+        let mut model = Vec::new();
+        for i in 0..1000 {
+            let index = rng.below(model.len() + 1);
+            t.insert(index, i);
+            model.insert(index, i);
+        }
+        let extracted: Vec<i32> = t.extract_if(|v| *v % 3 == 0).collect();
+        let expected: Vec<i32> = model.iter().cloned().filter(|v| v % 3 == 0).collect();
+        model.retain(|v| v % 3 != 0);
+        assert_eq!(extracted, expected);
+        assert_eq!(t.len(), model.len());
+        assert!(t.iter().eq(model.iter()));
+        for (i, v) in model.iter().enumerate() {
+            assert_eq!(t.get(i), Some(v));
+        }
+
+        // Stopping early keeps what was not reached.
+        let first = t.extract_if(|v| *v % 2 == 0).next();
+        let position = model.iter().position(|v| v % 2 == 0).unwrap();
+        assert_eq!(first, Some(model.remove(position)));
+        assert!(t.iter().eq(model.iter()));
+        t.insert(5, -1);
+        model.insert(5, -1);
+        assert!(t.iter().eq(model.iter()));
+    }
+
+    #[test]
+    fn test_retain_tree_array() {
+        let mut rng = Rng(0x4e7a_1000);
+        let mut t = TreeArray::new();
+        let mut model = Vec::new();
+        for _ in 0..1000 {
+            let v = rng.below(100);
+            let index = rng.below(model.len() + 1);
+            t.insert(index, v);
+            model.insert(index, v);
+        }
+        t.retain(|&v| v < 50);
+        model.retain(|&v| v < 50);
+        assert!(t.iter().eq(model.iter()));
+        t.retain_mut(|v| { *v *= 2; *v % 3 != 0 });
+        model.retain_mut(|v| { *v *= 2; *v % 3 != 0 });
+        assert!(t.iter().eq(model.iter()));
+        for (i, v) in model.iter().enumerate() {
+            assert_eq!(t.get(i), Some(v));
+        }
+        t.retain(|_| false);
+        assert!(t.is_empty());
+    }
+
+    #[test]
+    fn test_from_iter_tree_array() {
+        let t: TreeArray<i32> = (0..1_000_000).collect();
+        assert_eq!(t.len(), 1_000_000);
+        assert_eq!(t.get(0), Some(&0));
+        assert_eq!(t.get(765_432), Some(&765_432));
+        assert!(t.iter().zip(0..).all(|(&v, i)| v == i));
+        let mut t: TreeArray<char> = "hello".chars().collect();
+        t.insert(5, '!');
+        assert_eq!(t.iter().collect::<String>(), "hello!");
+        let empty: TreeArray<i32> = std::iter::empty().collect();
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn test_extend_tree_array() {
+        let mut t: TreeArray<i32> = (0..10).collect();
+        t.extend(10..1000);
+        t.extend(&[1000, 1001]);
+        t.extend(Vec::<i32>::new());
+        assert_eq!(t.len(), 1002);
+        assert!(t.iter().zip(0..).all(|(&v, i)| v == i));
+        assert_eq!(t.get(1001), Some(&1001));
+        t.insert(1002, 1002);
+        assert_eq!(t.last(), Some(&1002));
+        let mut empty = TreeArray::new();
+        empty.extend(vec!["a", "b"]);
+        assert_eq!(empty.get(1), Some(&"b"));
+    }
+
+    #[test]
+    fn test_index_tree_array() {
+        let mut t: TreeArray<i32> = (0..100).collect();
+        t[42] = -42;
+        t[0] += 1;
+        let shared = &t;
+        assert_eq!(shared[42], -42);
+        assert_eq!(shared[0], 1);
+        assert_eq!(shared[99], 99);
+    }
+
+    #[test]
+    #[should_panic(expected = "index out of bounds: the len is 3 but the index is 3")]
+    fn test_index_out_of_bounds_tree_array() {
+        let t: TreeArray<i32> = (0..3).collect();
+        let _ = t[3];
+    }
+
+    #[test]
+    #[should_panic(expected = "index out of bounds: the len is 3 but the index is 5")]
+    fn test_index_mut_out_of_bounds_tree_array() {
+        let mut t: TreeArray<i32> = (0..3).collect();
+        t[5] = 1;
+    }
+
+    #[test]
+    fn test_slice_tree_array() {
+        let t: TreeArray<i32> = (0..100).collect();
+        let slice = t.slice(10..20);
+        assert_eq!(slice.len(), 10);
+        assert_eq!(slice[0], 10);
+        assert_eq!(slice.get(10), None);
+        assert_eq!(slice.first(), Some(&10));
+        assert_eq!(slice.last(), Some(&19));
+        assert!(slice.iter().eq((10..20).collect::<Vec<i32>>().iter()));
+        let sub = slice.slice(2..=4);
+        assert_eq!(sub, [12, 13, 14][..]);
+        assert_eq!(sub, &[12, 13, 14][..]);
+        assert_eq!(sub, t.slice(12..15));
+        assert!(sub != t.slice(12..16));
+        assert_eq!(sub.into_iter().rev().cloned().collect::<Vec<i32>>(), vec![14, 13, 12]);
+        assert!(t.slice(50..50).is_empty());
+        assert_eq!(t.slice(..).len(), 100);
+    }
+
+    #[test]
+    #[should_panic(expected = "range end (is 11) should be <= len (is 10)")]
+    fn test_slice_out_of_bounds_tree_array() {
+        let t: TreeArray<i32> = (0..100).collect();
+        t.slice(10..20).slice(5..11);
+    }
+
+    #[test]
+    fn test_clone_default_tree_array() {
+        let empty: TreeArray<i32> = TreeArray::default();
+        assert!(empty.clone().is_empty());
+        let mut rng = Rng(0xc10e_0000);
+        let mut t = TreeArray::new();
+        for i in 0..500 {
+            let index = rng.below(t.len() + 1);
+            t.insert(index, i.to_string());
+        }
+        let mut copy = t.clone();
+        assert_eq!(format!("{:#?}", copy), format!("{:#?}", t));
+        copy.set(0, String::from("changed"));
+        assert!(t.get(0) != copy.get(0));
+        assert!(t.iter().skip(1).eq(copy.iter().skip(1)));
+
+        // A degenerate tree, a million nodes deep.
+        let mut deep = TreeArray::new();
+        for i in 0..1_000_000 {
+            deep.push(i);
+        }
+        let copy = deep.clone();
+        assert_eq!(copy.len(), 1_000_000);
+        assert_eq!(copy.get(999_999), Some(&999_999));
+    }
+
+    #[test]
+    fn test_eq_tree_array() {
+        let mut a = TreeArray::new();
+        for i in (0..100).rev() {
+            a.push_front(i);
+        }
+        let b: TreeArray<i32> = (0..100).collect();
+        // Same contents, different shapes.
+        assert!(format!("{:#?}", a) != format!("{:#?}", b));
+        assert_eq!(a, b);
+        let expected: Vec<i32> = (0..100).collect();
+        assert_eq!(a, expected);
+        assert_eq!(a, expected[..]);
+        assert_eq!(a, &expected[..]);
+        a.set(50, -1);
+        assert!(a != b);
+        a.pop();
+        assert!(a != expected);
+        let small: TreeArray<i32> = (1..4).collect();
+        assert_eq!(small, vec![1, 2, 3]);
+        assert_eq!(small, [1, 2, 3]);
+        assert!(small != [1, 2]);
+        let strings: TreeArray<String> = vec![String::from("a")].into_iter().collect();
+        assert_eq!(strings, ["a"]);
+    }
+
+    #[test]
+    fn test_ord_tree_array() {
+        let mut rng = Rng(0x04d0_0000);
+        let mut arrays = Vec::new();
+        for _ in 0..100 {
+            let len = rng.below(5);
+            let values: Vec<usize> = (0..len).map(|_| rng.below(3)).collect();
+            arrays.push(values);
+        }
+        let mut trees: Vec<TreeArray<usize>> =
+            arrays.iter().map(|a| a.iter().cloned().collect()).collect();
+        for (a, ta) in arrays.iter().zip(&trees) {
+            for (b, tb) in arrays.iter().zip(&trees) {
+                assert_eq!(ta.cmp(tb), a.cmp(b));
+                assert_eq!(ta.partial_cmp(tb), a.partial_cmp(b));
+            }
+        }
+        arrays.sort();
+        trees.sort();
+        for (a, t) in arrays.iter().zip(&trees) {
+            assert_eq!(t, a);
+        }
+        let mut map = std::collections::BTreeMap::new();
+        map.insert(trees[0].clone(), 0);
+        assert_eq!(map.get(&trees[0]), Some(&0));
+        let nan: TreeArray<f64> = vec![f64::NAN].into_iter().collect();
+        assert_eq!(nan.partial_cmp(&nan), None);
+    }
+
+    #[test]
+    fn test_hash_tree_array() {
+        use std::collections::hash_map::DefaultHasher;
+        use std::collections::HashSet;
+        use std::hash::{Hash, Hasher};
+        fn hash_of<T: Hash>(t: &T) -> u64 {
+            let mut hasher = DefaultHasher::new();
+            t.hash(&mut hasher);
+            hasher.finish()
+        }
+        let mut a = TreeArray::new();
+        for i in (0..100).rev() {
+            a.push_front(i);
+        }
+        let b: TreeArray<i32> = (0..100).collect();
+        assert_eq!(hash_of(&a), hash_of(&b));
+        a.set(3, 0);
+        assert!(hash_of(&a) != hash_of(&b));
+        let mut set = HashSet::new();
+        set.insert(b.clone());
+        assert!(set.contains(&(0..100).collect::<TreeArray<i32>>()));
+        assert!(!set.contains(&a));
+        // The length is part of the hash, so nested arrays do not collide.
+        let nested1: TreeArray<TreeArray<i32>> =
+            vec![TreeArray::new(), (0..1).collect()].into_iter().collect();
+        let nested2: TreeArray<TreeArray<i32>> =
+            vec![(0..1).collect(), TreeArray::new()].into_iter().collect();
+        assert!(hash_of(&nested1) != hash_of(&nested2));
+    }
+
+    #[test]
+    fn test_vec_conversions_tree_array() {
+        let vec: Vec<i32> = (0..1000).collect();
+        let mut t = TreeArray::from(vec.clone());
+        assert_eq!(t, vec);
+        assert_eq!(t.to_vec(), vec);
+        t.insert(500, -1);
+        let mut expected = vec.clone();
+        expected.insert(500, -1);
+        let back: Vec<i32> = t.into();
+        assert_eq!(back, expected);
+        let t = TreeArray::from(&vec[10..20]);
+        assert_eq!(t.len(), 10);
+        assert_eq!(t.into_vec(), &vec[10..20]);
+        let empty: Vec<i32> = TreeArray::from(Vec::new()).into_vec();
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn test_deque_list_conversions_tree_array() {
+        let deque: std::collections::VecDeque<i32> = (0..100).collect();
+        let mut t = TreeArray::from(deque.clone());
+        assert_eq!(t, (0..100).collect::<Vec<i32>>());
+        t.push_front(-1);
+        let back: std::collections::VecDeque<i32> = t.into();
+        assert_eq!(back.len(), 101);
+        assert_eq!(back.front(), Some(&-1));
+        assert!(back.iter().skip(1).eq(deque.iter()));
+        let list: LinkedList<i32> = (0..50).collect();
+        let t = TreeArray::from(list);
+        assert_eq!(t, (0..50).collect::<Vec<i32>>());
+    }
+
+    #[test]
+    fn test_tree_array_macro() {
+        let empty: TreeArray<i32> = tree_array![];
+        assert!(empty.is_empty());
+        let t = tree_array![1, 2, 3];
+        assert_eq!(t, [1, 2, 3]);
+        let t = tree_array!["a", "b",];
+        assert_eq!(t, ["a", "b"]);
+        let t = tree_array![String::from("x"); 4];
+        assert_eq!(t.len(), 4);
+        assert!(t.iter().all(|s| s == "x"));
+        let t: TreeArray<i32> = tree_array![0; 0];
+        assert!(t.is_empty());
+    }
+
+    #[test]
+    fn test_sum_tree_array() {
+        let parts = vec![tree_array![1, 2], TreeArray::new(), tree_array![3], (4..100).collect()];
+        let total: TreeArray<i32> = parts.into_iter().sum();
+        assert_eq!(total, (1..100).collect::<Vec<i32>>());
+        let empty: TreeArray<i32> = Vec::new().into_iter().sum();
+        assert!(empty.is_empty());
+        // Functional pipelines end in a tree array.
+        let lines: TreeArray<String> = "a b c".split(' ').map(String::from).collect();
+        let doubled: TreeArray<String> = lines.iter().map(|l| l.repeat(2)).collect();
+        assert_eq!(doubled, ["aa", "bb", "cc"]);
+    }
+
+    #[test]
+    fn test_split_off_tree_array() {
+        let mut rng = Rng(0x5b11_7000);
+        for _ in 0..50 {
+            let len = rng.below(200);
+            let mut t: TreeArray<usize> = (0..len).collect();
+            for _ in 0..rng.below(20) {
+                t.get_mut(rng.below(len.max(1)));
+            }
+            let at = rng.below(len + 1);
+            let tail = t.split_off(at);
+            assert_eq!(t.len(), at);
+            assert_eq!(tail.len(), len - at);
+            assert_eq!(t, (0..at).collect::<Vec<usize>>());
+            assert_eq!(tail, (at..len).collect::<Vec<usize>>());
+            for i in 0..at {
+                assert_eq!(t.get(i), Some(&i));
+            }
+            for i in at..len {
+                assert_eq!(tail.get(i - at), Some(&i));
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "split index (is 4) should be <= len (is 3)")]
+    fn test_split_off_out_of_bounds_tree_array() {
+        let mut t = tree_array![1, 2, 3];
+        t.split_off(4);
+    }
+
+    #[test]
+    fn test_append_join_tree_array() {
+        let mut rng = Rng(0xa99e_0d00);
+        let mut t = TreeArray::new();
+        let mut model = Vec::new();
+        for i in 0..100 {
+            let len = rng.below(30);
+            let mut other: TreeArray<usize> = (0..len).map(|j| i * 100 + j).collect();
+            model.extend((0..len).map(|j| i * 100 + j));
+            if len > 0 {
+                other.get_mut(rng.below(len));
+            }
+            t.append(&mut other);
+            assert!(other.is_empty());
+            assert_eq!(t.len(), model.len());
+        }
+        assert_eq!(t, model);
+        for (i, v) in model.iter().enumerate() {
+            assert_eq!(t.get(i), Some(v));
+        }
+        let joined = tree_array![1, 2].join(tree_array![3]).join(TreeArray::new());
+        assert_eq!(joined, [1, 2, 3]);
+        let joined = TreeArray::new().join(tree_array![1]);
+        assert_eq!(joined, [1]);
+    }
+
+    #[test]
+    fn test_insert_all_tree_array() {
+        let mut rng = Rng(0x1a5e_a111);
+        let mut t = TreeArray::new();
+        let mut model = Vec::new();
+        for i in 0..200 {
+            let len = rng.below(10);
+            let other: TreeArray<usize> = (0..len).map(|j| i * 100 + j).collect();
+            let index = rng.below(model.len() + 1);
+            t.insert_all(index, other);
+            let tail = model.split_off(index);
+            model.extend((0..len).map(|j| i * 100 + j));
+            model.extend(tail);
+            assert_eq!(t.len(), model.len());
+        }
+        assert_eq!(t, model);
+        for (i, v) in model.iter().enumerate() {
+            assert_eq!(t.get(i), Some(v));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "insertion index (is 2) should be <= len (is 1)")]
+    fn test_insert_all_out_of_bounds_tree_array() {
+        let mut t = tree_array![1];
+        t.insert_all(2, tree_array![2]);
+    }
+
+    #[test]
+    fn test_splice_tree_array() {
+        let mut rng = Rng(0x5971_ce00);
+        let mut t = TreeArray::new();
+        let mut model = Vec::new();
+        for i in 0..300 {
+            let a = rng.below(model.len() + 1);
+            let b = (a + rng.below(10)).min(model.len());
+            let new: Vec<usize> = (0..rng.below(12)).map(|j| i * 100 + j).collect();
+            let removed: Vec<usize> = t.splice(a..b, new.clone()).collect();
+            assert_eq!(removed, model.splice(a..b, new).collect::<Vec<usize>>());
+            assert_eq!(t.len(), model.len());
+        }
+        assert_eq!(t, model);
+        let mut t = tree_array![1, 2, 3, 4];
+        drop(t.splice(1..3, vec![7, 8, 9]));
+        assert_eq!(t, [1, 7, 8, 9, 4]);
+    }
+
+    #[test]
+    fn test_take_remove_range_tree_array() {
+        let mut rng = Rng(0x7a4e_0000);
+        let mut t: TreeArray<usize> = (0..1000).collect();
+        let mut model: Vec<usize> = (0..1000).collect();
+        while !model.is_empty() {
+            let a = rng.below(model.len() + 1);
+            let b = (a + rng.below(40)).min(model.len());
+            if rng.below(2) == 0 {
+                let mut taken = t.take(a..b);
+                let expected: Vec<usize> = model.drain(a..b).collect();
+                assert_eq!(taken, expected);
+                // The removed values stay editable.
+                taken.push(0);
+                assert_eq!(taken.len(), expected.len() + 1);
+            } else {
+                t.remove_range(a..b);
+                model.drain(a..b);
+            }
+            assert_eq!(t.len(), model.len());
+        }
+        assert!(t.is_empty());
+        let mut t = tree_array![1, 2, 3];
+        assert_eq!(t.take(..), [1, 2, 3]);
+        assert!(t.is_empty());
+    }
+
+    #[test]
+    fn test_move_range_tree_array() {
+        let mut t: TreeArray<i32> = (0..10).collect();
+        t.move_range(2..4, 8);
+        assert_eq!(t, [0, 1, 4, 5, 6, 7, 2, 3, 8, 9]);
+        t.move_range(6..8, 2);
+        assert_eq!(t, (0..10).collect::<Vec<i32>>());
+        t.move_range(0..3, 10);
+        assert_eq!(t, [3, 4, 5, 6, 7, 8, 9, 0, 1, 2]);
+        t.move_range(7.., 0);
+        assert_eq!(t, (0..10).collect::<Vec<i32>>());
+        t.move_range(4..6, 4);
+        t.move_range(4..6, 6);
+        assert_eq!(t, (0..10).collect::<Vec<i32>>());
+
+        let mut rng = Rng(0x307e_0000);
+        let mut model: Vec<i32> = (0..200).collect();
+        let mut t: TreeArray<i32> = (0..200).collect();
+        for _ in 0..300 {
+            let a = rng.below(201);
+            let b = a + rng.below(201 - a);
+            let mut dest = rng.below(201);
+            if dest > a && dest < b {
+                dest = b;
+            }
+            t.move_range(a..b, dest);
+            let block: Vec<i32> = model.drain(a..b).collect();
+            let at = if dest >= b { dest - (b - a) } else { dest };
+            let tail = model.split_off(at);
+            model.extend(block);
+            model.extend(tail);
+            assert_eq!(t, model);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "destination (is 3) should not be within the range 2..5")]
+    fn test_move_range_into_itself_tree_array() {
+        let mut t: TreeArray<i32> = (0..10).collect();
+        t.move_range(2..5, 3);
+    }
+
+    #[test]
+    fn test_insert_slice_tree_array() {
+        let mut t = tree_array![0, 10];
+        t.insert_slice(1, &[1, 2, 3]);
+        t.extend_from_slice(&[11, 12]);
+        t.insert_slice(0, &[]);
+        assert_eq!(t, [0, 1, 2, 3, 10, 11, 12]);
+        let block: Vec<i32> = (100..10_100).collect();
+        t.insert_slice(4, &block);
+        assert_eq!(t.len(), 10_007);
+        assert_eq!(t[3], 3);
+        assert_eq!(t[4], 100);
+        assert_eq!(t[10_003], 10_099);
+        assert_eq!(t[10_004], 10);
+    }
+
+    #[test]
+    fn test_extend_from_within_tree_array() {
+        let mut rng = Rng(0xe87e_0d00);
+        let mut t: TreeArray<usize> = (0..10).collect();
+        let mut model: Vec<usize> = (0..10).collect();
+        for _ in 0..100 {
+            let a = rng.below(model.len() + 1);
+            let b = (a + rng.below(10)).min(model.len());
+            t.extend_from_within(a..b);
+            model.extend_from_within(a..b);
+            assert_eq!(t, model);
+        }
+        let mut t = tree_array![String::from("a"), String::from("b")];
+        t.extend_from_within(..);
+        t[2].push('!');
+        assert_eq!(t, ["a", "b", "a!", "b"]);
+    }
+
+    #[test]
+    fn test_rotate_tree_array() {
+        let mut rng = Rng(0x4074_7e00);
+        let mut t: TreeArray<usize> = (0..100).collect();
+        let mut model: Vec<usize> = (0..100).collect();
+        for _ in 0..200 {
+            let k = rng.below(101);
+            if rng.below(2) == 0 {
+                t.rotate_left(k);
+                model.rotate_left(k);
+            } else {
+                t.rotate_right(k);
+                model.rotate_right(k);
+            }
+            assert_eq!(t, model);
+        }
+        let mut empty: TreeArray<i32> = TreeArray::new();
+        empty.rotate_left(0);
+        empty.rotate_right(0);
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "rotation (is 4) should be <= len (is 3)")]
+    fn test_rotate_out_of_bounds_tree_array() {
+        let mut t = tree_array![1, 2, 3];
+        t.rotate_right(4);
+    }
+
+    #[test]
+    fn test_reverse_tree_array() {
+        let mut rng = Rng(0x4e7e_45e0);
+        let mut t = TreeArray::new();
+        let mut model = Vec::new();
+        for i in 0..500 {
+            let index = rng.below(model.len() + 1);
+            t.insert(index, i);
+            model.insert(index, i);
+        }
+        t.reverse();
+        model.reverse();
+        assert_eq!(t, model);
+        // The mirrored tree must splay as well as the original one.
+        for i in 0..2000 {
+            let index = rng.below(model.len() + 1);
+            match rng.below(4) {
+                0 => { t.insert(index, i); model.insert(index, i); },
+                1 if index < model.len() => assert_eq!(t.remove(index), Some(model.remove(index))),
+                2 => { t.reverse(); model.reverse(); },
+                _ => assert_eq!(t.splay_get(index), model.get(index)),
+            }
+            assert_eq!(t.len(), model.len());
+        }
+        assert_eq!(t, model);
+        let mut deep = TreeArray::new();
+        for i in 0..1_000_000 {
+            deep.push(i);
+        }
+        deep.reverse();
+        assert_eq!(deep.first(), Some(&999_999));
+        assert_eq!(deep.get(1), Some(&999_998));
+    }
+
+    #[test]
+    fn test_reverse_range_tree_array() {
+        let mut rng = Rng(0x2e7e_45e1);
+        let mut t: TreeArray<usize> = (0..300).collect();
+        let mut model: Vec<usize> = (0..300).collect();
+        for i in 0..3000 {
+            let a = rng.below(model.len() + 1);
+            let b = rng.below(model.len() + 1);
+            let (start, end) = if a < b { (a, b) } else { (b, a) };
+            match rng.below(6) {
+                0 => { t.reverse_range(start..end); model[start..end].reverse(); },
+                1 => { t.insert(start, i); model.insert(start, i); },
+                2 if start < model.len() => assert_eq!(t.remove(start), Some(model.remove(start))),
+                3 => {
+                    // Readers behind a shared reference see pending reversals without pushing
+                    // them down.
+                    assert_eq!(t.get(start), model.get(start));
+                    assert_eq!(t.iter_range(start..end).collect::<Vec<_>>(),
+                               model[start..end].iter().collect::<Vec<_>>());
+                    assert_eq!(t.iter().rev().nth(start), model.iter().rev().nth(start));
+                    let x = model.get(start);
+                    assert_eq!(t.position(|v| Some(v) == x), model.iter().position(|v| Some(v) == x));
+                    assert_eq!(t.rposition(|v| Some(v) == x), model.iter().rposition(|v| Some(v) == x));
+                    assert_eq!((t.first(), t.last()), (model.first(), model.last()));
+                },
+                4 => {
+                    let mut cursor = t.cursor_at(start);
+                    for j in start..end {
+                        assert_eq!(cursor.current(), model.get(j));
+                        assert_eq!(cursor.peek_prev(), model.get(j.wrapping_sub(1)));
+                        cursor.move_next();
+                    }
+                    for j in (start..end).rev() {
+                        cursor.move_prev();
+                        assert_eq!(cursor.current(), model.get(j));
+                        assert_eq!(cursor.peek_next(), model.get(j + 1));
+                    }
+                },
+                _ => {
+                    for v in t.iter_mut().skip(start).take(end - start) {
+                        *v += 1;
+                    }
+                    model[start..end].iter_mut().for_each(|v| *v += 1);
+                },
+            }
+            assert_eq!(t.len(), model.len());
+        }
+        assert_eq!(t, model);
+        assert_eq!(t.clone(), model);
+        assert_eq!(t.clone().into_iter().rev().collect::<Vec<_>>(),
+                   model.iter().rev().cloned().collect::<Vec<_>>());
+        t.reverse_range(10..20);
+        model[10..20].reverse();
+        assert_eq!(t.get_many_mut([12, 3, 19]).map(|[a, b, c]| (*a, *b, *c)),
+                   Some((model[12], model[3], model[19])));
+        assert_eq!(t.drain(5..25).collect::<Vec<_>>(), model.drain(5..25).collect::<Vec<_>>());
+        assert_eq!(t, model);
+
+        let mut t = tree_array![1, 2, 3];
+        t.reverse();
+        assert_eq!(format!("{:#?}", t), "TreeArray {\n    [2] size=3 reversed\n        left: [1] size=1\n        right: [3] size=1\n}");
+        assert_eq!(format!("{:?}", t), "[3, 2, 1]");
+    }
+
+    #[test]
+    fn test_swap_ranges_tree_array() {
+        let mut t: TreeArray<i32> = (0..10).collect();
+        t.swap_ranges(1..3, 6..9);
+        assert_eq!(t, [0, 6, 7, 8, 3, 4, 5, 1, 2, 9]);
+        // The order of the ranges does not matter, and adjacent ranges are fine.
+        t.swap_ranges(7..9, 1..4);
+        assert_eq!(t, [0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        t.swap_ranges(..5, 5..);
+        assert_eq!(t, [5, 6, 7, 8, 9, 0, 1, 2, 3, 4]);
+        t.swap_ranges(2..2, 8..);
+        assert_eq!(t, [5, 6, 3, 4, 7, 8, 9, 0, 1, 2]);
+        // An empty range within the other one leaves the values in place.
+        t.swap_ranges(3..3, 2..5);
+        t.swap_ranges(2..5, 5..5);
+        t.swap_ranges(4..4, 4..4);
+        assert_eq!(t, [5, 6, 3, 4, 7, 8, 9, 0, 1, 2]);
+    }
+
+    #[test]
+    #[should_panic(expected = "ranges 2..5 and 4..6 should not overlap")]
+    fn test_swap_ranges_overlap_tree_array() {
+        let mut t: TreeArray<i32> = (0..10).collect();
+        t.swap_ranges(4..6, 2..5);
+    }
+
+    #[test]
+    fn test_clone_within_tree_array() {
+        let mut t = tree_array![String::from("a"), String::from("b"), String::from("c")];
+        t.clone_within(1.., 0);
+        assert_eq!(t, ["b", "c", "a", "b", "c"]);
+        t.clone_within(..1, 5);
+        assert_eq!(t, ["b", "c", "a", "b", "c", "b"]);
+        // The destination may fall within the range.
+        t.clone_within(2..4, 3);
+        assert_eq!(t, ["b", "c", "a", "a", "b", "b", "c", "b"]);
+        t.clone_within(3..3, 0);
+        assert_eq!(t.len(), 8);
+    }
+
+    #[test]
+    fn test_split_at_mut_tree_array() {
+        let mut t: TreeArray<i32> = (0..10).collect();
+        {
+            let mut split = t.split_at_mut(4);
+            let (left, right) = split.halves();
+            for (a, b) in left.iter_mut().zip(right.iter_mut()) {
+                std::mem::swap(a, b);
+            }
+            assert_eq!(*left, [4, 5, 6, 7]);
+            split.right().push(10);
+            split.left().pop_front();
+        }
+        assert_eq!(t, [5, 6, 7, 0, 1, 2, 3, 8, 9, 10]);
+        t.split_at_mut(10).right().push(11);
+        assert_eq!(t.len(), 11);
+    }
+
+    #[test]
+    fn test_range_eq_tree_array() {
+        let t: TreeArray<char> = "hello world".chars().collect();
+        assert!(t.range_eq(6..9, &['w', 'o', 'r']));
+        assert!(!t.range_eq(6..9, &['w', 'o']));
+        assert!(t.range_eq(3..3, &[] as &[char]));
+        assert!(t.starts_with(&['h', 'e']));
+        assert!(!t.starts_with(&['e']));
+        assert!(t.ends_with(&['l', 'd']));
+        assert!(t.ends_with(&[] as &[char]));
+        assert!(!t.ends_with(&"hello world!".chars().collect::<Vec<_>>()));
+        let empty: TreeArray<char> = TreeArray::new();
+        assert!(empty.starts_with(&[] as &[char]));
+        assert!(!empty.ends_with(&['x']));
+    }
+
+    #[test]
+    fn test_find_subsequence_tree_array() {
+        let t: TreeArray<char> = "abababcab".chars().collect();
+        assert_eq!(t.find_subsequence(&['a', 'b', 'c']), Some(4));
+        assert_eq!(t.find_subsequence(&['c', 'b']), None);
+        assert_eq!(t.subsequence_indices(&['a', 'b', 'a']).collect::<Vec<_>>(), [0, 2]);
+        assert_eq!(t.subsequence_indices(&['a', 'b']).collect::<Vec<_>>(), [0, 2, 4, 7]);
+        assert_eq!(t.subsequence_indices(&[]).count(), 10);
+        // Matches agree with a naive search over a vector.
+        let mut rng = Rng(0x5eb5e9);
+        let values: Vec<usize> = (0..2000).map(|_| rng.below(3)).collect();
+        let t = TreeArray::from(values.clone());
+        for len in 1..8 {
+            let pattern: Vec<usize> = (0..len).map(|_| rng.below(3)).collect();
+            let expected: Vec<usize> = values.windows(len).enumerate()
+                .filter(|(_, w)| *w == &pattern[..]).map(|(i, _)| i).collect();
+            assert_eq!(t.subsequence_indices(&pattern).collect::<Vec<_>>(), expected);
+        }
+    }
+
+    #[test]
+    fn test_builder_tree_array() {
+        let mut builder = TreeArrayBuilder::new();
+        assert!(builder.is_empty());
+        for i in 0..5000 {
+            builder.push(i);
+        }
+        builder.extend(5000..5003);
+        assert_eq!(builder.len(), 5003);
+        let t = builder.finish();
+        assert!(t.iter().cloned().eq(0..5003));
+        assert_eq!(TreeArrayBuilder::<i32>::default().finish().len(), 0);
+    }
+
+    #[test]
+    fn test_sort_tree_array() {
+        let mut rng = Rng(0x5027);
+        for &len in &[0, 1, 31, 33, 500, 5000] {
+            // Pairs of a key and their original index, to check stability.
+            let mut model: Vec<(usize, usize)> = (0..len).map(|i| (rng.below(50), i)).collect();
+            let mut t = TreeArray::from(model.clone());
+            t.sort_by_key(|&(key, _)| key);
+            model.sort_by_key(|&(key, _)| key);
+            assert_eq!(t, model);
+            t.sort_by(|a, b| b.cmp(a));
+            model.sort_by(|a, b| b.cmp(a));
+            assert_eq!(t, model);
+            t.sort();
+            model.sort();
+            assert_eq!(t, model);
+        }
+        let mut sorted: TreeArray<i32> = (0..100_000).collect();
+        sorted.reverse_range(40_000..40_010);
+        sorted.sort();
+        assert!(sorted.iter().cloned().eq(0..100_000));
+    }
+
+    #[test]
+    fn test_is_sorted_tree_array() {
+        let mut t = tree_array![1, 2, 2, 5];
+        assert!(t.is_sorted());
+        assert!(!t.is_sorted_by(|a, b| a < b));
+        assert!(t.is_sorted_by_key(|v| v / 2));
+        t.reverse();
+        assert!(!t.is_sorted());
+        assert!(t.is_sorted_by(|a, b| a >= b));
+        assert!(TreeArray::<f64>::new().is_sorted());
+        assert!(!tree_array![1.0, f64::NAN].is_sorted());
+    }
+
+    #[test]
+    fn test_dedup_tree_array() {
+        let mut t = tree_array![1, 1, 2, 3, 3, 3, 1, 4, 4];
+        t.dedup();
+        assert_eq!(t, [1, 2, 3, 1, 4]);
+        t.dedup_by_key(|v| *v / 2);
+        assert_eq!(t, [1, 2, 1, 4]);
+        let mut t = tree_array!["a", "A", "b", "B", "b"];
+        t.dedup_by(|a, b| a.eq_ignore_ascii_case(b));
+        assert_eq!(t, ["a", "b"]);
+        // The kept value can absorb the ones removed.
+        let mut t = tree_array![(1, 1), (1, 2), (2, 5), (2, 1)];
+        t.dedup_by(|a, b| {
+            let same = a.0 == b.0;
+            if same {
+                b.1 += a.1;
+            }
+            same
+        });
+        assert_eq!(t, [(1, 3), (2, 6)]);
+    }
+
+    #[test]
+    fn test_merge_tree_array() {
+        let evens: TreeArray<i32> = (0..20).step_by(2).collect();
+        let odds: TreeArray<i32> = (1..20).step_by(2).collect();
+        assert!(evens.merge(odds).iter().cloned().eq(0..20));
+        let big: TreeArray<i32> = (0..10_000).collect();
+        let merged = big.merge(tree_array![-1, 5000, 20_000]);
+        assert_eq!(merged.len(), 10_003);
+        assert!(merged.is_sorted());
+        assert_eq!((merged[0], merged[5001], merged[10_002]), (-1, 5000, 20_000));
+        // Equal values of the first array come first.
+        let a = tree_array![(1, 'a'), (2, 'a')];
+        let b = tree_array![(1, 'b'), (3, 'b')];
+        assert_eq!(a.merge_by(b, |x, y| x.0.cmp(&y.0)), [(1, 'a'), (1, 'b'), (2, 'a'), (3, 'b')]);
+        assert_eq!(TreeArray::new().merge(tree_array![1]), [1]);
+    }
+
+    #[test]
+    fn test_partition_tree_array() {
+        let t: TreeArray<i32> = (0..10).collect();
+        let (even, odd) = t.partition(|v| v % 2 == 0);
+        assert_eq!(even, [0, 2, 4, 6, 8]);
+        assert_eq!(odd, [1, 3, 5, 7, 9]);
+        let (all, none) = even.partition(|_| true);
+        assert_eq!((all.len(), none.len()), (5, 0));
+    }
+
+    #[test]
+    fn test_binary_search_tree_array() {
+        let t: TreeArray<i32> = (0..100).map(|v| v * 2).collect();
+        assert_eq!(t.binary_search(&42), Ok(21));
+        assert_eq!(t.binary_search(&43), Err(22));
+        assert_eq!(t.binary_search(&-1), Err(0));
+        assert_eq!(t.binary_search(&1000), Err(100));
+        assert_eq!(t.binary_search_by_key(&21, |v| v / 2), Ok(21));
+        assert_eq!(t.partition_point(|&v| v < 51), 26);
+        assert_eq!(TreeArray::<i32>::new().binary_search(&0), Err(0));
+        let mut rng = Rng(0xb5);
+        let mut values: Vec<usize> = (0..1000).map(|_| rng.below(300)).collect();
+        values.sort();
+        let mut t = TreeArray::from(values.clone());
+        t.reverse();
+        t.reverse_range(..);
+        for x in 0..300 {
+            assert_eq!(t.partition_point(|&v| v < x), values.partition_point(|&v| v < x));
+            match t.binary_search(&x) {
+                Ok(i) => assert_eq!(values[i], x),
+                Err(i) => assert_eq!(Err(i), values.binary_search(&x)),
+            }
+        }
+    }
+
+    #[test]
+    fn test_sorted_tree_array() {
+        let mut rng = Rng(0x50f7);
+        let mut sorted = SortedTreeArray::new();
+        let mut model: Vec<usize> = Vec::new();
+        for _ in 0..2000 {
+            let x = rng.below(200);
+            match rng.below(3) {
+                0 => assert_eq!(sorted.remove_value(&x).is_some(),
+                                model.iter().position(|&v| v == x).map(|i| model.remove(i)).is_some()),
+                _ => {
+                    let index = sorted.insert_sorted(x);
+                    assert_eq!(index, model.partition_point(|&v| v <= x));
+                    model.insert(index, x);
+                },
+            }
+            assert_eq!(sorted.rank(&x), model.partition_point(|&v| v < x));
+            assert_eq!(sorted.count(&x), model.iter().filter(|&&v| v == x).count());
+            let k = rng.below(model.len() + 1);
+            assert_eq!(sorted.select(k), model.get(k));
+        }
+        assert!(sorted.iter().eq(model.iter()));
+        let mut sorted: SortedTreeArray<i32> = tree_array![3, 1, 2].into();
+        assert_eq!(format!("{:?}", sorted), "[1, 2, 3]");
+        assert!(sorted.contains(&2) && !sorted.contains(&4));
+        assert_eq!(sorted.first(), Some(&1));
+        assert_eq!(sorted.last(), Some(&3));
+    }
+
+    #[test]
+    fn test_sorted_queries_splay() {
+        let mut rng = Rng(0x6d1a);
+        let n = 1 << 14;
+        let mut sorted = SortedTreeArray::new();
+        for x in 0..n {
+            sorted.insert_sorted(x);
+        }
+        assert_eq!(sorted.rank(&0), 0);
+        for _ in 0..100 {
+            let x = rng.below(n);
+            assert_eq!(sorted.rank(&x), x);
+            assert!(sorted.kth(rng.below(n)).is_some());
+        }
+        assert_eq!(sorted.median(), Some(&(n / 2 - 1)));
+        // Walking down a spine would leave it as deep as the array is long.
+        assert!(sorted.depth() < n / 16);
+    }
+
+    #[test]
+    fn test_tree_map() {
+        let mut map = TreeMap::new();
+        assert_eq!(map.insert("b", 2), None);
+        assert_eq!(map.insert("d", 4), None);
+        assert_eq!(map.insert("a", 1), None);
+        assert_eq!(map.insert("b", 20), Some(2));
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.get(&"b"), Some(&20));
+        assert_eq!(map.get(&"c"), None);
+        *map.get_mut(&"a").unwrap() += 10;
+        assert_eq!(map.get_index(0), Some((&"a", &11)));
+        assert_eq!(map.get_index(3), None);
+        assert_eq!(map.rank_of_key(&"c"), 2);
+        assert_eq!(map.rank_of_key(&"d"), 2);
+        assert_eq!(map.index_of_key(&"c"), None);
+        assert_eq!(map.remove(&"b"), Some(20));
+        assert!(!map.contains_key(&"b"));
+        assert_eq!(format!("{:?}", map), r#"{"a": 11, "d": 4}"#);
+        assert_eq!(map.remove_index(1), Some(("d", 4)));
+        let mut map: TreeMap<i32, char> = vec![(3, 'c'), (1, 'a'), (3, 'C'), (2, 'b')].into_iter().collect();
+        assert!(map.iter().eq(vec![(&1, &'a'), (&2, &'b'), (&3, &'C')]));
+        assert!(map.keys().cloned().eq(1..4));
+        assert_eq!(map.values().rev().collect::<String>(), "Cba");
+        assert_eq!(map.first(), Some((&1, &'a')));
+        assert_eq!(map.last(), Some((&3, &'C')));
+    }
+
+    #[test]
+    fn test_tree_map_lookups_splay() {
+        let mut rng = Rng(0x5b1a);
+        let n = 1 << 14;
+        let mut map = TreeMap::new();
+        for key in 0..n {
+            map.insert(key, key * 2);
+        }
+        assert_eq!(map.get(&0), Some(&0));
+        for _ in 0..100 {
+            let key = rng.below(n);
+            assert_eq!(map.get(&key), Some(&(key * 2)));
+            assert_eq!(map.rank_of_key(&key), key);
+        }
+        // Walking down a spine would leave it as deep as the map is long.
+        assert!(map.depth() < n / 16);
+    }
+
+    #[test]
+    fn test_select_nth_unstable_tree_array() {
+        let mut rng = Rng(0x5e1ec7);
+        for &len in &[1, 2, 10, 1000] {
+            for _ in 0..10 {
+                let values: Vec<usize> = (0..len).map(|_| rng.below(len / 2 + 1)).collect();
+                let mut sorted = values.clone();
+                sorted.sort();
+                let k = rng.below(len);
+                let mut t = TreeArray::from(values);
+                assert_eq!(*t.select_nth_unstable(k), sorted[k]);
+                assert_eq!(t[k], sorted[k]);
+                assert!(t.iter().take(k).all(|&v| v <= sorted[k]));
+                assert!(t.iter().skip(k).all(|&v| v >= sorted[k]));
+                t.sort();
+                assert_eq!(t, sorted);
+            }
+        }
+        let mut t = tree_array![5, 1, 4, 2, 3];
+        *t.select_nth_unstable_by_key(0, |v| -v) += 10;
+        assert_eq!(t[0], 15);
+        let mut sorted: SortedTreeArray<i32> = (0..10).rev().collect();
+        assert_eq!(sorted.kth(3), Some(&3));
+        assert_eq!(sorted.median(), Some(&4));
+        assert_eq!(SortedTreeArray::<i32>::new().median(), None);
+    }
+
+    #[test]
+    fn test_measure_tree_array() {
+        let mut rng = Rng(0x3ea5);
+        let mut t: TreeArray<usize, Concat> = (0..200).collect();
+        let mut model: Vec<usize> = (0..200).collect();
+        for i in 0..3000 {
+            let a = rng.below(model.len() + 1);
+            let b = rng.below(model.len() + 1);
+            let (start, end) = if a < b { (a, b) } else { (b, a) };
+            match rng.below(9) {
+                0 => { t.insert(start, i); model.insert(start, i); },
+                1 if start < model.len() => assert_eq!(t.remove(start), Some(model.remove(start))),
+                2 if start < model.len() => { t.set(start, i); model[start] = i; },
+                3 if end < model.len() => { t.swap(start, end); model.swap(start, end); },
+                4 => { t.reverse_range(start..end); model[start..end].reverse(); },
+                5 => { t.rotate_left(start); model.rotate_left(start); },
+                6 => {
+                    let removed: Vec<usize> = t.splice(start..end, vec![i, i + 1]).collect();
+                    assert_eq!(removed, model.splice(start..end, vec![i, i + 1]).collect::<Vec<_>>());
+                },
+                7 => { t.retain(|v| v % 7 != 0); model.retain(|v| v % 7 != 0); },
+                _ => assert_eq!(t.measure_range(start..end).0, &model[start..end]),
+            }
+            assert_eq!(t.measure().0, model);
+        }
+        t.reverse();
+        model.reverse();
+        assert_eq!(t.clone().measure().0, model);
+        t.sort();
+        model.sort();
+        assert_eq!(t.measure().0, model);
+
+        let mut totals: TreeArray<usize, Total> = TreeArray::default();
+        assert_eq!(totals.measure(), Total(0));
+        totals.extend(1..=100);
+        assert_eq!(totals.measure(), Total(5050));
+        assert_eq!(totals.measure_range(10..20), Total((11..=20).sum()));
+        assert_eq!(totals.measure_range(..), Total(5050));
+        let mut tail = totals.split_off(50);
+        assert_eq!((totals.measure(), tail.measure()), (Total(1275), Total(3775)));
+        tail.truncate(10);
+        assert_eq!(tail.measure(), Total((51..=60).sum()));
+        assert_eq!(TreeArray::<usize>::new().measure(), ());
+    }
+
+    #[test]
+    fn test_measure_once_per_value() {
+        use std::cell::Cell;
+        thread_local!(static MEASURED: Cell<usize> = const { Cell::new(0) });
+
+        // Measure counting how many values it measured.
+        #[derive(Clone, Copy, Debug, PartialEq)]
+        struct Counted(usize);
+
+        impl Measure<usize> for Counted {
+            fn identity() -> Counted {
+                Counted(0)
+            }
+
+            fn measure(value: &usize) -> Counted {
+                MEASURED.with(|measured| measured.set(measured.get() + 1));
+                Counted(*value)
+            }
+
+            fn combine(&self, other: &Counted) -> Counted {
+                Counted(self.0 + other.0)
+            }
+        }
+
+        let mut rng = Rng(0x0ce);
+        let mut t: TreeArray<usize, Counted> = (0..1000).collect();
+        assert_eq!(MEASURED.with(Cell::get), 1000);
+        // Splaying, splitting and joining only combine the measures kept on the nodes.
+        for _ in 0..100 {
+            let index = rng.below(1000);
+            assert_eq!(t.splay_get(index), Some(&index));
+            let (start, end) = (index / 2, index);
+            assert_eq!(t.measure_range(start..end), Counted((start..end).sum()));
+            t.reverse_range(start..end);
+            t.reverse_range(start..end);
+            t.swap(start, index);
+            t.swap(start, index);
+        }
+        assert_eq!(MEASURED.with(Cell::get), 1000);
+        t.set(10, 5000);
+        t.insert(20, 6000);
+        assert_eq!(MEASURED.with(Cell::get), 1002);
+        assert_eq!(t.measure(), Counted((0..1000).sum::<usize>() - 10 + 5000 + 6000));
+        assert_eq!(t.clone().measure(), t.measure());
+    }
+
+    #[test]
+    fn test_range_extrema() {
+        let mut rng = Rng(0x714);
+        let mut t: TreeArray<usize, MinMax<usize>> = TreeArray::default();
+        let mut model: Vec<usize> = Vec::new();
+        assert_eq!(t.min_in_range(..), None);
+        for _ in 0..2000 {
+            let a = rng.below(model.len() + 1);
+            let b = rng.below(model.len() + 1);
+            let (start, end) = if a < b { (a, b) } else { (b, a) };
+            match rng.below(5) {
+                0 | 1 => { let v = rng.below(50); t.insert(start, v); model.insert(start, v); },
+                2 if start < model.len() => assert_eq!(t.remove(start), Some(model.remove(start))),
+                3 => { t.reverse_range(start..end); model[start..end].reverse(); },
+                _ => {
+                    let window = &model[start..end];
+                    let argmin = window.iter().enumerate().min_by_key(|&(_, v)| v).map(|(i, _)| start + i);
+                    let argmax = window.iter().enumerate().max_by_key(|&(_, v)| v).map(|(i, _)| start + i);
+                    assert_eq!(t.argmin_in_range(start..end), argmin);
+                    assert_eq!(t.argmax_in_range(start..end), argmax);
+                    assert_eq!(t.min_in_range(start..end), window.iter().min());
+                    assert_eq!(t.max_in_range(start..end), window.iter().max());
+                },
+            }
+        }
+        assert_eq!(t.measure().min(), model.iter().min());
+        assert_eq!(t.measure().argmax(), model.iter().enumerate().max_by_key(|&(_, v)| v).map(|(i, _)| i));
+    }
+
+    #[test]
+    fn test_range_extrema_splay() {
+        let mut rng = Rng(0x714e);
+        let n = 1 << 14;
+        let mut t: TreeArray<usize, MinMax<usize>> = TreeArray::default();
+        for i in 0..n {
+            t.push(i);
+        }
+        for _ in 0..100 {
+            let start = rng.below(n - 64);
+            assert_eq!(t.min_in_range(start..start + 64), Some(&start));
+            assert_eq!(t.argmax_in_range(start..start + 64), Some(start + 63));
+        }
+        // Walking down the spine would leave it as deep as the array is long.
+        assert!(t.depth() < n / 16);
+    }
+
+    #[test]
+    fn test_lazy_add_to_range() {
+        let mut rng = Rng(0xadd);
+        let mut t: LazyTreeArray<i64> = (0..100).collect();
+        let mut model: Vec<i64> = (0..100).collect();
+        for i in 0..3000 {
+            let a = rng.below(model.len() + 1);
+            let b = rng.below(model.len() + 1);
+            let (start, end) = if a < b { (a, b) } else { (b, a) };
+            let delta = rng.below(21) as i64 - 10;
+            match rng.below(6) {
+                0 => { t.insert(start, i); model.insert(start, i); },
+                1 if start < model.len() => assert_eq!(t.remove(start), Some(model.remove(start))),
+                2 if start < model.len() => assert_eq!(t.get(start), Some(&model[start])),
+                3 if start < model.len() => { t.set(start, delta); model[start] = delta; },
+                4 => assert_eq!(t.sum_range(start..end), model[start..end].iter().sum::<i64>()),
+                _ => {
+                    t.add_to_range(start..end, delta);
+                    for v in &mut model[start..end] {
+                        *v += delta;
+                    }
+                },
+            }
+            assert_eq!(t.sum(), model.iter().sum::<i64>());
+        }
+        assert_eq!(t.to_vec(), model);
+        assert_eq!(format!("{:?}", t), format!("{:?}", model));
+        assert_eq!(t.into_vec(), model);
+    }
+
+    #[test]
+    fn test_lazy_assign_range() {
+        let mut rng = Rng(0xa551);
+        let mut t: LazyTreeArray<i64> = LazyTreeArray::new();
+        let mut model: Vec<i64> = Vec::new();
+        for i in 0..3000 {
+            let a = rng.below(model.len() + 1);
+            let b = rng.below(model.len() + 1);
+            let (start, end) = if a < b { (a, b) } else { (b, a) };
+            let x = rng.below(21) as i64 - 10;
+            match rng.below(6) {
+                0 | 1 => { t.insert(start, i); model.insert(start, i); },
+                2 if start < model.len() => assert_eq!(t.get(start), Some(&model[start])),
+                3 => assert_eq!(t.sum_range(start..end), model[start..end].iter().sum::<i64>()),
+                4 => {
+                    t.add_to_range(start..end, x);
+                    for v in &mut model[start..end] {
+                        *v += x;
+                    }
+                },
+                _ => {
+                    t.assign_range(start..end, x);
+                    for v in &mut model[start..end] {
+                        *v = x;
+                    }
+                },
+            }
+            assert_eq!(t.sum(), model.iter().sum::<i64>());
+        }
+        assert_eq!(t.to_vec(), model);
+        t.assign_range(.., 2);
+        assert_eq!(t.sum(), 2 * model.len() as i64);
+    }
+
+    #[test]
+    fn test_find_by_prefix_sum() {
+        let mut rng = Rng(0xfe2);
+        let weights: Vec<u64> = (0..500).map(|_| rng.below(10) as u64).collect();
+        let mut t: TreeArray<u64, Sum<u64>> = weights.iter().cloned().collect();
+        let total: u64 = weights.iter().sum();
+        for x in 0..total + 2 {
+            let expected = weights.iter().scan(0, |sum, w| { *sum += w; Some(*sum) }).position(|sum| sum > x);
+            assert_eq!(t.find_by_prefix_sum(&x), expected);
+        }
+        assert_eq!(TreeArray::<u64, Sum<u64>>::default().find_by_prefix_sum(&0), None);
+
+        // Lookups splay what they find, rather than walking down the spine left by pushes.
+        let n = 1 << 14;
+        let mut t: TreeArray<u64, Sum<u64>> = TreeArray::default();
+        for _ in 0..n {
+            t.push(1);
+        }
+        for _ in 0..100 {
+            let x = rng.below(n);
+            assert_eq!(t.find_by_prefix_sum(&(x as u64)), Some(x));
+        }
+        assert!(t.depth() < n / 16);
+
+        // Prefixes are read in index order, whatever reversals are pending.
+        let mut t: TreeArray<usize, Concat> = (0..300).collect();
+        let mut model: Vec<usize> = (0..300).collect();
+        for _ in 0..100 {
+            let a = rng.below(model.len() + 1);
+            let b = rng.below(model.len() + 1);
+            let (start, end) = if a < b { (a, b) } else { (b, a) };
+            t.reverse_range(start..end);
+            model[start..end].reverse();
+            let x = rng.below(300);
+            assert_eq!(t.position_by_measure(|c| c.0.contains(&x)), model.iter().position(|&v| v == x));
+        }
+    }
+
+    #[test]
+    fn test_weighted_width() {
+        let mut t: TreeArray<String, TotalWidth> = TreeArray::default();
+        assert_eq!(t.insert_at_offset(0, "world".to_string()), 0);
+        assert_eq!(t.insert_at_offset(0, "hello ".to_string()), 0);
+        assert_eq!(t.insert_at_offset(11, "!".to_string()), 2);
+        assert_eq!(t.insert_at_offset(6, "".to_string()), 1);
+        assert_eq!(t.insert_at_offset(6, "big ".to_string()), 2);
+        assert_eq!(t.total_width(), 16);
+        assert_eq!(t.iter().cloned().collect::<String>(), "hello big world!");
+        assert_eq!(t.get_at_offset(0).map(|s| s.as_str()), Some("hello "));
+        assert_eq!(t.get_at_offset(5).map(|s| s.as_str()), Some("hello "));
+        assert_eq!(t.get_at_offset(6).map(|s| s.as_str()), Some("big "));
+        assert_eq!(t.get_at_offset(15).map(|s| s.as_str()), Some("!"));
+        assert_eq!(t.get_at_offset(16), None);
+        assert_eq!(t.offset_of(3), 10);
+        assert_eq!(t.offset_of(5), 16);
+        t.set(3, "wide world".to_string());
+        assert_eq!(t.total_width(), 21);
+        assert_eq!(t.index_at_offset(19), Some(3));
+        t.reverse();
+        assert_eq!(t.get_at_offset(0).map(|s| s.as_str()), Some("!"));
+        assert_eq!(t.offset_of(2), 11);
+
+        // Lookups splay what they find, rather than walking down the spine left by pushes.
+        let mut rng = Rng(0x3d7);
+        let n = 1 << 14;
+        let mut t: TreeArray<String, TotalWidth> = TreeArray::default();
+        for _ in 0..n {
+            t.push("ab".to_string());
+        }
+        for _ in 0..100 {
+            let offset = rng.below(2 * n);
+            assert_eq!(t.index_at_offset(offset), Some(offset / 2));
+            assert!(t.get_at_offset(offset).is_some());
+        }
+        assert!(t.depth() < n / 16);
+    }
+
+    #[test]
+    #[should_panic(expected = "should fall between values")]
+    fn test_insert_at_offset_within_value() {
+        let mut t: TreeArray<Vec<u8>, TotalWidth> = TreeArray::default();
+        t.push(vec![1, 2, 3]);
+        t.insert_at_offset(1, vec![4]);
+    }
+
+    #[test]
+    fn test_tree_string() {
+        let mut rng = Rng(0x5791);
+        let pieces = ["a", "bc", "déjà vu ", "日本語", "🦀🦀", "\n", "x".repeat(300).as_str()].map(String::from);
+        let mut t = TreeString::new();
+        let mut model: Vec<char> = Vec::new();
+        assert_eq!(t.slice(..), "");
+        for _ in 0..2000 {
+            let a = rng.below(model.len() + 1);
+            let b = rng.below(model.len() + 1);
+            let (start, end) = if a < b { (a, b) } else { (b, a) };
+            match rng.below(4) {
+                0 | 1 => {
+                    let piece = &pieces[rng.below(pieces.len())];
+                    t.insert_str(start, piece);
+                    model.splice(start..start, piece.chars());
+                },
+                2 => { t.remove_range(start..end); model.drain(start..end); },
+                _ => {
+                    assert_eq!(t.slice(start..end), model[start..end].iter().collect::<String>());
+                    assert_eq!(t.char(start), model.get(start).cloned());
+                },
+            }
+            assert_eq!(t.len_chars(), model.len());
+        }
+        let text: String = model.iter().collect();
+        assert_eq!(t.len_bytes(), text.len());
+        assert_eq!(t.to_string(), text);
+        assert_eq!(t, text.as_str());
+        assert_eq!(t, TreeString::from(text.clone()));
+        assert!(t.chunks().all(|chunk| chunk.len() <= 300));
+        assert!(matches!(TreeString::from("borrowed").slice(1..4), Cow::Borrowed("orr")));
+        assert_eq!(format!("{:?}", TreeString::from("a\"b")), "\"a\\\"b\"");
+    }
+
+    #[test]
+    fn test_tree_string_lookups_splay() {
+        let mut rng = Rng(0x5792);
+        let n = 1 << 12;
+        let piece = "x".repeat(256);
+        assert!(TreeString::from(piece.repeat(n)).depth() <= 13);
+        // Appending chunk after chunk hangs each one off the end of a spine.
+        let mut t = TreeString::new();
+        for i in 0..n {
+            t.insert_str(i * 256, &piece);
+        }
+        for _ in 0..100 {
+            let i = rng.below(n * 256);
+            assert_eq!(t.char(i), Some('x'));
+        }
+        // Walking down the spine would leave it as deep as there are chunks.
+        assert!(t.depth() < n / 16);
+    }
+
+    #[test]
+    fn test_tree_string_lines() {
+        let mut rng = Rng(0x11e5);
+        let pieces = ["ab", "\n", "é\nà", "\n\n", "日本語\n", "y".repeat(290).as_str()].map(String::from);
+        let mut t = TreeString::new();
+        let mut text = String::new();
+        for _ in 0..300 {
+            let piece = &pieces[rng.below(pieces.len())];
+            let at = rng.below(t.len_chars() + 1);
+            t.insert_str(at, piece);
+            let byte = text.char_indices().nth(at).map_or(text.len(), |(b, _)| b);
+            text.insert_str(byte, piece);
+        }
+        let lines: Vec<&str> = text.split_inclusive('\n').collect();
+        assert_eq!(t.len_lines(), text.matches('\n').count() + 1);
+        let mut start = 0;
+        for (n, line) in lines.iter().enumerate() {
+            assert_eq!(t.line_to_char(n), start);
+            assert_eq!(t.line(n), *line);
+            assert_eq!(t.char_to_line(start), n);
+            start += line.chars().count();
+            assert_eq!(t.char_to_line(start - 1), n);
+        }
+        assert_eq!(t.line_to_char(t.len_lines()), t.len_chars());
+        assert_eq!(t.char_to_line(t.len_chars()), t.len_lines() - 1);
+        let mut t = TreeString::from("one\ntwo");
+        assert_eq!(t.len_lines(), 2);
+        assert_eq!(t.line(1), Cow::Borrowed("two"));
+        assert_eq!(t.line_to_char(2), 7);
+        assert_eq!(TreeString::new().line(0), "");
+    }
+
+    #[test]
+    fn test_tree_string_line_lookups_splay() {
+        let mut rng = Rng(0x11e6);
+        let n = 1 << 12;
+        let line = "y".repeat(255) + "\n";
+        let mut t = TreeString::new();
+        for i in 0..n {
+            t.insert_str(i * 256, &line);
+        }
+        for _ in 0..100 {
+            let l = rng.below(n);
+            assert_eq!(t.line_to_char(l), l * 256);
+        }
+        // Walking down the spine would leave it as deep as there are lines.
+        assert!(t.depth() < n / 16);
+        assert_eq!(t.char_to_line(300), 1);
+    }
+
+    #[test]
+    fn test_tree_string_offsets() {
+        let text: String = "aé日🦀\n".repeat(200);
+        let mut t = TreeString::from(text.as_str());
+        assert_eq!((t.len_bytes(), t.len_utf16()), (text.len(), text.encode_utf16().count()));
+        let (mut byte, mut utf16) = (0, 0);
+        for (i, c) in text.chars().enumerate() {
+            assert_eq!(t.char_to_byte(i), byte);
+            assert_eq!(t.char_to_utf16(i), utf16);
+            for b in byte..byte + c.len_utf8() {
+                assert_eq!(t.byte_to_char(b), i);
+            }
+            for u in utf16..utf16 + c.len_utf16() {
+                assert_eq!(t.utf16_to_char(u), i);
+            }
+            byte += c.len_utf8();
+            utf16 += c.len_utf16();
+        }
+        let len = t.len_chars();
+        assert_eq!((t.char_to_byte(len), t.char_to_utf16(len)), (byte, utf16));
+        assert_eq!((t.byte_to_char(byte), t.utf16_to_char(utf16)), (len, len));
+    }
+
+    #[test]
+    #[cfg(feature = "unicode-segmentation")]
+    fn test_tree_string_graphemes() {
+        use unicode_segmentation::UnicodeSegmentation;
+        let text = "e\u{301}👩‍👩‍👧🇫🇷a\r\n".repeat(40);
+        let mut t = TreeString::from(text.as_str());
+        let graphemes: Vec<&str> = text.graphemes(true).collect();
+        assert_eq!(t.graphemes().collect::<Vec<_>>(), graphemes);
+        let mut boundaries = vec![0];
+        for g in &graphemes {
+            boundaries.push(boundaries.last().unwrap() + g.chars().count());
+        }
+        for w in boundaries.windows(2) {
+            for i in w[0]..w[1] {
+                assert_eq!(t.next_grapheme_boundary(i), w[1]);
+                assert_eq!(t.prev_grapheme_boundary(i + 1), w[0]);
+            }
+        }
+        assert_eq!(t.next_grapheme_boundary(t.len_chars()), t.len_chars());
+        assert_eq!(t.prev_grapheme_boundary(0), 0);
+        // Flags pair up regional indicators, counted from the start of a run across chunks.
+        let flags = "🇫🇷".repeat(100) + "🇫";
+        let t = TreeString::from(flags.as_str());
+        assert_eq!(t.graphemes().collect::<Vec<_>>(), flags.graphemes(true).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_anchors() {
+        let mut rng = Rng(0xa2c);
+        let mut t: AnchoredTreeArray<usize> = TreeArray::from((0..50).collect::<Vec<_>>()).into();
+        let mut model: Vec<(Anchor, Affinity, usize)> = Vec::new();
+        for i in 0..3000 {
+            let a = rng.below(t.len() + 1);
+            let b = rng.below(t.len() + 1);
+            let (start, end) = if a < b { (a, b) } else { (b, a) };
+            match rng.below(6) {
+                0 | 1 => {
+                    let affinity = if rng.below(2) == 0 { Affinity::Left } else { Affinity::Right };
+                    model.push((t.anchor(start, affinity), affinity, start));
+                },
+                2 => {
+                    t.insert(start, i);
+                    for &mut (_, affinity, ref mut p) in &mut model {
+                        if *p > start || (*p == start && affinity == Affinity::Right) {
+                            *p += 1;
+                        }
+                    }
+                },
+                3 => {
+                    let count = rng.below(4);
+                    t.insert_all(start, TreeArray::from(vec![i; count]));
+                    for &mut (_, affinity, ref mut p) in &mut model {
+                        if *p > start || (*p == start && affinity == Affinity::Right) {
+                            *p += count;
+                        }
+                    }
+                },
+                4 => {
+                    t.remove_range(start..end);
+                    for &mut (_, _, ref mut p) in &mut model {
+                        *p = if *p > end { *p - (end - start) } else { (*p).min(start) };
+                    }
+                },
+                _ if !model.is_empty() => {
+                    let (anchor, _, _) = model.swap_remove(rng.below(model.len()));
+                    assert!(t.remove_anchor(anchor));
+                    assert!(!t.remove_anchor(anchor));
+                    assert_eq!(t.resolve(anchor), None);
+                },
+                _ => {},
+            }
+            for &(anchor, _, p) in &model {
+                assert_eq!(t.resolve(anchor), Some(p));
+            }
+        }
+        assert_eq!(t.anchors().len(), model.len());
+
+        // Anchors added again and again at the same place exhaust labels, and get new ones.
+        let mut anchors = Anchors::new();
+        let placed: Vec<Anchor> = (0..200).map(|i| anchors.add(5, if i % 2 == 0 { Affinity::Left } else { Affinity::Right })).collect();
+        anchors.insert(5, 3);
+        for (i, &anchor) in placed.iter().enumerate() {
+            assert_eq!(anchors.resolve(anchor), Some(if i % 2 == 0 { 5 } else { 8 }));
+        }
+
+        // Anchors added again and again between two others only get labels around them anew.
+        let n = 1 << 14;
+        let mut anchors = Anchors::new();
+        let ends = [anchors.add(0, Affinity::Left), anchors.add(10, Affinity::Left)];
+        let placed: Vec<Anchor> = (0..n).map(|_| anchors.add(5, Affinity::Left)).collect();
+        anchors.insert(7, 2);
+        assert_eq!(ends.map(|anchor| anchors.resolve(anchor)), [Some(0), Some(12)]);
+        for &anchor in placed.iter().step_by(97) {
+            assert_eq!(anchors.resolve(anchor), Some(5));
+        }
+    }
+
+    #[test]
+    fn test_anchors_resolve_splays() {
+        let mut rng = Rng(0xa2d);
+        let n = 1 << 14;
+        let mut anchors = Anchors::new();
+        let added: Vec<Anchor> = (0..n).map(|i| anchors.add(i, Affinity::Left)).collect();
+        assert_eq!(anchors.resolve(added[0]), Some(0));
+        for _ in 0..100 {
+            let i = rng.below(n);
+            assert_eq!(anchors.resolve(added[i]), Some(i));
+        }
+        // Walking down a spine would leave it as deep as there are anchors.
+        assert!(anchors.depth() < n / 16);
+    }
+
+    #[test]
+    fn test_io_adapters() {
+        use std::io::{Read, Seek, SeekFrom, Write};
+        let mut t: TreeArray<u8> = TreeArray::new();
+        {
+            let mut writer = t.writer();
+            writer.write_all(b"hello world").unwrap();
+            writer.seek(SeekFrom::Start(6)).unwrap();
+            writer.write_all(b"there, rope").unwrap();
+            writer.seek(SeekFrom::End(2)).unwrap();
+            writer.write_all(b"!").unwrap();
+            assert_eq!(writer.position(), 20);
+            assert!(writer.seek(SeekFrom::Current(-21)).is_err());
+        }
+        assert_eq!(t, b"hello there, rope\0\0!".to_vec());
+        let mut reader = t.reader();
+        let mut word = [0; 5];
+        reader.read_exact(&mut word).unwrap();
+        assert_eq!(&word, b"hello");
+        reader.seek(SeekFrom::Current(8)).unwrap();
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b"rope\0\0!");
+        assert_eq!(reader.seek(SeekFrom::End(-1)).unwrap(), 19);
+        assert_eq!(reader.read(&mut word).unwrap(), 1);
+        reader.seek(SeekFrom::Start(100)).unwrap();
+        assert_eq!(reader.read(&mut word).unwrap(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "bytes")]
+    fn test_byte_rope_buf() {
+        use bytes::{Buf, BufMut, Bytes};
+        use std::io::IoSlice;
+        let mut rope = ByteRope::default();
+        rope.push(Bytes::from_static(b"head "));
+        rope.push(Bytes::new());
+        {
+            let mut appender = rope.appender();
+            for i in 0..3000u32 {
+                appender.put_u32(i);
+            }
+            appender.put_slice(b" tail");
+        }
+        assert_eq!(rope.remaining(), 5 + 12000 + 5);
+        assert!(rope.len() > 3);
+        let mut slices = [IoSlice::new(&[]); 8];
+        let n = rope.chunks_vectored(&mut slices);
+        assert_eq!(&*slices[0], b"head ");
+        assert_eq!(slices[..n].iter().map(|s| s.len()).sum::<usize>(), rope.remaining());
+        assert_eq!(rope.copy_to_bytes(3), Bytes::from_static(b"hea"));
+        assert_eq!(rope.chunk(), b"d ");
+        assert_eq!(rope.copy_to_bytes(4), Bytes::from_static(b"d \0\0"));
+        rope.advance(2);
+        for i in 1..3000 {
+            assert_eq!(rope.get_u32(), i);
+        }
+        assert_eq!(rope.copy_to_bytes(5), Bytes::from_static(b" tail"));
+        assert!(!rope.has_remaining());
+        assert_eq!(rope.chunk(), b"");
+    }
+
+    #[test]
+    fn test_tree_bits() {
+        let mut rng = Rng(0xb175);
+        let mut model: Vec<bool> = (0..300).map(|i| i % 3 == 0).collect();
+        let mut t: TreeBits = model.iter().cloned().collect();
+        for _ in 0..4000 {
+            let index = rng.below(model.len() + 1);
+            match rng.below(5) {
+                0 | 1 => {
+                    let bit = rng.below(2) == 0;
+                    t.insert_bit(index, bit);
+                    model.insert(index, bit);
+                },
+                2 => assert_eq!(t.remove_bit(index), if index < model.len() { Some(model.remove(index)) } else { None }),
+                3 if index < model.len() => { t.set(index, !model[index]); model[index] = !model[index]; },
+                _ => {
+                    let ones = model[..index].iter().filter(|&&b| b).count();
+                    assert_eq!((t.rank1(index), t.rank0(index)), (ones, index - ones));
+                    let k = rng.below(model.len() + 1);
+                    let nth = |bit: bool| model.iter().enumerate().filter(|&(_, &b)| b == bit).nth(k).map(|(i, _)| i);
+                    assert_eq!((t.select1(k), t.select0(k)), (nth(true), nth(false)));
+                    assert_eq!(t.get(index), model.get(index).cloned());
+                },
+            }
+            assert_eq!(t.len(), model.len());
+        }
+        assert_eq!(t.iter().collect::<Vec<_>>(), model);
+        assert_eq!(t.count_ones(), model.iter().filter(|&&b| b).count());
+        assert_eq!(format!("{:?}", [true, false, true].iter().cloned().collect::<TreeBits>()), "101");
+    }
+
+    #[test]
+    fn test_tree_bits_lookups_splay() {
+        let mut rng = Rng(0xb176);
+        let n = 1 << 16;
+        let mut t = TreeBits::new();
+        for i in 0..n {
+            t.push(i & 1 == 0);
+        }
+        for _ in 0..100 {
+            let index = rng.below(n);
+            assert_eq!(t.rank1(index), index.div_ceil(2));
+            assert_eq!(t.get(index), Some(index & 1 == 0));
+            assert_eq!(t.select1(index / 2), Some(index / 2 * 2));
+            assert_eq!(t.select0(index / 2), Some(index / 2 * 2 + 1));
+        }
+        // Walking down the spine would leave it as deep as there are blocks.
+        assert!(t.depth() < n / 64 / 16);
+    }
+
+    #[test]
+    fn test_rle_tree_array() {
+        let mut rng = Rng(0x41e);
+        let mut model: Vec<u8> = vec![0; 100];
+        let mut t: RleTreeArray<u8> = model.iter().cloned().collect();
+        assert_eq!(t.run_count(), 1);
+        for _ in 0..3000 {
+            let a = rng.below(model.len() + 1);
+            let b = rng.below(model.len() + 1);
+            let (start, end) = if a < b { (a, b) } else { (b, a) };
+            let value = rng.below(3) as u8;
+            match rng.below(6) {
+                0 => { t.insert(start, value); model.insert(start, value); },
+                1 => {
+                    let count = rng.below(20);
+                    t.insert_run(start, value, count);
+                    model.splice(start..start, vec![value; count]);
+                },
+                2 => assert_eq!(t.remove(start), if start < model.len() { Some(model.remove(start)) } else { None }),
+                3 if end - start < 30 => { t.remove_range(start..end); model.drain(start..end); },
+                4 => {
+                    t.fill(start..end, value);
+                    for v in &mut model[start..end] {
+                        *v = value;
+                    }
+                },
+                _ => assert_eq!(t.get(start), model.get(start)),
+            }
+            assert_eq!(t.len(), model.len());
+            let runs = 1 + model.windows(2).filter(|w| w[0] != w[1]).count();
+            assert_eq!(t.run_count(), if model.is_empty() { 0 } else { runs });
+        }
+        assert_eq!(t.iter().cloned().collect::<Vec<_>>(), model);
+        let t: RleTreeArray<char> = "aaab".chars().collect();
+        assert_eq!(format!("{:?}", t), "[('a', 3), ('b', 1)]");
+    }
+
+    #[test]
+    fn test_rle_tree_array_lookups_splay() {
+        let mut rng = Rng(0x41f);
+        let n = 1 << 14;
+        let mut t = RleTreeArray::new();
+        for i in 0..n {
+            t.insert_run(2 * i, i, 2);
+        }
+        assert_eq!(t.run_count(), n);
+        for _ in 0..100 {
+            let index = rng.below(2 * n);
+            assert_eq!(t.get(index), Some(&(index / 2)));
+        }
+        // Walking down the spine would leave it as deep as there are runs.
+        assert!(t.depth() < n / 16);
+    }
+
+    #[test]
+    fn test_dirty_tracking() {
+        let mut rng = Rng(0xd127);
+        let mut t: DirtyTreeArray<usize> = (0..200).collect();
+        let mut model: Vec<(usize, bool)> = (0..200).map(|i| (i, true)).collect();
+        t.clean_range(..);
+        for entry in &mut model {
+            entry.1 = false;
+        }
+        for i in 0..4000 {
+            let a = rng.below(model.len() + 1);
+            let b = rng.below(model.len() + 1);
+            let (start, end) = if a < b { (a, b) } else { (b, a) };
+            match rng.below(7) {
+                0 => { t.insert(start, i); model.insert(start, (i, true)); },
+                1 if start < model.len() => assert_eq!(t.remove(start), Some(model.remove(start).0)),
+                2 if start < model.len() => { t.set(start, i); model[start] = (i, true); },
+                3 => {
+                    t.mark_dirty(start..end);
+                    for entry in &mut model[start..end] {
+                        entry.1 = true;
+                    }
+                },
+                4 => {
+                    t.clean_range(start..end);
+                    for entry in &mut model[start..end] {
+                        entry.1 = false;
+                    }
+                },
+                5 => assert_eq!(t.is_dirty(start), model.get(start).map(|e| e.1)),
+                _ => {
+                    let expected = model.iter().skip(start).position(|e| e.1).map(|p| p + start);
+                    assert_eq!(t.first_dirty_after(start), expected);
+                },
+            }
+            assert_eq!(t.dirty_count(), model.iter().filter(|e| e.1).count());
+        }
+        assert!(t.iter().eq(model.iter().map(|e| &e.0)));
+        let mut seen = Vec::new();
+        let mut at = 0;
+        while let Some(i) = t.first_dirty_after(at) {
+            seen.push(i);
+            at = i + 1;
+        }
+        assert_eq!(seen, model.iter().enumerate().filter(|e| (e.1).1).map(|e| e.0).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_flag_search() {
+        let mut rng = Rng(0xf1a9);
+        let mut t: TreeArray<u32, AnyFlags> = TreeArray::default();
+        let mut model: Vec<u32> = Vec::new();
+        for _ in 0..3000 {
+            let a = rng.below(model.len() + 1);
+            let b = rng.below(model.len() + 1);
+            let (start, end) = if a < b { (a, b) } else { (b, a) };
+            let flag = 1 << rng.below(2);
+            match rng.below(5) {
+                0 | 1 => { let v = 1 + 2 * rng.below(60) as u32; t.insert(start, v); model.insert(start, v); },
+                2 if start < model.len() => { t.set(start, start as u32); model[start] = start as u32; },
+                3 => { t.reverse_range(start..end); model[start..end].reverse(); },
+                _ => {
+                    let found = |from: usize| model.iter().skip(from).position(|v| v.flags() & flag != 0).map(|i| i + from);
+                    assert_eq!(t.first_index_where(flag), found(0));
+                    assert_eq!(t.next_index_where(start, flag), found(start + 1));
+                },
+            }
+        }
+        assert_eq!(t.next_index_where(usize::MAX, 1), None);
+        let mut at = 0;
+        let mut evens = Vec::new();
+        while let Some(i) = t.position_by_measure_from(at, |any| any.0 & 1 != 0) {
+            evens.push(i);
+            at = i + 1;
+        }
+        assert_eq!(evens, model.iter().enumerate().filter(|e| e.1 % 2 == 0).map(|e| e.0).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_content_hash() {
+        let mut rng = Rng(0x4a54);
+        let values: Vec<u64> = (0..500).map(|_| rng.below(1000) as u64).collect();
+        let mut a: TreeArray<u64, ContentHash> = values.iter().cloned().collect();
+        let mut b: TreeArray<u64, ContentHash> = TreeArray::default();
+        for &v in values.iter().rev() {
+            b.push(v);
+        }
+        b.reverse();
+        b.reverse_range(100..300);
+        b.reverse_range(100..300);
+        assert_eq!(a.root_hash(), b.root_hash());
+        for _ in 0..100 {
+            let x = rng.below(a.len() + 1);
+            let y = rng.below(a.len() + 1);
+            let (start, end) = if x < y { (x, y) } else { (y, x) };
+            let part: TreeArray<u64, ContentHash> = values[start..end].iter().cloned().collect();
+            assert_eq!(a.range_hash(start..end), part.root_hash());
+            assert_eq!(b.range_hash(start..end), part.root_hash());
+        }
+        b.set(250, 1000);
+        assert_ne!(a.root_hash(), b.root_hash());
+        b.set(250, values[250]);
+        assert_eq!(a.root_hash(), b.root_hash());
+        a.swap(0, 1);
+        assert_eq!(a.root_hash() == b.root_hash(), values[0] == values[1]);
+        assert_eq!(TreeArray::<u64, ContentHash>::default().root_hash(), 0);
+        let t: TreeArray<u64, ContentHash> = (1..4).collect();
+        let u: TreeArray<String, ContentHash> = ["ab", "c"].iter().map(|s| s.to_string()).collect();
+        // Hashes are read off a fixed encoding of the values, the same on every platform.
+        #[cfg(not(feature = "blake3"))]
+        assert_eq!((t.root_hash(), u.root_hash()), (0x0211_5cd6_f140_5684, 0x15ab_5281_e903_9b7a));
+        #[cfg(feature = "blake3")]
+        assert_eq!((t.root_hash(), u.root_hash()), (0x0af3_11ca_8897_bd8d, 0x1423_3fad_062c_7f0d));
+    }
+
+    #[test]
+    fn test_diff() {
+        fn apply(values: &mut Vec<u64>, edits: &[Edit<u64>]) {
+            for edit in edits {
+                match *edit {
+                    Edit::Insert(index, value) => values.insert(index, value),
+                    Edit::Delete(index) => {
+                        values.remove(index);
+                    },
+                    Edit::Replace(index, value) => values[index] = value,
+                }
+            }
+        }
+        let mut rng = Rng(0x3d1f);
+        let values: Vec<u64> = (0..5000).map(|_| rng.below(50) as u64).collect();
+        let a: TreeArray<u64, ContentHash> = values.iter().cloned().collect();
+        assert!(a.diff(&a.clone()).is_empty());
+        let mut b = a.clone();
+        b.insert(2500, 7);
+        assert_eq!(a.diff(&b), vec![Edit::Insert(2500, 7)]);
+        b.reverse_range(10..20);
+        b.reverse_range(10..20);
+        b.set(4000, 100);
+        assert_eq!(a.diff(&b), vec![Edit::Insert(2500, 7), Edit::Replace(4000, 100)]);
+        for round in 0..20 {
+            let mut b = a.clone();
+            for _ in 0..round * 3 {
+                match rng.below(4) {
+                    0 => b.insert(rng.below(b.len() + 1), rng.below(50) as u64),
+                    1 => b.set(rng.below(b.len()), rng.below(50) as u64),
+                    2 => {
+                        let start = rng.below(b.len() - 100);
+                        b.remove_range(start..start + rng.below(100));
+                    },
+                    _ => {
+                        let start = rng.below(b.len() - 100);
+                        let moved = b.take(start..start + rng.below(100));
+                        let dest = rng.below(b.len() + 1);
+                        b.insert_all(dest, moved);
+                    },
+                }
+            }
+            let mut edited = values.clone();
+            apply(&mut edited, &a.diff(&b));
+            assert_eq!(b, edited);
+            assert_eq!(a.diff_verified(&b), a.diff(&b));
+            let mut edited = b.to_vec();
+            apply(&mut edited, &b.diff(&a));
+            assert_eq!(a, edited);
+        }
+        let empty = TreeArray::default();
+        assert_eq!(a.diff(&empty).len(), a.len());
+        assert_eq!(empty.diff(&a).len(), a.len());
+        // Values that all hash the same still get told apart when verified.
+        #[derive(Clone, Debug, PartialEq)]
+        struct Colliding(usize);
+        impl ContentBytes for Colliding {
+            fn content_bytes<F>(&self, _: &mut F) where F: FnMut(&[u8]) {}
+        }
+        let a: TreeArray<Colliding, ContentHash> = (0..50).map(Colliding).collect();
+        let b: TreeArray<Colliding, ContentHash> = (0..50).map(|i| Colliding(i % 7)).collect();
+        let mut edited = a.clone();
+        assert_eq!(edited.apply(&a.diff_verified(&b)), Ok(()));
+        assert!(edited.iter().eq(b.iter()));
+        assert!(a.diff(&b).is_empty());
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut rng = Rng(0x92ab);
+        let a: TreeArray<u64, ContentHash> = (0..2000).map(|_| rng.below(30) as u64).collect();
+        for _ in 0..10 {
+            let mut b = a.clone();
+            for _ in 0..10 {
+                let start = rng.below(b.len() - 50);
+                b.reverse_range(start..start + 50);
+            }
+            let mut edited = a.clone();
+            assert_eq!(edited.apply(&a.diff(&b)), Ok(()));
+            assert_eq!(edited, b);
+            assert_eq!(edited.root_hash(), b.root_hash());
+        }
+        let mut t = tree_array![1, 2, 3];
+        let edits = [Edit::Insert(3, 4), Edit::Delete(0), Edit::Replace(2, 5), Edit::Insert(1, 6)];
+        assert_eq!(t.apply(&edits), Ok(()));
+        assert_eq!(t, [2, 6, 3, 5]);
+        let edits = [Edit::Delete(0), Edit::Delete(0), Edit::Replace(2, 7)];
+        assert_eq!(t.apply(&edits), Err(Error::OutOfBounds { index: 2, len: 2 }));
+        assert_eq!(t, [2, 6, 3, 5]);
+        assert_eq!(t.apply(&[Edit::Insert(5, 0)]), Err(Error::OutOfBounds { index: 5, len: 4 }));
+        assert_eq!(t, [2, 6, 3, 5]);
+    }
+
+    #[test]
+    fn test_edit_batch() {
+        let mut rng = Rng(0xba7c);
+        let values: Vec<usize> = (0..300).collect();
+        let mut t: TreeArray<usize, Total> = values.iter().cloned().collect();
+        // Values inserted before each index, and what becomes of the value at it.
+        let mut inserted = vec![Vec::new(); values.len() + 1];
+        let mut kept: Vec<Option<usize>> = values.iter().map(|&v| Some(v)).collect();
+        let mut batch = t.edit_batch();
+        for i in 0..200 {
+            let index = rng.below(values.len());
+            match rng.below(3) {
+                0 => {
+                    let index = rng.below(values.len() + 1);
+                    batch.insert(index, 1000 + i);
+                    inserted[index].push(1000 + i);
+                },
+                1 => {
+                    batch.remove(index);
+                    kept[index] = None;
+                },
+                _ => {
+                    batch.set(index, 2000 + i);
+                    kept[index] = Some(2000 + i);
+                },
+            }
+        }
+        assert_eq!(batch.len(), 200);
+        assert_eq!(batch.apply(), Ok(()));
+        let mut expected = Vec::new();
+        for (i, values) in inserted.iter().enumerate() {
+            expected.extend(values.iter().cloned());
+            expected.extend(kept.get(i).cloned().unwrap_or(None));
+        }
+        assert_eq!(t, expected);
+        assert_eq!(t.measure().0, expected.iter().sum::<usize>());
+        let mut batch = t.edit_batch();
+        batch.remove(0);
+        batch.set(expected.len(), 0);
+        let len = expected.len();
+        assert_eq!(batch.apply(), Err(Error::OutOfBounds { index: len, len }));
+        assert_eq!(t, expected);
+        let mut batch = t.edit_batch();
+        batch.insert(expected.len(), 5);
+        batch.insert(0, 4);
+        assert_eq!(batch.apply(), Ok(()));
+        assert_eq!(t.first(), Some(&4));
+        assert_eq!(t.last(), Some(&5));
+    }
+
+    #[test]
+    fn test_undo() {
+        let mut rng = Rng(0x9417);
+        let mut t: UndoTreeArray<usize> = UndoTreeArray::from((0..100).collect::<TreeArray<_>>());
+        let mut states = vec![(t.snapshot(), t.values().to_vec())];
+        for i in 0..200 {
+            match rng.below(5) {
+                0 => t.insert(rng.below(t.len() + 1), 1000 + i),
+                1 => t.set(rng.below(t.len()), 2000 + i),
+                2 => {
+                    let index = rng.below(t.len());
+                    let value = t.get(index).cloned();
+                    assert_eq!(t.remove_cloned(index), value);
+                },
+                3 => {
+                    let start = rng.below(t.len() - 10);
+                    t.remove_range(start..start + rng.below(10));
+                },
+                _ => t.insert_all(rng.below(t.len() + 1), (0..rng.below(10)).collect()),
+            }
+            states.push((t.snapshot(), t.values().to_vec()));
+        }
+        for &(version, ref values) in states.iter().rev() {
+            assert_eq!(t.snapshot(), version);
+            assert_eq!(*t.values(), values[..]);
+            t.undo();
+        }
+        assert!(!t.undo());
+        for &(version, ref values) in states.iter().skip(1) {
+            assert!(t.redo());
+            assert_eq!(t.snapshot(), version);
+            assert_eq!(*t.values(), values[..]);
+        }
+        assert!(!t.redo());
+        for _ in 0..20 {
+            let (version, ref values) = states[rng.below(states.len())];
+            assert!(t.revert_to(version));
+            assert_eq!(*t.values(), values[..]);
+        }
+        assert!(t.revert_to(states[50].0));
+        t.push(7);
+        assert!(!t.revert_to(states[51].0));
+        assert!(!t.redo());
+        assert!(t.undo());
+        assert_eq!(*t.values(), states[50].1[..]);
+        t.clear_history();
+        assert!(!t.undo());
+        assert!(!t.revert_to(states[0].0));
+        assert!(t.revert_to(t.snapshot()));
+        assert_eq!(*t.values(), states[50].1[..]);
+        let last = t.values().last().cloned();
+        assert_eq!(t.pop_cloned(), last);
+        assert!(t.undo());
+        assert_eq!(*t.values(), states[50].1[..]);
+        // Values need not be cloned to be removed as a range, as the history keeps them.
+        struct Unique(usize);
+        let mut t = UndoTreeArray::new();
+        t.push(Unique(1));
+        t.push(Unique(2));
+        assert_eq!(t.remove_range(0..1).first().map(|value| value.0), Some(1));
+        assert_eq!(t.remove_range(..).first().map(|value| value.0), Some(2));
+        assert!(t.is_empty());
+        t.undo();
+        t.undo();
+        assert!(t.iter().map(|value| value.0).eq(vec![1, 2]));
+    }
+
+    #[test]
+    fn test_transaction() {
+        let mut t: UndoTreeArray<char> = UndoTreeArray::from("ab".chars().collect::<TreeArray<_>>());
+        let text = |t: &UndoTreeArray<char>| t.iter().collect::<String>();
+        t.push('c');
+        let before = t.snapshot();
+        t.begin_transaction();
+        t.insert(1, '\n');
+        t.begin_transaction();
+        t.insert(2, ' ');
+        t.insert(3, ' ');
+        t.commit();
+        assert!(t.in_transaction());
+        t.set(0, 'A');
+        assert_eq!(t.snapshot(), before);
+        t.commit();
+        assert!(!t.in_transaction());
+        assert_eq!(text(&t), "A\n  bc");
+        let after = t.snapshot();
+        assert!(t.undo());
+        assert_eq!(text(&t), "abc");
+        assert_eq!(t.snapshot(), before);
+        assert!(t.redo());
+        assert_eq!(text(&t), "A\n  bc");
+        assert_eq!(t.snapshot(), after);
+        assert!(t.undo());
+        t.begin_transaction();
+        t.remove_range(0..2);
+        t.push('d');
+        t.set(0, 'x');
+        assert_eq!(text(&t), "xd");
+        assert!(t.rollback());
+        assert!(!t.rollback());
+        assert_eq!(text(&t), "abc");
+        assert_eq!(t.snapshot(), before);
+        assert!(t.redo());
+        assert_eq!(t.snapshot(), after);
+        t.begin_transaction();
+        t.commit();
+        assert_eq!(t.snapshot(), after);
+        t.begin_transaction();
+        t.pop_cloned();
+        assert!(t.undo());
+        assert!(!t.in_transaction());
+        assert_eq!(text(&t), "A\n  bc");
+        assert!(t.undo());
+        assert_eq!(text(&t), "abc");
+        assert!(t.undo());
+        assert_eq!(text(&t), "ab");
+    }
+
+    #[test]
+    fn test_persistent() {
+        let mut rng = Rng(0x96e1);
+        let mut t: PersistentTreeArray<usize> = (0..100).collect();
+        let mut model: Vec<usize> = (0..100).collect();
+        let mut versions = Vec::new();
+        for i in 0..500 {
+            versions.push((t.clone(), model.clone()));
+            match rng.below(6) {
+                0 | 1 => {
+                    let index = rng.below(model.len() + 1);
+                    t.insert(index, i);
+                    model.insert(index, i);
+                },
+                2 => {
+                    let index = rng.below(model.len());
+                    assert_eq!(t.remove(index), Some(model.remove(index)));
+                },
+                3 => {
+                    let index = rng.below(model.len());
+                    t.set(index, i);
+                    model[index] = i;
+                },
+                4 => {
+                    let at = rng.below(model.len() + 1);
+                    let (left, right) = t.split_at(at);
+                    assert_eq!(left, model[..at]);
+                    assert_eq!(right, model[at..]);
+                    t = right.concat(&left);
+                    model.rotate_left(at);
+                },
+                _ => {
+                    let (old, values) = &versions[rng.below(versions.len())];
+                    t.append(old.clone());
+                    let tail = t.split_off(model.len());
+                    assert_eq!(tail, values[..]);
+                },
+            }
+            assert_eq!(t, model[..]);
+        }
+        for (version, model) in &versions {
+            assert_eq!(*version, model[..]);
+            assert_eq!(version.iter().len(), model.len());
+            assert_eq!(version.get(model.len()), None);
+            assert_eq!(version.last(), model.last());
+        }
+        let copy = t.clone();
+        assert!(copy.ptr_eq(&t));
+        let updated = copy.update(0, 1000);
+        assert_eq!(updated.first(), Some(&1000));
+        assert_eq!(copy, t);
+        assert!(!updated.ptr_eq(&t));
+    }
+
+    #[test]
+    fn test_persistent_make_mut() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        // Value counting how many times values were cloned.
+        struct Counted(usize, Rc<Cell<usize>>);
+
+        impl Clone for Counted {
+            fn clone(&self) -> Counted {
+                self.1.set(self.1.get() + 1);
+                Counted(self.0, self.1.clone())
+            }
+        }
+
+        let clones = Rc::new(Cell::new(0));
+        let mut t: PersistentTreeArray<Counted> = PersistentTreeArray::new();
+        for i in 0..1000 {
+            t.insert(i / 2, Counted(i, clones.clone()));
+        }
+        t.set(500, Counted(0, clones.clone()));
+        t.get_mut(20).unwrap().0 = 7;
+        assert_eq!(t.remove(10).map(|c| c.0), Some(21));
+        let tail = t.split_off(600);
+        t.append(tail);
+        assert_eq!(clones.get(), 0);
+        let copy = t.clone();
+        t.set(300, Counted(1, clones.clone()));
+        let copied = clones.get();
+        assert!(copied > 0 && copied < 100);
+        assert_eq!(copy.len(), 999);
+        t.set(301, Counted(2, clones.clone()));
+        assert!(clones.get() - copied < 100);
+        assert_eq!(copy.get(19).unwrap().0, 7);
+        assert_eq!(t.get(300).unwrap().0, 1);
+        assert_ne!(copy.get(300).unwrap().0, 1);
+        assert_eq!(t.len(), 999);
+        drop(copy);
+        let before = clones.get();
+        t.set(302, Counted(3, clones.clone()));
+        t.remove(0);
+        assert_eq!(clones.get(), before);
+    }
+
+    #[test]
+    fn test_publish() {
+        use std::sync::mpsc;
+        use std::thread;
+
+        fn is_send_sync<T: Send + Sync>(_: &T) {}
+
+        let mut t: PersistentTreeArray<usize> = (0..1000).collect();
+        let (sender, receiver) = mpsc::channel();
+        sender.send(t.publish()).unwrap();
+        let reader = thread::spawn(move || {
+            let mut last = 0;
+            for snapshot in receiver {
+                is_send_sync(&*snapshot);
+                // Each version holds the values of the first one, each shifted by its number.
+                let version = *snapshot.first().unwrap();
+                assert_eq!(snapshot.len(), 1000);
+                assert!(snapshot.iter().enumerate().all(|(i, &v)| v == i + version));
+                assert!(version >= last);
+                last = version;
+            }
+            last
+        });
+        for version in 0..100 {
+            let snapshot = t.publish();
+            sender.send(snapshot.clone()).unwrap();
+            for i in 0..t.len() {
+                *t.get_mut(i).unwrap() += 1;
+            }
+            assert_eq!(snapshot.get(0), Some(&version));
+            assert_eq!(snapshot.thaw().len(), 1000);
+        }
+        drop(sender);
+        assert_eq!(reader.join().unwrap(), 99);
+    }
+
+    #[test]
+    fn test_snapshot_iter() {
+        let mut t: PersistentTreeArray<String> = (0..100).map(|i| i.to_string()).collect();
+        let mut iter = t.snapshot_iter();
+        assert_eq!(iter.next(), Some("0".to_string()));
+        for i in 0..t.len() {
+            t.get_mut(i).unwrap().push('!');
+        }
+        t.remove(50);
+        let frozen = t.publish();
+        t.insert(0, "new".to_string());
+        assert_eq!(iter.len(), 99);
+        assert!(iter.eq((1..100).map(|i| i.to_string())));
+        drop(t);
+        assert_eq!(frozen.snapshot_iter().nth(50), Some("51!".to_string()));
+    }
+
+    #[test]
+    fn test_handles() {
+        let mut rng = Rng(0x4a3d);
+        let mut t: HandleTreeArray<usize> = (0..50).collect();
+        let mut model: Vec<(usize, Option<Handle>)> = (0..50).map(|i| (i, None)).collect();
+        let mut removed = Vec::new();
+        for i in 0..2000 {
+            // Inserting over and over at the same spot runs out of room between labels.
+            let index = if i % 3 == 0 { 10 } else { rng.below(t.len() + 1) };
+            match rng.below(6) {
+                0..=2 => {
+                    let handle = t.insert_with_handle(index, 1000 + i);
+                    model.insert(index, (1000 + i, Some(handle)));
+                },
+                3 => {
+                    t.insert(index, 1000 + i);
+                    model.insert(index, (1000 + i, None));
+                },
+                4 => {
+                    let index = rng.below(t.len());
+                    t.set(index, 1000 + i);
+                    model[index].0 = 1000 + i;
+                },
+                _ => {
+                    let start = rng.below(t.len() - 3);
+                    t.remove_range(start..start + 3);
+                    removed.extend(model.drain(start..start + 3).filter_map(|(_, h)| h));
+                },
+            }
+        }
+        assert!(t.iter().eq(model.iter().map(|(value, _)| value)));
+        for (index, &(value, handle)) in model.iter().enumerate() {
+            if let Some(handle) = handle {
+                assert_eq!(t.index_of(handle), Some(index));
+                assert_eq!(t.get_by_handle(handle), Some(&value));
+            }
+        }
+        for &handle in &removed {
+            assert_eq!(t.index_of(handle), None);
+        }
+        let handle = t.handle_at(5).unwrap();
+        assert_eq!(t.handle_at(5), Some(handle));
+        t.insert(0, 7);
+        assert_eq!(t.remove_by_handle(handle), Some(model[5].0));
+        assert_eq!(t.get_by_handle(handle), None);
+        assert_eq!(t.handle_at(t.len()), None);
+    }
+
+    #[test]
+    fn test_handles_lookups_splay() {
+        let mut rng = Rng(0x4a3e);
+        let n = 1 << 14;
+        let mut t: HandleTreeArray<usize> = HandleTreeArray::new();
+        let handles: Vec<Handle> = (0..n).map(|i| t.push_with_handle(i)).collect();
+        assert_eq!(t.index_of(handles[0]), Some(0));
+        for _ in 0..100 {
+            let i = rng.below(n);
+            assert_eq!(t.index_of(handles[i]), Some(i));
+            assert_eq!(t.get_by_handle(handles[i]), Some(&i));
+        }
+        // Walking down a spine would leave it as deep as the array is long.
+        assert!(t.depth() < n / 16);
+    }
+
+    #[test]
+    #[cfg(feature = "crdt")]
+    fn test_rga() {
+        let (mut a, mut b) = (Rga::new(1), Rga::new(2));
+        let op = a.local_insert(0, 'x');
+        b.apply_remote(op);
+        let ops = [a.local_insert(1, 'a'), b.local_insert(1, 'b')];
+        a.apply_remote(ops[1].clone());
+        b.apply_remote(ops[0].clone());
+        assert!(a.iter().eq(b.iter()));
+        assert_eq!(a.meta(0), Some(Meta { stamp: Stamp { counter: 1, replica: 1 }, after: None }));
+        assert_eq!(a.index_of(Stamp { counter: 2, replica: 2 }), Some(1));
+
+        let mut rng = Rng(0x96a);
+        let mut replicas: Vec<Rga<usize>> = (0..3).map(Rga::new).collect();
+        let mut logs: Vec<Vec<Operation<usize>>> = vec![Vec::new(); 3];
+        for round in 0..20 {
+            for (r, replica) in replicas.iter_mut().enumerate() {
+                for i in 0..10 {
+                    let op = if replica.is_empty() || rng.below(3) > 0 {
+                        replica.local_insert(rng.below(replica.len() + 1), round * 100 + i)
+                    } else {
+                        replica.local_delete(rng.below(replica.len())).unwrap()
+                    };
+                    logs[r].push(op);
+                }
+            }
+            // Some edits of the others get through before the next round, out of order.
+            for (r, replica) in replicas.iter_mut().enumerate() {
+                for log in logs.iter().enumerate().filter(|&(s, _)| s != r).map(|(_, log)| log) {
+                    for _ in 0..5 {
+                        replica.apply_remote(log[rng.below(log.len())].clone());
+                    }
+                }
+            }
+        }
+        for (r, replica) in replicas.iter_mut().enumerate() {
+            let mut ops: Vec<_> = logs.iter().enumerate().filter(|&(s, _)| s != r)
+                .flat_map(|(_, log)| log.iter().cloned()).collect();
+            for i in (1..ops.len()).rev() {
+                ops.swap(i, rng.below(i + 1));
+            }
+            for op in ops {
+                replica.apply_remote(op);
+            }
+            assert_eq!(replica.pending(), 0);
+        }
+        assert!(replicas[0].iter().eq(replicas[1].iter()));
+        assert!(replicas[0].iter().eq(replicas[2].iter()));
+        assert!(replicas[0].len() > 100);
+        for index in 0..replicas[0].len() {
+            let meta = replicas[0].meta(index).unwrap();
+            assert_eq!(replicas[2].index_of(meta.stamp), Some(index));
+            if let Some(after) = meta.after {
+                assert!(after < meta.stamp);
+            }
+        }
+    }
+
+    // Run with `RUSTFLAGS="--cfg loom" cargo test --release --lib loom`.
+    // Every clone and drop of a node counts as an atomic operation, so preemptions are bounded.
+    #[cfg(loom)]
+    fn loom_model<F>(f: F) where F: Fn() + Sync + Send + 'static {
+        let mut builder = loom::model::Builder::new();
+        builder.preemption_bound = Some(1);
+        builder.check(f);
+    }
+
+    #[cfg(loom)]
+    #[test]
+    fn loom_snapshot_iter() {
+        loom_model(|| {
+            let mut t: PersistentTreeArray<usize> = (0..3).collect();
+            let iter = t.snapshot_iter();
+            let reader = loom::thread::spawn(move || iter.collect::<Vec<_>>());
+            t.set(1, 10);
+            t.remove(0);
+            t.push(3);
+            drop(t);
+            assert_eq!(reader.join().unwrap(), [0, 1, 2]);
+        });
+    }
+
+    #[cfg(loom)]
+    #[test]
+    fn loom_publish() {
+        loom_model(|| {
+            let mut t: PersistentTreeArray<usize> = (0..3).collect();
+            t.set(0, 5);
+            let frozen = t.publish();
+            let reader = loom::thread::spawn(move || frozen.iter().cloned().collect::<Vec<_>>());
+            t.insert(1, 4);
+            t.set(2, 6);
+            drop(t);
+            assert_eq!(reader.join().unwrap(), [5, 1, 2]);
+        });
     }
 }
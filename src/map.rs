@@ -0,0 +1,196 @@
+use std::fmt;
+use std::iter::{FromIterator, FusedIterator};
+use std::mem;
+use iter;
+use tree_array::TreeArray;
+
+// A map from ordered keys to values, kept as a tree array of pairs sorted by key.
+// Besides looking values up by key, it tells the position of a key among all keys, and which
+// entry comes at a given position, by walking down the tree using subtree sizes.
+// Lookups splay the entry they reach to the root, as edits do, which is why they take the map
+// mutably: a walk down the tree is then logarithmic amortized, even when keys were inserted in
+// increasing order, which would otherwise leave a spine for every lookup to walk down.
+pub struct TreeMap<K, V> where K: Ord {
+    tree: TreeArray<(K, V)>,
+}
+
+impl<K, V> TreeMap<K, V> where K: Ord {
+    pub fn new() -> TreeMap<K, V> {
+        TreeMap { tree: TreeArray::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    // Inserts a value under a key, returning the value it replaces, if any.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        match self.search(&key) {
+            Ok(index) => self.tree.get_mut(index).map(|entry| mem::replace(&mut entry.1, value)),
+            Err(index) => {
+                self.tree.insert(index, (key, value));
+                None
+            },
+        }
+    }
+
+    // Fetches the value under a key, splaying its entry to the root.
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        let index = self.search(key).ok()?;
+        self.tree.splay_get(index).map(|entry| &entry.1)
+    }
+
+    // Fetches a mutable reference to the value under a key, splaying its entry to the root.
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let index = self.search(key).ok()?;
+        self.tree.get_mut(index).map(|entry| &mut entry.1)
+    }
+
+    pub fn contains_key(&mut self, key: &K) -> bool {
+        self.search(key).is_ok()
+    }
+
+    // Removes the entry under a key, and returns its value.
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let index = self.search(key).ok()?;
+        self.tree.remove(index).map(|entry| entry.1)
+    }
+
+    // Fetches the entry at a given position in key order.
+    pub fn get_index(&mut self, index: usize) -> Option<(&K, &V)> {
+        self.tree.splay_get(index).map(|entry| (&entry.0, &entry.1))
+    }
+
+    // Removes the entry at a given position in key order, and returns it.
+    pub fn remove_index(&mut self, index: usize) -> Option<(K, V)> {
+        self.tree.remove(index)
+    }
+
+    // Number of keys smaller than `key`, which is the position of its entry if there is one.
+    pub fn rank_of_key(&mut self, key: &K) -> usize {
+        match self.search(key) {
+            Ok(index) | Err(index) => index,
+        }
+    }
+
+    // Position of the entry under a key, or None if there is none.
+    pub fn index_of_key(&mut self, key: &K) -> Option<usize> {
+        self.search(key).ok()
+    }
+
+    // The entry with the smallest key.
+    pub fn first(&mut self) -> Option<(&K, &V)> {
+        self.tree.splay_get(0).map(|entry| (&entry.0, &entry.1))
+    }
+
+    // The entry with the largest key.
+    pub fn last(&mut self) -> Option<(&K, &V)> {
+        let len = self.len();
+        self.tree.splay_get(len.checked_sub(1)?).map(|entry| (&entry.0, &entry.1))
+    }
+
+    // Iterates over the entries in key order.
+    pub fn iter(&self) -> MapIter<'_, K, V> {
+        MapIter { entries: self.tree.iter() }
+    }
+
+    // Iterates over the keys in order.
+    pub fn keys(&self) -> impl DoubleEndedIterator<Item = &K> + ExactSizeIterator {
+        self.iter().map(|(key, _)| key)
+    }
+
+    // Iterates over the values in key order.
+    pub fn values(&self) -> impl DoubleEndedIterator<Item = &V> + ExactSizeIterator {
+        self.iter().map(|(_, value)| value)
+    }
+
+    pub fn clear(&mut self) {
+        self.tree.clear();
+    }
+
+    // Finds the position of a key, or where it would be inserted, splaying the entry reached.
+    fn search(&mut self, key: &K) -> Result<usize, usize> {
+        self.tree.splay_search_by(|entry| entry.0.cmp(key))
+    }
+
+    // Depth of the tree, to check that lookups keep it shallow.
+    #[cfg(test)]
+    pub(crate) fn depth(&self) -> usize {
+        self.tree.depth()
+    }
+}
+
+impl<K, V> Default for TreeMap<K, V> where K: Ord {
+    fn default() -> TreeMap<K, V> {
+        TreeMap::new()
+    }
+}
+
+impl<K, V> Clone for TreeMap<K, V> where K: Ord + Clone, V: Clone {
+    fn clone(&self) -> TreeMap<K, V> {
+        TreeMap { tree: self.tree.clone() }
+    }
+}
+
+// Collecting sorts the entries by key, and keeps the last value given for each key.
+impl<K, V> FromIterator<(K, V)> for TreeMap<K, V> where K: Ord {
+    fn from_iter<I>(iter: I) -> TreeMap<K, V> where I: IntoIterator<Item = (K, V)> {
+        let mut entries: Vec<(K, V)> = iter.into_iter().collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut tree = TreeArray::from(entries);
+        tree.dedup_by(|next, kept| {
+            let same = next.0 == kept.0;
+            if same {
+                mem::swap(&mut next.1, &mut kept.1);
+            }
+            same
+        });
+        TreeMap { tree }
+    }
+}
+
+impl<K, V> fmt::Debug for TreeMap<K, V> where K: Ord + fmt::Debug, V: fmt::Debug {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a TreeMap<K, V> where K: Ord {
+    type Item = (&'a K, &'a V);
+    type IntoIter = MapIter<'a, K, V>;
+
+    fn into_iter(self) -> MapIter<'a, K, V> {
+        self.iter()
+    }
+}
+
+// Iterator over the entries of a tree map, in key order.
+pub struct MapIter<'a, K: 'a, V: 'a> {
+    entries: iter::Iter<'a, (K, V)>,
+}
+
+impl<'a, K, V> Iterator for MapIter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        self.entries.next().map(|entry| (&entry.0, &entry.1))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.entries.size_hint()
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator for MapIter<'a, K, V> {
+    fn next_back(&mut self) -> Option<(&'a K, &'a V)> {
+        self.entries.next_back().map(|entry| (&entry.0, &entry.1))
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for MapIter<'a, K, V> {}
+
+impl<'a, K, V> FusedIterator for MapIter<'a, K, V> {}
@@ -0,0 +1,220 @@
+use std::cmp::Ordering;
+use std::ops::Add;
+
+// A summary of a sequence of values, kept on every node of a tree array for the values of its
+// subtree, such as their sum, their maximum, or how many of them match some property.
+// Summaries form a monoid: they are combined in index order, and `identity` summarizes no values
+// at all, so that combining with it changes nothing. Nodes combine the summaries of their
+// children with the measure of their own value as rotations, splits and joins change the shape
+// of the tree, so that the summary of any range is at hand in logarithmic time.
+//
+// The type implementing the trait is the summary itself. The unit type summarizes nothing; it
+// is the default for tree arrays, and costs neither space nor time.
+pub trait Measure<V>: Clone {
+    // The summary of no values.
+    fn identity() -> Self;
+
+    // The summary of a single value.
+    fn measure(value: &V) -> Self;
+
+    // The summary of the values summarized by `self`, followed by those summarized by `other`.
+    fn combine(&self, other: &Self) -> Self;
+
+    // The summary of the same values in reverse order, for when a range gets reversed.
+    // Summaries that do not depend on the order of the values, like sums, are left as they are.
+    fn reverse(&self) -> Self {
+        self.clone()
+    }
+
+    // Summaries may also hold back changes to all the values of their subtree, so that updating a
+    // range only marks the root of the range; the summary of a node then already reflects them.
+    // This hands them down as the tree is walked down through the node: they are applied to its
+    // value, and recorded on the summaries of its children. It returns whether the value changed,
+    // for the node to measure it anew.
+    // Values read through shared references may not have received them yet, so such summaries
+    // are best kept behind a type that only reads values through operations that walk down.
+    fn push_down(&mut self, _value: &mut V, _left: Option<&mut Self>, _right: Option<&mut Self>)
+        -> bool {
+        false
+    }
+}
+
+impl<V> Measure<V> for () {
+    fn identity() {}
+
+    fn measure(_: &V) {}
+
+    fn combine(&self, _: &()) {}
+}
+
+// Summary of the sum of a sequence of values, for prefix sums.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Sum<V>(pub V);
+
+impl<V> Measure<V> for Sum<V> where V: Clone + Default + Add<Output = V> {
+    fn identity() -> Sum<V> {
+        Sum(V::default())
+    }
+
+    fn measure(value: &V) -> Sum<V> {
+        Sum(value.clone())
+    }
+
+    fn combine(&self, other: &Sum<V>) -> Sum<V> {
+        Sum(self.0.clone() + other.0.clone())
+    }
+}
+
+// Values taking up some width, like the length of a chunk of text, or the duration of an audio
+// frame, so that arrays of them can be indexed by offset.
+pub trait Width {
+    fn width(&self) -> usize;
+}
+
+impl Width for String {
+    fn width(&self) -> usize {
+        self.len()
+    }
+}
+
+impl Width for &str {
+    fn width(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<T> Width for Vec<T> {
+    fn width(&self) -> usize {
+        self.len()
+    }
+}
+
+// Summary of the total width of a sequence of values.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TotalWidth(pub usize);
+
+impl<V> Measure<V> for TotalWidth where V: Width {
+    fn identity() -> TotalWidth {
+        TotalWidth(0)
+    }
+
+    fn measure(value: &V) -> TotalWidth {
+        TotalWidth(value.width())
+    }
+
+    fn combine(&self, other: &TotalWidth) -> TotalWidth {
+        TotalWidth(self.0 + other.0)
+    }
+}
+
+// Values carrying flags, up to 64 of them, as the bits of a mask derived from the value, so that
+// arrays of them can find the values having some flag.
+pub trait Flagged {
+    fn flags(&self) -> u64;
+}
+
+// Summary of the flags that any of a sequence of values has.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct AnyFlags(pub u64);
+
+impl<V> Measure<V> for AnyFlags where V: Flagged {
+    fn identity() -> AnyFlags {
+        AnyFlags(0)
+    }
+
+    fn measure(value: &V) -> AnyFlags {
+        AnyFlags(value.flags())
+    }
+
+    fn combine(&self, other: &AnyFlags) -> AnyFlags {
+        AnyFlags(self.0 | other.0)
+    }
+}
+
+// Summary of the smallest and largest values of a sequence, with their positions within it.
+// Positions are kept for the first and the last occurrence of each, so that they can be found
+// again once the values are reversed.
+#[derive(Clone, Debug)]
+pub struct MinMax<V> {
+    len: usize,
+    min: Option<Extremum<V>>,
+    max: Option<Extremum<V>>,
+}
+
+#[derive(Clone, Debug)]
+struct Extremum<V> {
+    value: V,
+    first: usize,
+    last: usize,
+}
+
+impl<V> MinMax<V> {
+    // The smallest value.
+    pub fn min(&self) -> Option<&V> {
+        self.min.as_ref().map(|e| &e.value)
+    }
+
+    // The largest value.
+    pub fn max(&self) -> Option<&V> {
+        self.max.as_ref().map(|e| &e.value)
+    }
+
+    // The position of the first smallest value, like `Iterator::min` picks it.
+    pub fn argmin(&self) -> Option<usize> {
+        self.min.as_ref().map(|e| e.first)
+    }
+
+    // The position of the last largest value, like `Iterator::max` picks it.
+    pub fn argmax(&self) -> Option<usize> {
+        self.max.as_ref().map(|e| e.last)
+    }
+}
+
+impl<V> Extremum<V> where V: Clone {
+    fn shift(&self, offset: usize) -> Extremum<V> {
+        Extremum { value: self.value.clone(), first: self.first + offset, last: self.last + offset }
+    }
+}
+
+// Picks the extremum of two runs of values, the second run starting at `offset`, where `keep`
+// tells whether the value of the first run wins over the value of the second.
+fn pick<V, F>(a: &Option<Extremum<V>>, b: &Option<Extremum<V>>, offset: usize, keep: F)
+    -> Option<Extremum<V>> where V: Ord + Clone, F: Fn(Ordering) -> bool {
+    match (a.as_ref(), b.as_ref()) {
+        (None, None) => None,
+        (Some(a), None) => Some(a.clone()),
+        (None, Some(b)) => Some(b.shift(offset)),
+        (Some(a), Some(b)) => match a.value.cmp(&b.value) {
+            Ordering::Equal => Some(Extremum { last: b.last + offset, ..a.clone() }),
+            order if keep(order) => Some(a.clone()),
+            _ => Some(b.shift(offset)),
+        },
+    }
+}
+
+impl<V> Measure<V> for MinMax<V> where V: Ord + Clone {
+    fn identity() -> MinMax<V> {
+        MinMax { len: 0, min: None, max: None }
+    }
+
+    fn measure(value: &V) -> MinMax<V> {
+        let extremum = Extremum { value: value.clone(), first: 0, last: 0 };
+        MinMax { len: 1, min: Some(extremum.clone()), max: Some(extremum) }
+    }
+
+    fn combine(&self, other: &MinMax<V>) -> MinMax<V> {
+        MinMax {
+            len: self.len + other.len,
+            min: pick(&self.min, &other.min, self.len, |order| order == Ordering::Less),
+            max: pick(&self.max, &other.max, self.len, |order| order == Ordering::Greater),
+        }
+    }
+
+    fn reverse(&self) -> MinMax<V> {
+        let len = self.len;
+        let flip = |e: &Extremum<V>| {
+            Extremum { value: e.value.clone(), first: len - 1 - e.last, last: len - 1 - e.first }
+        };
+        MinMax { len, min: self.min.as_ref().map(&flip), max: self.max.as_ref().map(&flip) }
+    }
+}
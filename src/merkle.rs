@@ -0,0 +1,180 @@
+use std::ops::RangeBounds;
+use measure::Measure;
+use tree_array::TreeArray;
+
+// Modulus of hash arithmetic, the Mersenne prime 2^61 - 1.
+const P: u64 = (1 << 61) - 1;
+
+// Base of the polynomial hash.
+const BASE: u64 = 0x1f2e_3d4c_5b6a_7988 % P;
+
+// Summary of the content of a sequence of values: a polynomial hash of the hashes of the values,
+// in index order and in reverse order, along with the power of the base it spans.
+// Unlike a hash tree of the nodes, it only depends on the values and their order, not on the
+// shape of the tree, so that replicas holding the same values have the same hash, and so that the
+// hash of any range is at hand. It is meant to detect differences, not to resist forgery.
+// Values are hashed from the bytes `ContentBytes` gives for them, which are the same on every
+// platform: through blake3 with the `blake3` feature, and through FNV-1a otherwise. Replicas
+// comparing hashes should agree on the feature.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ContentHash {
+    forward: u64,
+    backward: u64,
+    power: u64,
+}
+
+impl ContentHash {
+    // The hash of the values in index order.
+    pub fn value(&self) -> u64 {
+        self.forward
+    }
+}
+
+fn mul(a: u64, b: u64) -> u64 {
+    (a as u128 * b as u128 % P as u128) as u64
+}
+
+fn add(a: u64, b: u64) -> u64 {
+    (a + b) % P
+}
+
+// Values hashed into content hashes, as a fixed encoding of their content: integers in
+// little-endian order at a fixed width, `usize` and `isize` at 64 bits, chars as their code point,
+// and sequences as their length followed by their items, so that two values that are equal on
+// different platforms write the same bytes.
+pub trait ContentBytes {
+    // Hands the bytes of the value to `write`, in one or more pieces.
+    fn content_bytes<F>(&self, write: &mut F) where F: FnMut(&[u8]);
+}
+
+macro_rules! content_bytes_le {
+    ($($t:ty),*) => ($(
+        impl ContentBytes for $t {
+            fn content_bytes<F>(&self, write: &mut F) where F: FnMut(&[u8]) {
+                write(&self.to_le_bytes());
+            }
+        }
+    )*)
+}
+
+content_bytes_le!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+impl ContentBytes for usize {
+    fn content_bytes<F>(&self, write: &mut F) where F: FnMut(&[u8]) {
+        (*self as u64).content_bytes(write);
+    }
+}
+
+impl ContentBytes for isize {
+    fn content_bytes<F>(&self, write: &mut F) where F: FnMut(&[u8]) {
+        (*self as i64).content_bytes(write);
+    }
+}
+
+impl ContentBytes for bool {
+    fn content_bytes<F>(&self, write: &mut F) where F: FnMut(&[u8]) {
+        write(&[*self as u8]);
+    }
+}
+
+impl ContentBytes for char {
+    fn content_bytes<F>(&self, write: &mut F) where F: FnMut(&[u8]) {
+        (*self as u32).content_bytes(write);
+    }
+}
+
+impl ContentBytes for str {
+    fn content_bytes<F>(&self, write: &mut F) where F: FnMut(&[u8]) {
+        self.len().content_bytes(write);
+        write(self.as_bytes());
+    }
+}
+
+impl ContentBytes for String {
+    fn content_bytes<F>(&self, write: &mut F) where F: FnMut(&[u8]) {
+        self.as_str().content_bytes(write);
+    }
+}
+
+impl<T> ContentBytes for [T] where T: ContentBytes {
+    fn content_bytes<F>(&self, write: &mut F) where F: FnMut(&[u8]) {
+        self.len().content_bytes(write);
+        for item in self {
+            item.content_bytes(write);
+        }
+    }
+}
+
+impl<T> ContentBytes for Vec<T> where T: ContentBytes {
+    fn content_bytes<F>(&self, write: &mut F) where F: FnMut(&[u8]) {
+        self.as_slice().content_bytes(write);
+    }
+}
+
+impl<T> ContentBytes for &T where T: ContentBytes + ?Sized {
+    fn content_bytes<F>(&self, write: &mut F) where F: FnMut(&[u8]) {
+        (**self).content_bytes(write);
+    }
+}
+
+impl<V> Measure<V> for ContentHash where V: ContentBytes {
+    fn identity() -> ContentHash {
+        ContentHash { forward: 0, backward: 0, power: 1 }
+    }
+
+    fn measure(value: &V) -> ContentHash {
+        let hash = hash_value(value) % P;
+        ContentHash { forward: hash, backward: hash, power: BASE }
+    }
+
+    fn combine(&self, other: &ContentHash) -> ContentHash {
+        ContentHash {
+            forward: add(mul(self.forward, other.power), other.forward),
+            backward: add(mul(other.backward, self.power), self.backward),
+            power: mul(self.power, other.power),
+        }
+    }
+
+    fn reverse(&self) -> ContentHash {
+        ContentHash { forward: self.backward, backward: self.forward, power: self.power }
+    }
+}
+
+// The first 8 bytes of the blake3 hash of the content of a value, read in little-endian order.
+#[cfg(feature = "blake3")]
+fn hash_value<V>(value: &V) -> u64 where V: ContentBytes {
+    let mut hasher = ::blake3::Hasher::new();
+    value.content_bytes(&mut |bytes: &[u8]| {
+        hasher.update(bytes);
+    });
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&hasher.finalize().as_bytes()[..8]);
+    u64::from_le_bytes(bytes)
+}
+
+// The 64-bit FNV-1a hash of the content of a value.
+#[cfg(not(feature = "blake3"))]
+fn hash_value<V>(value: &V) -> u64 where V: ContentBytes {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    value.content_bytes(&mut |bytes: &[u8]| {
+        for &byte in bytes {
+            hash = (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    });
+    hash
+}
+
+// Content hashes of arrays measured by them, to check that replicas agree, and to find where
+// they differ by comparing the hashes of their ranges.
+impl<V> TreeArray<V, ContentHash> where V: ContentBytes {
+    // Hash of all values, in constant time.
+    pub fn root_hash(&self) -> u64 {
+        self.measure().value()
+    }
+
+    // Hash of the values in a range, which is the root hash of an array of those values.
+    // Panics if the range is out of bounds.
+    pub fn range_hash<R>(&mut self, range: R) -> u64 where R: RangeBounds<usize> {
+        self.measure_range(range).value()
+    }
+}
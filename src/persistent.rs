@@ -0,0 +1,493 @@
+use std::fmt;
+use std::iter::FromIterator;
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(loom)]
+use loom::sync::Arc;
+#[cfg(not(loom))]
+use std::sync::Arc;
+
+// An optional subtree of a persistent tree array, shared between the versions holding it.
+type Link<V> = Option<Arc<PNode<V>>>;
+
+#[derive(Clone)]
+struct PNode<V> {
+    value: V,
+    size: usize,
+    // Random priority, no lower than those of the nodes of the subtree.
+    priority: u64,
+    left: Link<V>,
+    right: Link<V>,
+}
+
+// A tree array whose versions share their nodes, so that cloning it takes constant time, and
+// each version stays readable, unchanged, as the others get edited. Shared nodes are never
+// changed: an edit makes new copies of the shared nodes on its paths down the tree, and points
+// them at the subtrees left untouched. Nodes held by a single version are edited in place, so
+// that editing a version that no other one shares costs no copies.
+// Since reading must not change the tree either, it is kept balanced by random priorities
+// rather than by splaying: a node's priority is no lower than those of its subtree, which keeps
+// its depth logarithmic in expectation. Like for tree arrays, each node holds the size of its
+// subtree, and edits come down to splits and joins.
+pub struct PersistentTreeArray<V> {
+    root: Link<V>,
+}
+
+// A version of a persistent tree array that can no longer be edited, to be shared between
+// threads. It is Send and Sync when values are, and reading it takes no lock: its nodes are
+// only ever dropped or copied by the array it was published from, never changed.
+pub struct FrozenTreeArray<V> {
+    values: PersistentTreeArray<V>,
+}
+
+// Iterator over the values of a persistent tree array, in index order.
+pub struct PersistentIter<'a, V: 'a> {
+    // Nodes whose value and right subtree remain to be yielded, the next one last.
+    stack: Vec<&'a PNode<V>>,
+    remaining: usize,
+}
+
+// Iterator over the values of a version of a persistent tree array, holding onto the version
+// rather than borrowing the array, so that the array can be edited, or dropped, while iterating.
+// Values are cloned as they are yielded.
+pub struct SnapshotIter<V> {
+    stack: Vec<Arc<PNode<V>>>,
+    remaining: usize,
+}
+
+// Hands out random numbers, scrambling a counter shared by all threads: the priorities of new
+// nodes, and the pivots of selections on tree arrays.
+pub(crate) fn random() -> u64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut x = COUNTER.fetch_add(0x9e37_79b9_7f4a_7c15, Ordering::Relaxed);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+// Borrows a node to edit it, copying it first if other versions hold it, like `Arc::make_mut`.
+fn make_mut<V>(arc: &mut Arc<PNode<V>>) -> &mut PNode<V> where V: Clone {
+    if Arc::get_mut(arc).is_none() {
+        *arc = Arc::new(PNode::clone(arc));
+    }
+    Arc::get_mut(arc).unwrap()
+}
+
+fn size<V>(link: &Link<V>) -> usize {
+    match link {
+        Some(n) => n.size,
+        None => 0,
+    }
+}
+
+fn node<V>(value: V, priority: u64, left: Link<V>, right: Link<V>) -> Link<V> {
+    let size = 1 + size(&left) + size(&right);
+    Some(Arc::new(PNode { value, size, priority, left, right }))
+}
+
+impl<V> PNode<V> {
+    fn update(&mut self) {
+        self.size = 1 + size(&self.left) + size(&self.right);
+    }
+}
+
+// Splits a subtree into its first `k` values and the rest. The nodes along the way are edited in
+// place, once copied if other versions hold them.
+fn split<V>(link: Link<V>, k: usize) -> (Link<V>, Link<V>) where V: Clone {
+    let mut arc = match link {
+        Some(arc) => arc,
+        None => return (None, None),
+    };
+    let n = make_mut(&mut arc);
+    let left_size = size(&n.left);
+    if k <= left_size {
+        let (left, rest) = split(n.left.take(), k);
+        n.left = rest;
+        n.update();
+        (left, Some(arc))
+    } else {
+        let (rest, right) = split(n.right.take(), k - left_size - 1);
+        n.right = rest;
+        n.update();
+        (Some(arc), right)
+    }
+}
+
+// Joins two subtrees, the values of the first one coming first. The root of higher priority
+// stays on top.
+fn join<V>(a: Link<V>, b: Link<V>) -> Link<V> where V: Clone {
+    match (a, b) {
+        (None, b) => b,
+        (a, None) => a,
+        (Some(mut x), Some(y)) if x.priority > y.priority => {
+            let n = make_mut(&mut x);
+            n.right = join(n.right.take(), Some(y));
+            n.update();
+            Some(x)
+        },
+        (x, Some(mut y)) => {
+            let n = make_mut(&mut y);
+            n.left = join(x, n.left.take());
+            n.update();
+            Some(y)
+        },
+    }
+}
+
+impl<V> PersistentTreeArray<V> {
+    pub fn new() -> PersistentTreeArray<V> {
+        PersistentTreeArray { root: None }
+    }
+
+    pub fn len(&self) -> usize {
+        size(&self.root)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    // Fetches the value at a given index, walking down the tree.
+    pub fn get(&self, index: usize) -> Option<&V> {
+        let mut index = index;
+        let mut link = &self.root;
+        while let Some(n) = link {
+            let left_size = size(&n.left);
+            match index {
+                i if i < left_size => link = &n.left,
+                i if i == left_size => return Some(&n.value),
+                i => {
+                    index = i - left_size - 1;
+                    link = &n.right;
+                },
+            }
+        }
+        None
+    }
+
+    pub fn first(&self) -> Option<&V> {
+        self.get(0)
+    }
+
+    pub fn last(&self) -> Option<&V> {
+        self.get(self.len().checked_sub(1)?)
+    }
+
+    pub fn iter(&self) -> PersistentIter<'_, V> {
+        let mut iter = PersistentIter { stack: Vec::new(), remaining: self.len() };
+        iter.push_left(&self.root);
+        iter
+    }
+
+    // Iterates over the current version, whatever edits are made to the array meanwhile, as
+    // read-copy-update readers do: the nodes of the version are kept as long as the iterator
+    // needs them, and the first edit of each node since then copies it.
+    pub fn snapshot_iter(&self) -> SnapshotIter<V> {
+        let mut iter = SnapshotIter { stack: Vec::new(), remaining: self.len() };
+        iter.push_left(self.root.clone());
+        iter
+    }
+
+    // Freezes the current version, in constant time, for readers on other threads to hold while
+    // this array keeps being edited. The first edit of each node since then copies it.
+    pub fn publish(&self) -> Arc<FrozenTreeArray<V>> {
+        Arc::new(FrozenTreeArray { values: self.clone() })
+    }
+
+    // Whether both arrays are versions sharing the same tree, in which case they hold the same
+    // values. Arrays holding the same values may not share their tree though.
+    pub fn ptr_eq(&self, other: &PersistentTreeArray<V>) -> bool {
+        match (&self.root, &other.root) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+}
+
+impl<V> PersistentTreeArray<V> where V: Clone {
+    // Mutably borrows the value at a given index. The nodes down to it are copied if other
+    // versions hold them, so that this array holds them alone.
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut V> {
+        if index >= self.len() {
+            return None;
+        }
+        let mut index = index;
+        let mut link = &mut self.root;
+        while let Some(arc) = link {
+            let n = make_mut(arc);
+            let left_size = size(&n.left);
+            match index {
+                i if i < left_size => link = &mut n.left,
+                i if i == left_size => return Some(&mut n.value),
+                i => {
+                    index = i - left_size - 1;
+                    link = &mut n.right;
+                },
+            }
+        }
+        None
+    }
+
+    // Overwrites the value at a given index in the array.
+    // Panics if the index is out of bounds.
+    pub fn set(&mut self, index: usize, value: V) {
+        let len = self.len();
+        match self.get_mut(index) {
+            Some(v) => *v = value,
+            None => panic!("set index (is {}) should be < len (is {})", index, len),
+        }
+    }
+
+    // A copy of the array, with the value at a given index overwritten, leaving this one as it
+    // is. Panics if the index is out of bounds.
+    pub fn update(&self, index: usize, value: V) -> PersistentTreeArray<V> {
+        let mut array = self.clone();
+        array.set(index, value);
+        array
+    }
+
+    // Inserts a value at a given index, shifting the values after it.
+    // Panics if the index is greater than the length.
+    pub fn insert(&mut self, index: usize, value: V) {
+        let len = self.len();
+        assert!(index <= len, "insertion index (is {}) should be <= len (is {})", index, len);
+        let (left, right) = split(self.root.take(), index);
+        let single = node(value, random(), None, None);
+        self.root = join(join(left, single), right);
+    }
+
+    pub fn push(&mut self, value: V) {
+        let len = self.len();
+        self.insert(len, value);
+    }
+
+    pub fn push_front(&mut self, value: V) {
+        self.insert(0, value);
+    }
+
+    // Removes the value at a given index, and returns it. The array is left untouched if nothing
+    // is found at that index.
+    pub fn remove(&mut self, index: usize) -> Option<V> {
+        if index >= self.len() {
+            return None;
+        }
+        let (left, rest) = split(self.root.take(), index);
+        let (single, right) = split(rest, 1);
+        self.root = join(left, right);
+        single.map(|arc| match Arc::try_unwrap(arc) {
+            Ok(n) => n.value,
+            Err(arc) => arc.value.clone(),
+        })
+    }
+
+    pub fn pop(&mut self) -> Option<V> {
+        let len = self.len();
+        self.remove(len.checked_sub(1)?)
+    }
+
+    pub fn pop_front(&mut self) -> Option<V> {
+        self.remove(0)
+    }
+
+    // The values before an index, and those from it on, as two arrays.
+    // Panics if the index is greater than the length.
+    pub fn split_at(&self, at: usize) -> (PersistentTreeArray<V>, PersistentTreeArray<V>) {
+        let mut left = self.clone();
+        let right = left.split_off(at);
+        (left, right)
+    }
+
+    // Removes the values from an index on, and returns them as an array of their own.
+    // Panics if the index is greater than the length.
+    pub fn split_off(&mut self, at: usize) -> PersistentTreeArray<V> {
+        let len = self.len();
+        assert!(at <= len, "split index (is {}) should be <= len (is {})", at, len);
+        let (left, right) = split(self.root.take(), at);
+        self.root = left;
+        PersistentTreeArray { root: right }
+    }
+
+    // The values of both arrays, one after the other, as a new array, in logarithmic time.
+    pub fn concat(&self, other: &PersistentTreeArray<V>) -> PersistentTreeArray<V> {
+        PersistentTreeArray { root: join(self.root.clone(), other.root.clone()) }
+    }
+
+    // Appends the values of another array, in logarithmic time.
+    pub fn append(&mut self, other: PersistentTreeArray<V>) {
+        self.root = join(self.root.take(), other.root);
+    }
+}
+
+impl<V> FrozenTreeArray<V> {
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&V> {
+        self.values.get(index)
+    }
+
+    pub fn first(&self) -> Option<&V> {
+        self.values.first()
+    }
+
+    pub fn last(&self) -> Option<&V> {
+        self.values.last()
+    }
+
+    pub fn iter(&self) -> PersistentIter<'_, V> {
+        self.values.iter()
+    }
+
+    pub fn snapshot_iter(&self) -> SnapshotIter<V> {
+        self.values.snapshot_iter()
+    }
+
+    // An array starting from this version, to be edited on its own, in constant time.
+    pub fn thaw(&self) -> PersistentTreeArray<V> {
+        self.values.clone()
+    }
+}
+
+impl<'a, V> PersistentIter<'a, V> {
+    // Stacks the nodes on the leftmost path down a subtree.
+    fn push_left(&mut self, mut link: &'a Link<V>) {
+        while let Some(n) = link {
+            self.stack.push(n);
+            link = &n.left;
+        }
+    }
+}
+
+impl<'a, V> Iterator for PersistentIter<'a, V> {
+    type Item = &'a V;
+
+    fn next(&mut self) -> Option<&'a V> {
+        let n = self.stack.pop()?;
+        self.push_left(&n.right);
+        self.remaining -= 1;
+        Some(&n.value)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, V> ExactSizeIterator for PersistentIter<'a, V> {}
+
+impl<V> SnapshotIter<V> {
+    // Stacks the nodes on the leftmost path down a subtree.
+    fn push_left(&mut self, mut link: Link<V>) {
+        while let Some(n) = link {
+            link = n.left.clone();
+            self.stack.push(n);
+        }
+    }
+}
+
+impl<V> Iterator for SnapshotIter<V> where V: Clone {
+    type Item = V;
+
+    fn next(&mut self) -> Option<V> {
+        let n = self.stack.pop()?;
+        self.push_left(n.right.clone());
+        self.remaining -= 1;
+        Some(n.value.clone())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<V> ExactSizeIterator for SnapshotIter<V> where V: Clone {}
+
+impl<'a, V> IntoIterator for &'a FrozenTreeArray<V> {
+    type Item = &'a V;
+    type IntoIter = PersistentIter<'a, V>;
+
+    fn into_iter(self) -> PersistentIter<'a, V> {
+        self.iter()
+    }
+}
+
+impl<'a, V> IntoIterator for &'a PersistentTreeArray<V> {
+    type Item = &'a V;
+    type IntoIter = PersistentIter<'a, V>;
+
+    fn into_iter(self) -> PersistentIter<'a, V> {
+        self.iter()
+    }
+}
+
+// Cloning only shares the tree, in constant time.
+impl<V> Clone for PersistentTreeArray<V> {
+    fn clone(&self) -> PersistentTreeArray<V> {
+        PersistentTreeArray { root: self.root.clone() }
+    }
+}
+
+impl<V> Default for PersistentTreeArray<V> {
+    fn default() -> PersistentTreeArray<V> {
+        PersistentTreeArray::new()
+    }
+}
+
+impl<V> FromIterator<V> for PersistentTreeArray<V> where V: Clone {
+    fn from_iter<I>(iter: I) -> PersistentTreeArray<V> where I: IntoIterator<Item = V> {
+        let mut array = PersistentTreeArray::new();
+        array.extend(iter);
+        array
+    }
+}
+
+impl<V> Extend<V> for PersistentTreeArray<V> where V: Clone {
+    fn extend<I>(&mut self, iter: I) where I: IntoIterator<Item = V> {
+        for value in iter {
+            self.push(value);
+        }
+    }
+}
+
+impl<V> From<Vec<V>> for PersistentTreeArray<V> where V: Clone {
+    fn from(values: Vec<V>) -> PersistentTreeArray<V> {
+        values.into_iter().collect()
+    }
+}
+
+impl<V, W> PartialEq<PersistentTreeArray<W>> for PersistentTreeArray<V> where V: PartialEq<W> {
+    fn eq(&self, other: &PersistentTreeArray<W>) -> bool {
+        self.len() == other.len() && self.iter().zip(other.iter()).all(|(a, b)| a == b)
+    }
+}
+
+impl<V> Eq for PersistentTreeArray<V> where V: Eq {}
+
+impl<V, W> PartialEq<[W]> for PersistentTreeArray<V> where V: PartialEq<W> {
+    fn eq(&self, other: &[W]) -> bool {
+        self.len() == other.len() && self.iter().zip(other.iter()).all(|(a, b)| a == b)
+    }
+}
+
+impl<V, W> PartialEq<[W]> for FrozenTreeArray<V> where V: PartialEq<W> {
+    fn eq(&self, other: &[W]) -> bool {
+        self.values == *other
+    }
+}
+
+impl<V> fmt::Debug for FrozenTreeArray<V> where V: fmt::Debug {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.values.fmt(f)
+    }
+}
+
+impl<V> fmt::Debug for PersistentTreeArray<V> where V: fmt::Debug {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
@@ -0,0 +1,186 @@
+use std::fmt;
+use std::iter::FromIterator;
+use std::mem;
+use std::ops::RangeBounds;
+use measure::{TotalWidth, Width};
+use tree_array::{bounds, TreeArray};
+
+// A sequence stored as runs of equal values, each run a value along with how many times it
+// repeats, in a tree array indexed by the total length of the runs. Sequences dominated by long
+// runs, like highlighting spans or tile maps, take as little room as they have runs.
+// Runs are split when an edit falls within them, and merged when neighbouring runs end up
+// holding equal values.
+pub struct RleTreeArray<V> where V: PartialEq + Clone {
+    runs: TreeArray<Run<V>, TotalWidth>,
+}
+
+#[derive(Clone, Debug)]
+struct Run<V> {
+    value: V,
+    len: usize,
+}
+
+impl<V> Width for Run<V> {
+    fn width(&self) -> usize {
+        self.len
+    }
+}
+
+impl<V> RleTreeArray<V> where V: PartialEq + Clone {
+    pub fn new() -> RleTreeArray<V> {
+        RleTreeArray { runs: TreeArray::default() }
+    }
+
+    // Number of values, in constant time.
+    pub fn len(&self) -> usize {
+        self.runs.total_width()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.runs.is_empty()
+    }
+
+    // Number of runs.
+    pub fn run_count(&self) -> usize {
+        self.runs.len()
+    }
+
+    // Fetches the value at an index. Its run is splayed to the root, so that lookups take
+    // logarithmic time amortized, which is why they need a mutable reference.
+    pub fn get(&mut self, index: usize) -> Option<&V> {
+        self.runs.get_at_offset(index).map(|run| &run.value)
+    }
+
+    // Inserts `count` copies of a value at an index.
+    // Panics if the index is greater than the length.
+    pub fn insert_run(&mut self, index: usize, value: V, count: usize) {
+        let len = self.len();
+        assert!(index <= len, "insertion index (is {}) should be <= len (is {})", index, len);
+        if count == 0 {
+            return;
+        }
+        let k = self.split_at(index);
+        self.runs.insert(k, Run { value, len: count });
+        self.merge(k + 1);
+        self.merge(k);
+    }
+
+    // Panics if the index is greater than the length.
+    pub fn insert(&mut self, index: usize, value: V) {
+        self.insert_run(index, value, 1);
+    }
+
+    pub fn push(&mut self, value: V) {
+        let len = self.len();
+        self.insert_run(len, value, 1);
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<V> {
+        let value = self.get(index)?.clone();
+        self.remove_range(index..index + 1);
+        Some(value)
+    }
+
+    // Panics if the range is out of bounds.
+    pub fn remove_range<R>(&mut self, range: R) where R: RangeBounds<usize> {
+        let (start, end) = bounds(range, self.len());
+        let i = self.split_at(start);
+        let j = self.split_at(end);
+        self.runs.remove_range(i..j);
+        self.merge(i);
+    }
+
+    // Overwrites the value at an index. Panics if the index is out of bounds.
+    pub fn set(&mut self, index: usize, value: V) {
+        self.fill(index..index + 1, value);
+    }
+
+    // Overwrites all values in a range with a single run of a value.
+    // Panics if the range is out of bounds.
+    pub fn fill<R>(&mut self, range: R, value: V) where R: RangeBounds<usize> {
+        let (start, end) = bounds(range, self.len());
+        self.remove_range(start..end);
+        self.insert_run(start, value, end - start);
+    }
+
+    // Iterates over the values, each run repeating its value.
+    pub fn iter(&self) -> impl Iterator<Item = &V> {
+        self.runs.iter().flat_map(|run| (0..run.len).map(move |_| &run.value))
+    }
+
+    // Iterates over the runs, as each value along with how many times it repeats.
+    pub fn runs(&self) -> impl Iterator<Item = (&V, usize)> {
+        self.runs.iter().map(|run| (&run.value, run.len))
+    }
+
+    // Depth of the tree of runs, to check that lookups keep it shallow.
+    #[cfg(test)]
+    pub(crate) fn depth(&self) -> usize {
+        self.runs.depth()
+    }
+
+    // Makes a run start at an index, splitting the run holding it if need be, and returns the
+    // index of that run.
+    fn split_at(&mut self, index: usize) -> usize {
+        let (k, before) = match self.runs.locate_by_measure_mut(|width| width.0 > index) {
+            Some(found) => found,
+            None => return self.runs.len(),
+        };
+        let at = index - before.0;
+        if at == 0 {
+            return k;
+        }
+        let value = self.runs[k].value.clone();
+        let len = self.runs.modify(k, |run| mem::replace(&mut run.len, at) - at).unwrap();
+        self.runs.insert(k + 1, Run { value, len });
+        k + 1
+    }
+
+    // Merges the run at an index into the one before it, if they hold equal values.
+    fn merge(&mut self, k: usize) {
+        if k == 0 || k >= self.runs.len() || self.runs[k - 1].value != self.runs[k].value {
+            return;
+        }
+        let tail = self.runs.remove(k).unwrap();
+        self.runs.modify(k - 1, |run| run.len += tail.len);
+    }
+}
+
+impl<V> Default for RleTreeArray<V> where V: PartialEq + Clone {
+    fn default() -> RleTreeArray<V> {
+        RleTreeArray::new()
+    }
+}
+
+impl<V> Clone for RleTreeArray<V> where V: PartialEq + Clone {
+    fn clone(&self) -> RleTreeArray<V> {
+        RleTreeArray { runs: self.runs.clone() }
+    }
+}
+
+// Equal values in a row are gathered into runs as they come.
+impl<V> FromIterator<V> for RleTreeArray<V> where V: PartialEq + Clone {
+    fn from_iter<I>(iter: I) -> RleTreeArray<V> where I: IntoIterator<Item = V> {
+        let mut runs: Vec<Run<V>> = Vec::new();
+        for value in iter {
+            match runs.last_mut() {
+                Some(run) if run.value == value => run.len += 1,
+                _ => runs.push(Run { value, len: 1 }),
+            }
+        }
+        RleTreeArray { runs: runs.into_iter().collect() }
+    }
+}
+
+impl<V> PartialEq for RleTreeArray<V> where V: PartialEq + Clone {
+    fn eq(&self, other: &RleTreeArray<V>) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+// Formats as a list of runs, like `[(1, 3), (2, 1)]` for `[1, 1, 1, 2]`.
+impl<V> fmt::Debug for RleTreeArray<V> where V: PartialEq + Clone + fmt::Debug {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.runs()).finish()
+    }
+}
@@ -0,0 +1,151 @@
+use std::fmt;
+use std::ops::{Index, RangeBounds};
+use iter::Iter;
+use measure::Measure;
+use tree_array::{bounds, join, TreeArray};
+
+// A borrowed view over a range of indices of a tree array, in the manner of `&[T]`.
+// It holds no values of its own: reading through it walks down the underlying tree, and
+// re-slicing it only narrows the range.
+pub struct TreeSlice<'a, V: 'a, M: 'a = ()> {
+    tree: &'a TreeArray<V, M>,
+    start: usize,
+    end: usize,
+}
+
+impl<'a, V, M> TreeSlice<'a, V, M> where M: Measure<V> {
+    pub(crate) fn new(tree: &'a TreeArray<V, M>, start: usize, end: usize) -> TreeSlice<'a, V, M> {
+        TreeSlice { tree, start, end }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    // Fetches the value at a given index in the slice, or None if it is past its end.
+    pub fn get(&self, index: usize) -> Option<&'a V> {
+        if index >= self.len() {
+            return None;
+        }
+        self.tree.get(self.start + index)
+    }
+
+    pub fn first(&self) -> Option<&'a V> {
+        self.get(0)
+    }
+
+    pub fn last(&self) -> Option<&'a V> {
+        self.len().checked_sub(1).and_then(|i| self.get(i))
+    }
+
+    // Iterates over the values of the slice in index order.
+    pub fn iter(&self) -> Iter<'a, V, M> {
+        self.tree.iter_range(self.start..self.end)
+    }
+
+    // Narrows the view to a range of indices within the slice.
+    // Panics if the range is out of bounds.
+    pub fn slice<R>(&self, range: R) -> TreeSlice<'a, V, M> where R: RangeBounds<usize> {
+        let (start, end) = bounds(range, self.len());
+        TreeSlice::new(self.tree, self.start + start, self.start + end)
+    }
+}
+
+impl<'a, V, M> Clone for TreeSlice<'a, V, M> {
+    fn clone(&self) -> TreeSlice<'a, V, M> {
+        *self
+    }
+}
+
+impl<'a, V, M> Copy for TreeSlice<'a, V, M> {}
+
+impl<'a, V, M> fmt::Debug for TreeSlice<'a, V, M> where V: fmt::Debug, M: Measure<V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<'a, V, M> Index<usize> for TreeSlice<'a, V, M> where M: Measure<V> {
+    type Output = V;
+
+    fn index(&self, index: usize) -> &V {
+        match self.get(index) {
+            Some(v) => v,
+            None => panic!("index out of bounds: the len is {} but the index is {}", self.len(), index),
+        }
+    }
+}
+
+impl<'a, V, M> IntoIterator for TreeSlice<'a, V, M> where M: Measure<V> {
+    type Item = &'a V;
+    type IntoIter = Iter<'a, V, M>;
+
+    fn into_iter(self) -> Iter<'a, V, M> {
+        self.iter()
+    }
+}
+
+impl<'a, 'b, V, W, M, N> PartialEq<TreeSlice<'b, W, N>> for TreeSlice<'a, V, M>
+    where V: PartialEq<W>, M: Measure<V>, N: Measure<W> {
+    fn eq(&self, other: &TreeSlice<'b, W, N>) -> bool {
+        self.len() == other.len() && self.iter().zip(other.iter()).all(|(a, b)| a == b)
+    }
+}
+
+impl<'a, V, M> Eq for TreeSlice<'a, V, M> where V: Eq, M: Measure<V> {}
+
+impl<'a, V, W, M> PartialEq<[W]> for TreeSlice<'a, V, M> where V: PartialEq<W>, M: Measure<V> {
+    fn eq(&self, other: &[W]) -> bool {
+        self.len() == other.len() && self.iter().zip(other.iter()).all(|(a, b)| a == b)
+    }
+}
+
+impl<'a, 'b, V, W, M> PartialEq<&'b [W]> for TreeSlice<'a, V, M>
+    where V: PartialEq<W>, M: Measure<V> {
+    fn eq(&self, other: &&'b [W]) -> bool {
+        *self == **other
+    }
+}
+
+// The two halves of a tree array split at an index, which can be edited independently, at the
+// same time. Each half is a tree array of its own, split out in logarithmic time; the guard joins
+// them back into the original array when dropped. Should it be leaked instead, the original array
+// is left empty.
+pub struct SplitAtMut<'a, V: 'a, M: 'a = ()> where M: Measure<V> {
+    tree: &'a mut TreeArray<V, M>,
+    left: TreeArray<V, M>,
+    right: TreeArray<V, M>,
+}
+
+impl<'a, V, M> SplitAtMut<'a, V, M> where M: Measure<V> {
+    pub(crate) fn new(tree: &'a mut TreeArray<V, M>, mid: usize) -> SplitAtMut<'a, V, M> {
+        let right = tree.split_off(mid);
+        let left = TreeArray { root: tree.root.take() };
+        SplitAtMut { tree, left, right }
+    }
+
+    // The values before the split index.
+    pub fn left(&mut self) -> &mut TreeArray<V, M> {
+        &mut self.left
+    }
+
+    // The values from the split index onwards.
+    pub fn right(&mut self) -> &mut TreeArray<V, M> {
+        &mut self.right
+    }
+
+    // Both halves at once, in order.
+    pub fn halves(&mut self) -> (&mut TreeArray<V, M>, &mut TreeArray<V, M>) {
+        (&mut self.left, &mut self.right)
+    }
+}
+
+impl<'a, V, M> Drop for SplitAtMut<'a, V, M> where M: Measure<V> {
+    fn drop(&mut self) {
+        self.tree.root = join(self.left.root.take(), self.right.root.take());
+    }
+}
@@ -0,0 +1,157 @@
+use std::fmt;
+use std::iter::FromIterator;
+use iter::Iter;
+use tree_array::TreeArray;
+
+// A tree array whose values are kept sorted, making it an order-statistic multiset: besides
+// finding values, it tells how many values are smaller than a given one, and which value comes
+// at a given rank, all by walking down the tree using subtree sizes.
+// Queries splay the deepest node they reach to the root, which is why they take the array
+// mutably: they then take logarithmic time amortized, even though values inserted in sorted
+// order would otherwise leave a spine.
+// Equal values are kept in the order in which they were inserted.
+pub struct SortedTreeArray<V> where V: Ord {
+    tree: TreeArray<V>,
+}
+
+impl<V> SortedTreeArray<V> where V: Ord {
+    pub fn new() -> SortedTreeArray<V> {
+        SortedTreeArray { tree: TreeArray::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    // Inserts a value after all values not greater than it, and returns its index.
+    pub fn insert_sorted(&mut self, value: V) -> usize {
+        let index = self.tree.split_point(|v| *v <= value);
+        self.tree.insert(index, value);
+        index
+    }
+
+    // Removes one value equal to `x` and returns it, or None if there is none.
+    pub fn remove_value(&mut self, x: &V) -> Option<V> {
+        match self.tree.splay_search_by(|v| v.cmp(x)) {
+            Ok(index) => self.tree.remove(index),
+            Err(_) => None,
+        }
+    }
+
+    // Removes the value at a given rank and returns it, or None if there are not that many values.
+    pub fn remove_index(&mut self, k: usize) -> Option<V> {
+        self.tree.remove(k)
+    }
+
+    // Number of values smaller than `x`, which is the index of the first value equal to it if
+    // there is one.
+    pub fn rank(&mut self, x: &V) -> usize {
+        self.tree.split_point(|v| v < x)
+    }
+
+    // Number of values equal to `x`.
+    pub fn count(&mut self, x: &V) -> usize {
+        self.tree.split_point(|v| v <= x) - self.rank(x)
+    }
+
+    // The `k`-th smallest value, counting from zero, or None if there are not that many values.
+    pub fn select(&mut self, k: usize) -> Option<&V> {
+        self.tree.splay_get(k)
+    }
+
+    // The `k`-th smallest value, counting from zero; the same as `select`.
+    pub fn kth(&mut self, k: usize) -> Option<&V> {
+        self.select(k)
+    }
+
+    // The middle value, or the lower of the two middle values if there is an even number of
+    // them. It is found through subtree sizes, in logarithmic time amortized.
+    pub fn median(&mut self) -> Option<&V> {
+        match self.len() {
+            0 => None,
+            len => self.select((len - 1) / 2),
+        }
+    }
+
+    pub fn contains(&mut self, x: &V) -> bool {
+        self.tree.splay_search_by(|v| v.cmp(x)).is_ok()
+    }
+
+    // The smallest value.
+    pub fn first(&mut self) -> Option<&V> {
+        self.tree.splay_get(0)
+    }
+
+    // The largest value.
+    pub fn last(&mut self) -> Option<&V> {
+        let len = self.len();
+        self.tree.splay_get(len.checked_sub(1)?)
+    }
+
+    // Iterates over the values in increasing order.
+    pub fn iter(&self) -> Iter<'_, V> {
+        self.tree.iter()
+    }
+
+    // The underlying array, which can be read but not modified, lest it lose its order.
+    pub fn as_tree_array(&self) -> &TreeArray<V> {
+        &self.tree
+    }
+
+    pub fn into_tree_array(self) -> TreeArray<V> {
+        self.tree
+    }
+
+    // Depth of the tree, to check that queries keep it shallow.
+    #[cfg(test)]
+    pub(crate) fn depth(&self) -> usize {
+        self.tree.depth()
+    }
+}
+
+impl<V> Default for SortedTreeArray<V> where V: Ord {
+    fn default() -> SortedTreeArray<V> {
+        SortedTreeArray::new()
+    }
+}
+
+impl<V> Clone for SortedTreeArray<V> where V: Ord + Clone {
+    fn clone(&self) -> SortedTreeArray<V> {
+        SortedTreeArray { tree: self.tree.clone() }
+    }
+}
+
+// Turning an array into a sorted one sorts it.
+impl<V> From<TreeArray<V>> for SortedTreeArray<V> where V: Ord {
+    fn from(mut tree: TreeArray<V>) -> SortedTreeArray<V> {
+        tree.sort();
+        SortedTreeArray { tree }
+    }
+}
+
+impl<V> FromIterator<V> for SortedTreeArray<V> where V: Ord {
+    fn from_iter<I>(iter: I) -> SortedTreeArray<V> where I: IntoIterator<Item = V> {
+        let mut values: Vec<V> = iter.into_iter().collect();
+        values.sort();
+        SortedTreeArray { tree: TreeArray::from(values) }
+    }
+}
+
+impl<V> fmt::Debug for SortedTreeArray<V> where V: Ord + fmt::Debug {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.tree.fmt(f)
+    }
+}
+
+impl<'a, V> IntoIterator for &'a SortedTreeArray<V> where V: Ord {
+    type Item = &'a V;
+    type IntoIter = Iter<'a, V>;
+
+    fn into_iter(self) -> Iter<'a, V> {
+        self.iter()
+    }
+}
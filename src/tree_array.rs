@@ -1,155 +1,1817 @@
+use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::mem;
+use std::ops::{Add, Bound, Index, IndexMut, RangeBounds};
+use cursor::{Cursor, CursorMut};
+use error::Error;
+use iter::{ExtractIf, IntoIter, Iter, IterMut, Matches};
+use measure::{AnyFlags, Flagged, Measure, MinMax, Sum, TotalWidth, Width};
+use persistent::random;
+use slice::{SplitAtMut, TreeSlice};
+use std::cmp::Ordering;
+use std::collections::{LinkedList, VecDeque};
 use std::cmp::Ordering::{Less, Equal, Greater};
 
-#[derive(Clone)]
-pub struct Node<V> where V: std::fmt::Display {
+// An optional subtree, as found on each side of a node.
+pub type Tree<V, M = ()> = Option<Box<Node<V, M>>>;
+
+pub struct Node<V, M = ()> {
     pub value: V,
-    size: usize,
-    left: Option<Box<Node<V>>>,
-    right: Option<Box<Node<V>>>,
+    pub(crate) size: usize,
+    pub(crate) left: Tree<V, M>,
+    pub(crate) right: Tree<V, M>,
+    // Whether the subtree is to be read mirrored, its children not being swapped yet.
+    pub(crate) reversed: bool,
+    // Summary of the values of the subtree, in index order, pending reversal included.
+    pub(crate) summary: M,
+    // Measure of the value of the node alone, kept so that updates only combine summaries.
+    pub(crate) own: M,
+}
+
+impl<V, M> Node<V, M> {
+    // Index of the current node in the slice of the list corresponding to the subtree in which
+    // it is root.
+    // For instance, in a tree like this:
+    //
+    //         b
+    //        / \
+    //       a   x
+    //          / \
+    //         c   d
+    //
+    // … node x has relative index 1 (in the slice cxd, which is a part of abcxd).
+    // It only holds once pending reversals on the node and its ancestors are pushed down.
+    #[inline(always)]
+    pub fn rel_index(&self) -> usize {
+        size_of(&self.left)
+    }
+
+    // Children of the node in index order, for readers that cannot push reversals down.
+    // `flip` tells whether a pending reversal on an ancestor mirrors the node; the children are
+    // returned along with whether they are mirrored.
+    #[inline(always)]
+    pub(crate) fn sides(&self, flip: bool) -> (&Tree<V, M>, &Tree<V, M>, bool) {
+        let flip = flip != self.reversed;
+        if flip {
+            (&self.right, &self.left, flip)
+        } else {
+            (&self.left, &self.right, flip)
+        }
+    }
+}
+
+impl<V, M> Node<V, M> where M: Measure<V> {
+    pub fn new(v: V, l: Tree<V, M>, r: Tree<V, M>) -> Node<V, M> {
+        let own = M::measure(&v);
+        let mut node = Node {
+            value: v,
+            size: 0,
+            left: l,
+            right: r,
+            reversed: false,
+            summary: M::identity(),
+            own,
+        };
+        node.update();
+        node
+    }
+
+    // Recomputes the size and summary of the node from those of its children, once they changed.
+    #[inline(always)]
+    pub(crate) fn update(&mut self) {
+        self.size = 1 + size_of(&self.left) + size_of(&self.right);
+        self.summary = summary_of(&self.left).combine(&self.own).combine(&summary_of(&self.right));
+    }
+
+    // Measures the value of the node anew, once it changed, and updates the node.
+    #[inline(always)]
+    pub(crate) fn remeasure(&mut self) {
+        self.own = M::measure(&self.value);
+        self.update();
+    }
+
+    // Marks the subtree as mirrored, or no longer mirrored.
+    #[inline(always)]
+    pub(crate) fn toggle_reversed(&mut self) {
+        self.reversed = !self.reversed;
+        self.summary = self.summary.reverse();
+    }
+
+    // Swaps the children of the node if it holds a pending reversal, handing the reversal down to
+    // them, along with the changes held back by its summary.
+    // This must be done before walking down a node through a mutable reference.
+    #[inline(always)]
+    pub(crate) fn push_down(&mut self) {
+        if self.reversed {
+            mem::swap(&mut self.left, &mut self.right);
+            for child in self.left.iter_mut().chain(self.right.iter_mut()) {
+                child.toggle_reversed();
+            }
+            self.reversed = false;
+        }
+        let left = self.left.as_mut().map(|n| &mut n.summary);
+        let right = self.right.as_mut().map(|n| &mut n.summary);
+        if self.summary.push_down(&mut self.value, left, right) {
+            self.own = M::measure(&self.value);
+        }
+    }
+
+    #[inline(always)]
+    pub fn remove_left(&mut self) -> Tree<V, M> {
+        let left = self.left.take();
+        self.update();
+        left
+    }
+
+    #[inline(always)]
+    pub fn remove_right(&mut self) -> Tree<V, M> {
+        let right = self.right.take();
+        self.update();
+        right
+    }
+}
+
+pub struct TreeArray<V, M = ()> {
+    pub(crate) root: Tree<V, M>
+}
+
+// A tree array is a (preferably balanced) binary tree representing a map from indices to
+// values, just like an array, where inserting a value increments indices on the right.
+// It relies on maintaining the number of nodes in the subtree on each node, and, through a
+// measure, a summary of their values.
+impl<V, M> TreeArray<V, M> where M: Measure<V> {
+    // Fetches value at a given index in the array.
+    // The result is an option; it is None if nothing is found at that index.
+    // The tree is walked down without being restructured, so that it can be read from behind a
+    // shared reference.
+    pub fn get(&self, index: usize) -> Option<&V> {
+        self.node_at(index).map(|node| &node.value)
+    }
+
+    // Fetches value at a given index in the array, splaying it to the root.
+    // Accessing nearby indices afterwards is cheaper, which is what amortized workloads want.
+    pub fn splay_get(&mut self, index: usize) -> Option<&V> {
+        if !self.splay_at(index) {
+            return None;
+        }
+        self.root.as_ref().map(|root| &root.value)
+    }
+
+    // Iterates over the values of the array in index order.
+    pub fn iter(&self) -> Iter<'_, V, M> {
+        Iter::new(&self.root)
+    }
+
+    // Iterates over the values in a range of indices, in index order.
+    // The iterator starts by walking down to the start of the range, rather than skipping values
+    // from the front. Panics if the range is out of bounds.
+    pub fn iter_range<R>(&self, range: R) -> Iter<'_, V, M> where R: RangeBounds<usize> {
+        let (start, end) = bounds(range, self.len());
+        let mut iter = self.iter();
+        iter.seek(start);
+        iter.advance_back_by(self.len() - end).unwrap();
+        iter
+    }
+
+    // Borrows a range of indices of the array as a view, which can be read, iterated, compared and
+    // sliced further without copying. Panics if the range is out of bounds.
+    // It stands in for indexing by a range, `&array[range]`, which `Index` cannot give: `index`
+    // must return a reference, and a range of a tree array is no value held in memory to point
+    // to, but a view built on demand.
+    pub fn slice<R>(&self, range: R) -> TreeSlice<'_, V, M> where R: RangeBounds<usize> {
+        let (start, end) = bounds(range, self.len());
+        TreeSlice::new(self, start, end)
+    }
+
+    // Moves the values of the array into a vector, in index order.
+    pub fn into_vec(self) -> Vec<V> {
+        let mut vec = Vec::with_capacity(self.len());
+        vec.extend(self);
+        vec
+    }
+
+    // Clones the values of the array into a vector, in index order.
+    pub fn to_vec(&self) -> Vec<V> where V: Clone {
+        let mut vec = Vec::with_capacity(self.len());
+        vec.extend(self.iter().cloned());
+        vec
+    }
+
+    // Creates a cursor pointing at a given index, or at the ghost position past the end if the
+    // index is the length of the array. Panics if the index is past that.
+    pub fn cursor_at(&self, index: usize) -> Cursor<'_, V, M> {
+        Cursor::new(self, index)
+    }
+
+    // Fetches the first value of the array, walking down the left spine without splaying it, so
+    // that it takes time proportional to the length of the spine.
+    pub fn first(&self) -> Option<&V> {
+        let (mut node, mut flip) = (self.root.as_ref()?, false);
+        while let (Some(left), _, f) = node.sides(flip) {
+            node = left;
+            flip = f;
+        }
+        Some(&node.value)
+    }
+
+    // Fetches the last value of the array, walking down the right spine without splaying it, so
+    // that it takes time proportional to the length of the spine.
+    pub fn last(&self) -> Option<&V> {
+        let (mut node, mut flip) = (self.root.as_ref()?, false);
+        while let (_, Some(right), f) = node.sides(flip) {
+            node = right;
+            flip = f;
+        }
+        Some(&node.value)
+    }
+
+    // Fetches the first value of the array, splaying it to the root.
+    pub fn splay_first(&mut self) -> Option<&V> {
+        self.splay_get(0)
+    }
+
+    // Fetches the last value of the array, splaying it to the root.
+    pub fn splay_last(&mut self) -> Option<&V> {
+        let len = self.len();
+        self.splay_get(len.checked_sub(1)?)
+    }
+
+    // Tells whether a value equal to `x` is in the array.
+    pub fn contains(&self, x: &V) -> bool where V: PartialEq {
+        self.position(|v| v == x).is_some()
+    }
+
+    // Finds the index of the first value for which the predicate holds.
+    pub fn position<F>(&self, predicate: F) -> Option<usize> where F: FnMut(&V) -> bool {
+        self.scan(false, predicate)
+    }
+
+    // Finds the index of the last value for which the predicate holds.
+    pub fn rposition<F>(&self, predicate: F) -> Option<usize> where F: FnMut(&V) -> bool {
+        self.scan(true, predicate)
+    }
+
+    // Finds the index of the first value for which the predicate holds.
+    // This is the same as `position`, kept for callers that think in terms of lookups.
+    pub fn index_of<F>(&self, predicate: F) -> Option<usize> where F: FnMut(&V) -> bool {
+        self.position(predicate)
+    }
+
+    // Lists, in increasing order, the indices of all values for which the predicate holds.
+    // It takes a single traversal of the tree.
+    pub fn indices_of<F>(&self, mut predicate: F) -> Vec<usize> where F: FnMut(&V) -> bool {
+        let mut indices = Vec::new();
+        let mut index = 0;
+        self.scan(false, |v| {
+            if predicate(v) {
+                indices.push(index);
+            }
+            index += 1;
+            false
+        });
+        indices
+    }
+
+    // Tells whether the values in a range are equal to those of a slice, walking the range with
+    // an iterator rather than collecting it. Panics if the range is out of bounds.
+    pub fn range_eq<R, W>(&self, range: R, other: &[W]) -> bool
+        where R: RangeBounds<usize>, V: PartialEq<W> {
+        let iter = self.iter_range(range);
+        iter.len() == other.len() && iter.zip(other).all(|(a, b)| a == b)
+    }
+
+    // Tells whether the array starts with the values of a slice.
+    pub fn starts_with<W>(&self, prefix: &[W]) -> bool where V: PartialEq<W> {
+        prefix.len() <= self.len() && self.range_eq(..prefix.len(), prefix)
+    }
+
+    // Tells whether the array ends with the values of a slice.
+    pub fn ends_with<W>(&self, suffix: &[W]) -> bool where V: PartialEq<W> {
+        let len = self.len();
+        suffix.len() <= len && self.range_eq(len - suffix.len().., suffix)
+    }
+
+    // Finds the index at which a contiguous pattern of values first appears in the array, like
+    // `str::find`. It takes a single pass over the values, in linear time.
+    pub fn find_subsequence(&self, pattern: &[V]) -> Option<usize> where V: PartialEq {
+        self.subsequence_indices(pattern).next()
+    }
+
+    // Iterates over all indices at which a contiguous pattern of values appears in the array, in
+    // increasing order, including matches that overlap.
+    pub fn subsequence_indices<'a, 'b>(&'a self, pattern: &'b [V]) -> Matches<'a, 'b, V, M>
+        where V: PartialEq {
+        Matches::new(self.iter(), pattern)
+    }
+
+    // Searches a sorted array for a value, as `slice::binary_search` does: the result is the
+    // index of a matching value, or else the index at which it could be inserted while keeping the
+    // array sorted. The tree is walked down without being restructured.
+    pub fn binary_search(&self, x: &V) -> Result<usize, usize> where V: Ord {
+        self.binary_search_by(|v| v.cmp(x))
+    }
+
+    // Searches a sorted array with a comparison function, which tells whether each value it is
+    // given is less than, equal to, or greater than the target.
+    pub fn binary_search_by<F>(&self, mut f: F) -> Result<usize, usize>
+        where F: FnMut(&V) -> Ordering {
+        let (mut node, mut flip) = (self.root.as_ref(), false);
+        let mut start = 0;
+        while let Some(n) = node {
+            let (left, right, fl) = n.sides(flip);
+            flip = fl;
+            let index = start + size_of(left);
+            match f(&n.value) {
+                Equal => return Ok(index),
+                Less => {
+                    start = index + 1;
+                    node = right.as_ref();
+                },
+                Greater => node = left.as_ref(),
+            }
+        }
+        Err(start)
+    }
+
+    // Same as `binary_search_by`, splaying the deepest node reached to the root, so that searches
+    // take logarithmic time amortized, whatever the order in which values were inserted.
+    pub(crate) fn splay_search_by<F>(&mut self, mut f: F) -> Result<usize, usize>
+        where F: FnMut(&V) -> Ordering {
+        let (mut node, mut flip) = (self.root.as_ref(), false);
+        let (mut start, mut deepest, mut found) = (0, 0, None);
+        while let Some(n) = node {
+            let (left, right, fl) = n.sides(flip);
+            flip = fl;
+            deepest = start + size_of(left);
+            match f(&n.value) {
+                Equal => {
+                    found = Some(deepest);
+                    break;
+                },
+                Less => {
+                    start = deepest + 1;
+                    node = right.as_ref();
+                },
+                Greater => node = left.as_ref(),
+            }
+        }
+        self.splay_at(deepest);
+        found.ok_or(start)
+    }
+
+    // Searches an array sorted by a key extracted from each value.
+    pub fn binary_search_by_key<K, F>(&self, key: &K, mut f: F) -> Result<usize, usize>
+        where F: FnMut(&V) -> K, K: Ord {
+        self.binary_search_by(|v| f(v).cmp(key))
+    }
+
+    // Finds the index of the first value for which the predicate does not hold, assuming that it
+    // holds for all values before some index and for none after, as `slice::partition_point`
+    // does. It walks down the tree using subtree sizes, in logarithmic time on a balanced tree.
+    pub fn partition_point<F>(&self, predicate: F) -> usize where F: FnMut(&V) -> bool {
+        self.partition_walk(predicate).0
+    }
+
+    // Overwrites the value at a given index in the array.
+    // Panics if the index is out of bounds.
+    pub fn set(&mut self, index: usize, value: V) {
+        let len = self.len();
+        match self.modify(index, |v| *v = value) {
+            Some(()) => {},
+            None => panic!("set index (is {}) should be < len (is {})", index, len),
+        }
+    }
+
+    // Overwrites the value at a given index in the array, returning the previous value.
+    // The result is None, and the array is left untouched, if nothing is found at that index.
+    pub fn replace(&mut self, index: usize, value: V) -> Option<V> {
+        self.modify(index, |v| mem::replace(v, value))
+    }
+
+    // Exchanges the values at two indices, without moving nodes around.
+    // The lower index is splayed to the root, and the higher one to the root of its right subtree.
+    // Panics if either index is out of bounds.
+    pub fn swap(&mut self, a: usize, b: usize) {
+        let len = self.len();
+        assert!(a < len && b < len, "swap indices (are {} and {}) should be < len (is {})", a, b, len);
+        let (low, high) = if a < b { (a, b) } else { (b, a) };
+        if low == high {
+            return;
+        }
+        self.splay_at(low);
+        let root = self.root.as_mut().unwrap();
+        let right = root.right.as_mut().unwrap();
+        splay(high - low - 1, right);
+        mem::swap(&mut root.value, &mut right.value);
+        mem::swap(&mut root.own, &mut right.own);
+        right.update();
+        root.update();
+    }
+
+    // Inserts value at a given index in the array, shifting the following values to the right.
+    // Fails if the index is past the end of the array.
+    pub fn try_insert(&mut self, index: usize, value: V) -> Result<(), Error> {
+        let len = self.len();
+        if index > len {
+            return Err(Error::OutOfBounds { index, len });
+        }
+        self.insert(index, value);
+        Ok(())
+    }
+
+    // Inserts value at a given index in the array, shifting the following values to the right.
+    // Panics if the index is past the end of the array.
+    pub fn insert(&mut self, index: usize, value: V) {
+        let len = self.len();
+        assert!(index <= len, "insertion index (is {}) should be <= len (is {})", index, len);
+        match &mut self.root {
+            &mut Some(ref mut root) => {
+                splay(index, root);
+                match index.cmp(&root.rel_index()) {
+                    // If equal, the current root will move to the right, and therefore become
+                    // of index index+1, which is higher than index.
+                    Less | Equal => {
+                        let left = root.remove_left();
+                        let new = Node::new(value, left, None);
+                        let prev = mem::replace(root, Box::new(new));
+                        root.right = Some(prev);
+                        root.update();
+                    },
+                    Greater => {
+                        let right = root.remove_right();
+                        let new = Node::new(value, None, right);
+                        let prev = mem::replace(root, Box::new(new));
+                        root.left = Some(prev);
+                        root.update();
+                    },
+                }
+            },
+            slot => {
+                let node = Node::new(value, None, None);
+                *slot = Some(Box::new(node));
+            }
+        }
+    }
+
+    // Removes the value at a given index in the array, shifting the following values to the left.
+    // The result is None if nothing is found at that index.
+    pub fn remove(&mut self, index: usize) -> Option<V> {
+        if !self.splay_at(index) {
+            return None;
+        }
+        let mut root = self.root.take().unwrap();
+        let left = root.remove_left();
+        let right = root.remove_right();
+        self.root = join(left, right);
+        Some(root.value)
+    }
+
+    // Removes the value at a given index if the predicate holds for it, and returns it.
+    // The result is None if nothing is found at that index or if the predicate does not hold.
+    pub fn remove_if<F>(&mut self, index: usize, predicate: F) -> Option<V>
+        where F: FnOnce(&mut V) -> bool {
+        let matches = self.modify(index, predicate).unwrap_or(false);
+        if matches { self.remove(index) } else { None }
+    }
+
+    // Removes the last value if the predicate holds for it, and returns it.
+    pub fn pop_if<F>(&mut self, predicate: F) -> Option<V> where F: FnOnce(&mut V) -> bool {
+        match self.len() {
+            0 => None,
+            len => self.remove_if(len - 1, predicate),
+        }
+    }
+
+    // Removes the value at a given index, putting the last value in its place.
+    // This does not preserve ordering, but saves the join of a removal in the middle.
+    // The result is None if nothing is found at that index.
+    pub fn swap_remove(&mut self, index: usize) -> Option<V> {
+        if index >= self.len() {
+            return None;
+        }
+        let last = self.pop()?;
+        if index == self.len() {
+            return Some(last);
+        }
+        self.replace(index, last)
+    }
+
+    // Appends a value at the end of the array.
+    // The last node is left at the root, so that pushing repeatedly does not walk the tree.
+    pub fn push(&mut self, value: V) {
+        let len = self.len();
+        self.insert(len, value);
+    }
+
+    // Removes the last value of the array and returns it, or None if it is empty.
+    pub fn pop(&mut self) -> Option<V> {
+        match self.len() {
+            0 => None,
+            len => self.remove(len - 1),
+        }
+    }
+
+    // Prepends a value at the start of the array, shifting all values to the right.
+    pub fn push_front(&mut self, value: V) {
+        self.insert(0, value);
+    }
+
+    // Removes the first value of the array and returns it, or None if it is empty.
+    pub fn pop_front(&mut self) -> Option<V> {
+        self.remove(0)
+    }
+
+    // Walks down to the node at a given index, without splaying.
+    fn node_at(&self, index: usize) -> Option<&Node<V, M>> {
+        let (mut node, mut flip) = (self.root.as_ref(), false);
+        let mut index = index;
+        while let Some(n) = node {
+            let (left, right, f) = n.sides(flip);
+            let node_idx = size_of(left);
+            flip = f;
+            match index.cmp(&node_idx) {
+                Equal => return Some(n),
+                Less => node = left.as_ref(),
+                Greater => {
+                    index -= node_idx + 1;
+                    node = right.as_ref();
+                },
+            }
+        }
+        None
+    }
+
+    // Walks the values in index order (or in reverse), and returns the index of the first one for
+    // which `f` holds. The stack holds the nodes whose value is yet to be visited, along with
+    // their index, computed from the sizes of the subtrees we skip, and whether they are mirrored.
+    fn scan<F>(&self, backwards: bool, mut f: F) -> Option<usize> where F: FnMut(&V) -> bool {
+        let mut stack = Vec::new();
+        // The next subtree to walk down, the index of its first value, and whether it is mirrored.
+        let mut cur = (self.root.as_ref(), 0, false);
+        loop {
+            while let (Some(node), start, flip) = cur {
+                let (left, right, children) = node.sides(flip);
+                let index = start + size_of(left);
+                stack.push((node, index, flip));
+                cur = if backwards {
+                    (right.as_ref(), index + 1, children)
+                } else {
+                    (left.as_ref(), start, children)
+                };
+            }
+            let (node, index, flip) = stack.pop()?;
+            if f(&node.value) {
+                return Some(index);
+            }
+            let (left, right, children) = node.sides(flip);
+            cur = if backwards {
+                (left.as_ref(), index - size_of(left), children)
+            } else {
+                (right.as_ref(), index + 1, children)
+            };
+        }
+    }
+
+    // Same as `partition_point`, but the walk down is paid for by splaying the deepest node it
+    // reached, for callers that go on working around that point, or that need the walk to take
+    // logarithmic time amortized.
+    pub(crate) fn split_point<F>(&mut self, predicate: F) -> usize where F: FnMut(&V) -> bool {
+        let (count, deepest) = self.partition_walk(predicate);
+        self.splay_at(deepest);
+        count
+    }
+
+    // Walks down to the partition point, returning it along with the index of the deepest node
+    // met on the way.
+    fn partition_walk<F>(&self, mut predicate: F) -> (usize, usize) where F: FnMut(&V) -> bool {
+        let (mut node, mut flip) = (self.root.as_ref(), false);
+        let (mut count, mut deepest) = (0, 0);
+        while let Some(n) = node {
+            let (left, right, f) = n.sides(flip);
+            flip = f;
+            deepest = count + size_of(left);
+            if predicate(&n.value) {
+                count = deepest + 1;
+                node = right.as_ref();
+            } else {
+                node = left.as_ref();
+            }
+        }
+        (count, deepest)
+    }
+
+    // Same as `locate_by_measure`, for summaries holding back changes to their subtree, which are
+    // pushed down on the way. The deepest node reached is then splayed to the root.
+    pub(crate) fn locate_by_measure_mut<F>(&mut self, mut predicate: F) -> Option<(usize, M)>
+        where F: FnMut(&M) -> bool {
+        let (mut before, mut index) = (M::identity(), 0);
+        let (mut found, mut deepest) = (None, 0);
+        let mut node = self.root.as_mut();
+        while let Some(n) = node {
+            n.push_down();
+            let through_left = before.combine(&summary_of(&n.left));
+            deepest = index + size_of(&n.left);
+            if n.left.is_some() && predicate(&through_left) {
+                node = n.left.as_mut();
+                continue;
+            }
+            let through_node = through_left.combine(&n.own);
+            if predicate(&through_node) {
+                found = Some((deepest, through_left));
+                break;
+            }
+            before = through_node;
+            index = deepest + 1;
+            node = n.right.as_mut();
+        }
+        self.splay_at(deepest);
+        found
+    }
+
+    // Splits the values from `start` to `end` (excluded) out of the tree, and joins the rest.
+    fn detach(&mut self, start: usize, end: usize) -> Tree<V, M> {
+        let (left, rest) = split(self.root.take(), start);
+        let (middle, right) = split(rest, end - start);
+        self.root = join(left, right);
+        middle
+    }
+
+    // Applies `f` to the value at a given index, splayed to the root, whose summary is then
+    // brought up to date. The result is None if nothing is found at that index.
+    pub(crate) fn modify<R, F>(&mut self, index: usize, f: F) -> Option<R>
+        where F: FnOnce(&mut V) -> R {
+        if !self.splay_at(index) {
+            return None;
+        }
+        let root = self.root.as_mut().unwrap();
+        let result = f(&mut root.value);
+        root.remeasure();
+        Some(result)
+    }
+
+    // Splays the node at a given index to the root.
+    // Returns false if there is no such node.
+    fn splay_at(&mut self, index: usize) -> bool {
+        match &mut self.root {
+            None => false,
+            Some(ref mut root) => {
+                splay(index, root);
+                index == root.rel_index()
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self.root {
+            None => 0,
+            Some(ref root) => root.size,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    // Number of nodes on the longest path down from the root, to check that operations keep the
+    // tree shallow.
+    #[cfg(test)]
+    pub(crate) fn depth(&self) -> usize {
+        let mut stack: Vec<(&Node<V, M>, usize)> = self.root.iter().map(|n| (&**n, 1)).collect();
+        let mut depth = 0;
+        while let Some((node, d)) = stack.pop() {
+            depth = depth.max(d);
+            stack.extend(node.left.iter().chain(node.right.iter()).map(|n| (&**n, d + 1)));
+        }
+        depth
+    }
+
+    // Resizes the array to `len` values, either truncating it or appending clones of `value`.
+    pub fn resize(&mut self, len: usize, value: V) where V: Clone {
+        self.resize_with(len, || value.clone());
+    }
+
+    // Resizes the array to `len` values, either truncating it or appending values generated by
+    // `f`. The new values are built into a balanced tree joined at the end in one go.
+    pub fn resize_with<F>(&mut self, len: usize, f: F) where F: FnMut() -> V {
+        let cur = self.len();
+        if len <= cur {
+            self.truncate(len);
+            return;
+        }
+        let tail = build(&mut std::iter::repeat_with(f), len - cur);
+        self.root = join(self.root.take(), tail);
+    }
+
+    // Removes a range of values from the array, and iterates over them.
+    // The range is split out of the tree right away, in logarithmic time; values are yielded as
+    // the detached subtree gets torn down, and those not consumed are dropped with the iterator.
+    // Panics if the range is out of bounds.
+    pub fn drain<R>(&mut self, range: R) -> IntoIter<V, M> where R: RangeBounds<usize> {
+        let (start, end) = bounds(range, self.len());
+        IntoIter::new(self.detach(start, end))
+    }
+
+    // Removes a range of values, and returns them as an array of their own.
+    // The range is split out of the tree, and the rest joined back, in logarithmic time.
+    // Panics if the range is out of bounds.
+    pub fn take<R>(&mut self, range: R) -> TreeArray<V, M> where R: RangeBounds<usize> {
+        let (start, end) = bounds(range, self.len());
+        TreeArray { root: self.detach(start, end) }
+    }
+
+    // Removes a range of values, dropping them. Panics if the range is out of bounds.
+    pub fn remove_range<R>(&mut self, range: R) where R: RangeBounds<usize> {
+        let (start, end) = bounds(range, self.len());
+        drop_tree(self.detach(start, end));
+    }
+
+    // Moves a range of values in front of the value at index `dest`, or to the end if `dest` is
+    // the length of the array. `dest` is an index in the array as it is before the move, so it
+    // cannot fall strictly within the range. It only takes splits and joins, in logarithmic time.
+    // Panics if the range or `dest` are out of bounds.
+    pub fn move_range<R>(&mut self, range: R, dest: usize) where R: RangeBounds<usize> {
+        let len = self.len();
+        let (start, end) = bounds(range, len);
+        assert!(dest <= len, "destination (is {}) should be <= len (is {})", dest, len);
+        assert!(dest <= start || dest >= end,
+                "destination (is {}) should not be within the range {}..{}", dest, start, end);
+        let block = self.detach(start, end);
+        // Once the range is taken out, the values after it shift left.
+        let at = if dest >= end { dest - (end - start) } else { dest };
+        let (left, right) = split(self.root.take(), at);
+        self.root = join(join(left, block), right);
+    }
+
+    // Replaces a range of values with new ones, and iterates over the removed values.
+    // Both happen right away: the range is split out, and a balanced tree of the new values is
+    // joined in its place. Panics if the range is out of bounds.
+    pub fn splice<R, I>(&mut self, range: R, replace_with: I) -> IntoIter<V, M>
+        where R: RangeBounds<usize>, I: IntoIterator<Item = V> {
+        let (start, end) = bounds(range, self.len());
+        let removed = self.detach(start, end);
+        self.insert_all(start, replace_with.into_iter().collect());
+        IntoIter::new(removed)
+    }
+
+    // Removes the values for which the predicate holds, and iterates over them in index order.
+    // The remaining values keep their relative order. If the iterator is dropped before the end,
+    // the values it did not reach are kept.
+    pub fn extract_if<F>(&mut self, predicate: F) -> ExtractIf<'_, V, F, M>
+        where F: FnMut(&mut V) -> bool {
+        ExtractIf::new(self, predicate)
+    }
+
+    // Keeps only the values for which the predicate holds, in their order.
+    // It takes a single pass over the tree, rebuilding a balanced tree out of the survivors.
+    pub fn retain<F>(&mut self, mut predicate: F) where F: FnMut(&V) -> bool {
+        self.retain_mut(|v| predicate(v));
+    }
+
+    // Keeps only the values for which the predicate holds, which can modify them.
+    pub fn retain_mut<F>(&mut self, mut predicate: F) where F: FnMut(&mut V) -> bool {
+        self.extract_if(|v| !predicate(v)).for_each(drop);
+    }
+
+    // Removes consecutive equal values, keeping the first of each run.
+    pub fn dedup(&mut self) where V: PartialEq {
+        self.dedup_by(|a, b| a == b);
+    }
+
+    // Removes consecutive values that map to the same key, keeping the first of each run.
+    pub fn dedup_by_key<K, F>(&mut self, mut key: F) where F: FnMut(&mut V) -> K, K: PartialEq {
+        self.dedup_by(|a, b| key(a) == key(b));
+    }
+
+    // Removes consecutive values for which `same_bucket` holds, as `Vec::dedup_by` does: it is
+    // given each value, and the last value kept before it, which it may modify.
+    // The tree is taken apart in a single in-order pass, and the values kept rebuilt into a
+    // balanced tree.
+    pub fn dedup_by<F>(&mut self, mut same_bucket: F) where F: FnMut(&mut V, &mut V) -> bool {
+        let mut kept: Vec<V> = Vec::with_capacity(self.len());
+        for mut value in IntoIter::new(self.root.take()) {
+            let duplicate = match kept.last_mut() {
+                Some(last) => same_bucket(&mut value, last),
+                None => false,
+            };
+            if !duplicate {
+                kept.push(value);
+            }
+        }
+        let len = kept.len();
+        self.root = build(&mut kept.into_iter(), len);
+    }
+
+    // Summary of all values of the array, as kept at the root, in constant time.
+    pub fn measure(&self) -> M {
+        summary_of(&self.root)
+    }
+
+    // Summary of the values in a range. The range is split out of the tree, its summary read off
+    // its root, and the pieces joined back, in logarithmic time.
+    // Panics if the range is out of bounds.
+    pub fn measure_range<R>(&mut self, range: R) -> M where R: RangeBounds<usize> {
+        let (start, end) = bounds(range, self.len());
+        let (left, rest) = split(self.root.take(), start);
+        let (middle, right) = split(rest, end - start);
+        let summary = summary_of(&middle);
+        self.root = join(join(left, middle), right);
+        summary
+    }
+
+    // Finds the index of the first value at which the predicate holds of the summary of all values
+    // up to and including it. The predicate should hold from some index on, and not before, as
+    // it does for a running total of non-negative numbers exceeding a bound.
+    // It walks down the tree without splaying it, comparing against the summaries of left
+    // subtrees.
+    pub fn position_by_measure<F>(&self, predicate: F) -> Option<usize>
+        where F: FnMut(&M) -> bool {
+        self.locate_by_measure(predicate).map(|(index, _)| index)
+    }
+
+    // Finds the first index from `start` on at which the predicate holds of the summary of the
+    // values from `start` up to and including it, with the same requirements as
+    // `position_by_measure`.
+    // The tree is walked down to `start`; the nodes where the walk goes left come after it, each
+    // with its right subtree, and are tried from the deepest up.
+    pub fn position_by_measure_from<F>(&self, start: usize, mut predicate: F) -> Option<usize>
+        where F: FnMut(&M) -> bool {
+        let (mut node, mut flip, mut offset) = (self.root.as_ref()?, false, 0);
+        let mut later = Vec::new();
+        loop {
+            let (left, right, f) = node.sides(flip);
+            let at = offset + size_of(left);
+            match start.cmp(&at) {
+                Less => {
+                    later.push((node, flip, at));
+                    node = left.as_ref()?;
+                },
+                Equal => {
+                    later.push((node, flip, at));
+                    break;
+                },
+                Greater => {
+                    offset = at + 1;
+                    node = right.as_ref()?;
+                },
+            }
+            flip = f;
+        }
+        let mut before = M::identity();
+        while let Some((node, flip, at)) = later.pop() {
+            before = before.combine(&node.own);
+            if predicate(&before) {
+                return Some(at);
+            }
+            if let (_, Some(right), f) = node.sides(flip) {
+                match descend(right, f, before, &mut predicate) {
+                    Ok((index, _)) => return Some(at + 1 + index),
+                    Err(through) => before = through,
+                }
+            }
+        }
+        None
+    }
+
+    // Same as `position_by_measure`, also giving the summary of the values before the index.
+    pub(crate) fn locate_by_measure<F>(&self, mut predicate: F) -> Option<(usize, M)>
+        where F: FnMut(&M) -> bool {
+        descend(self.root.as_ref()?, false, M::identity(), &mut predicate).ok()
+    }
+
+    // Summary of the values from `start` to `end` (excluded), like `measure_range`, but walking
+    // down the tree without splitting it. The walk goes down to the first node in the range, and
+    // from there along both of its ends.
+    // Panics if the range is out of bounds.
+    pub(crate) fn summary_between(&self, start: usize, end: usize) -> M {
+        let len = self.len();
+        assert!(start <= end && end <= len, "range {}..{} is out of bounds for length {}",
+                start, end, len);
+        let (mut node, mut flip, mut offset) = (&self.root, false, 0);
+        while let Some(n) = node {
+            let (left, right, f) = n.sides(flip);
+            let at = offset + size_of(left);
+            if end <= at {
+                node = left;
+            } else if start > at {
+                offset = at + 1;
+                node = right;
+            } else {
+                return summary_from(left, f, start - offset).combine(&n.own)
+                    .combine(&summary_until(right, f, end - at - 1));
+            }
+            flip = f;
+        }
+        M::identity()
+    }
+
+    // Splits the array in two by a predicate: the values for which it holds, and the others, each
+    // keeping their relative order. It takes a single pass, and both arrays are built balanced.
+    pub fn partition<F>(self, mut predicate: F) -> (TreeArray<V, M>, TreeArray<V, M>)
+        where F: FnMut(&V) -> bool {
+        let (yes, no): (Vec<V>, Vec<V>) = self.into_iter().partition(|v| predicate(v));
+        (yes.into_iter().collect(), no.into_iter().collect())
+    }
+
+    // Splits the array in two at a given index: the array keeps the values before it, and the
+    // values from it onwards are returned in a new array.
+    // The node at that index is splayed to the root and detached with its right subtree, in
+    // logarithmic time. Panics if the index is past the end of the array.
+    pub fn split_off(&mut self, at: usize) -> TreeArray<V, M> {
+        let len = self.len();
+        assert!(at <= len, "split index (is {}) should be <= len (is {})", at, len);
+        let (left, right) = split(self.root.take(), at);
+        self.root = left;
+        TreeArray { root: right }
+    }
+
+    // Splits the array in two at a given index for as long as the returned guard lives, so that
+    // both halves can be borrowed mutably at once, and even grown or shrunk. They are joined back
+    // when the guard is dropped. Panics if the index is past the end of the array.
+    pub fn split_at_mut(&mut self, mid: usize) -> SplitAtMut<'_, V, M> {
+        SplitAtMut::new(self, mid)
+    }
+
+    // Moves all values of `other` to the end of the array, leaving `other` empty.
+    // The last node of the array is splayed to the root, and `other` hung as its right subtree,
+    // in logarithmic time.
+    pub fn append(&mut self, other: &mut TreeArray<V, M>) {
+        self.root = join(self.root.take(), other.root.take());
+    }
+
+    // Inserts all values of `other` at a given index, shifting the following values to the right.
+    // The array is split at the index, and the three pieces joined back, in logarithmic time.
+    // Panics if the index is past the end of the array.
+    pub fn insert_all(&mut self, index: usize, mut other: TreeArray<V, M>) {
+        let len = self.len();
+        assert!(index <= len, "insertion index (is {}) should be <= len (is {})", index, len);
+        let (left, right) = split(self.root.take(), index);
+        self.root = join(join(left, other.root.take()), right);
+    }
+
+    // Inserts clones of the values of a slice at a given index.
+    // A balanced tree is built out of the slice and spliced in, in linear time in the length of
+    // the slice plus logarithmic time in the length of the array.
+    // Panics if the index is past the end of the array.
+    pub fn insert_slice(&mut self, index: usize, values: &[V]) where V: Clone {
+        self.insert_all(index, values.iter().cloned().collect());
+    }
+
+    // Appends clones of the values of a slice to the end of the array.
+    pub fn extend_from_slice(&mut self, values: &[V]) where V: Clone {
+        self.extend(values.iter().cloned());
+    }
+
+    // Exchanges the values of two ranges that do not overlap; they may differ in length, in which
+    // case the values between them shift. The array is split in five pieces, and the ranges
+    // joined back each in place of the other, in logarithmic time.
+    // An empty range holds no values, so that it overlaps nothing: within the other range, the
+    // values stay where they are.
+    // Panics if either range is out of bounds, or if they overlap.
+    pub fn swap_ranges<R, S>(&mut self, a: R, b: S)
+        where R: RangeBounds<usize>, S: RangeBounds<usize> {
+        let len = self.len();
+        let (a, b) = (bounds(a, len), bounds(b, len));
+        let within = |(start, end): (usize, usize), (outer_start, outer_end): (usize, usize)| {
+            start == end && outer_start <= start && start <= outer_end
+        };
+        if within(a, b) || within(b, a) {
+            return;
+        }
+        let ((start1, end1), (start2, end2)) = if a <= b { (a, b) } else { (b, a) };
+        assert!(end1 <= start2, "ranges {}..{} and {}..{} should not overlap",
+                start1, end1, start2, end2);
+        let (rest, suffix) = split(self.root.take(), end2);
+        let (rest, second) = split(rest, start2);
+        let (rest, between) = split(rest, end1);
+        let (prefix, first) = split(rest, start1);
+        self.root = join(join(join(join(prefix, second), between), first), suffix);
+    }
+
+    // Appends clones of a range of values of the array to its end.
+    // The range is split out as a subtree, cloned as a whole, and both the range and its clone
+    // are joined back. Panics if the range is out of bounds.
+    pub fn extend_from_within<R>(&mut self, range: R) where V: Clone, R: RangeBounds<usize> {
+        let (start, end) = bounds(range, self.len());
+        let (left, rest) = split(self.root.take(), start);
+        let (middle, right) = split(rest, end - start);
+        let copy = clone_tree(&middle);
+        self.root = join(join(join(left, middle), right), copy);
+    }
+
+    // Inserts clones of a range of values of the array in front of the value at index `dest`, or
+    // at the end if `dest` is the length of the array, like `slice::copy_within` but growing the
+    // array. `dest` is an index in the array as it is before the copy. The range is cloned as a
+    // subtree and spliced in, in logarithmic time on top of the cloning.
+    // Panics if the range or `dest` are out of bounds.
+    pub fn clone_within<R>(&mut self, range: R, dest: usize) where V: Clone, R: RangeBounds<usize> {
+        let len = self.len();
+        let (start, end) = bounds(range, len);
+        assert!(dest <= len, "destination (is {}) should be <= len (is {})", dest, len);
+        let (left, rest) = split(self.root.take(), start);
+        let (middle, right) = split(rest, end - start);
+        let copy = clone_tree(&middle);
+        let (left, right) = split(join(join(left, middle), right), dest);
+        self.root = join(join(left, copy), right);
+    }
+
+    // Rotates the array so that the value at index `k` becomes the first one, and the first `k`
+    // values move to the end. It splits at `k` and joins the pieces the other way round, in
+    // logarithmic time. Panics if `k` is past the end of the array.
+    pub fn rotate_left(&mut self, k: usize) {
+        let len = self.len();
+        assert!(k <= len, "rotation (is {}) should be <= len (is {})", k, len);
+        let (left, right) = split(self.root.take(), k);
+        self.root = join(right, left);
+    }
+
+    // Rotates the array so that the last `k` values move to the front.
+    // Panics if `k` is past the end of the array.
+    pub fn rotate_right(&mut self, k: usize) {
+        let len = self.len();
+        assert!(k <= len, "rotation (is {}) should be <= len (is {})", k, len);
+        self.rotate_left(len - k);
+    }
+
+    // Reverses the order of the values, in constant time: the root is marked as reversed, and
+    // the children of each node get swapped as later operations walk down to it.
+    pub fn reverse(&mut self) {
+        if let Some(root) = self.root.as_mut() {
+            root.toggle_reversed();
+        }
+    }
+
+    // Reverses the order of the values in a range. The range is split out of the tree, marked as
+    // reversed at its root, and joined back, in logarithmic time.
+    // Panics if the range is out of bounds.
+    pub fn reverse_range<R>(&mut self, range: R) where R: RangeBounds<usize> {
+        self.update_range(range, |root| root.toggle_reversed());
+    }
+
+    // Splits a range out of the tree, hands the root of the range to `f`, if the range is not
+    // empty, and joins the pieces back. Panics if the range is out of bounds.
+    pub(crate) fn update_range<R, F>(&mut self, range: R, f: F)
+        where R: RangeBounds<usize>, F: FnOnce(&mut Node<V, M>) {
+        let (start, end) = bounds(range, self.len());
+        let (left, rest) = split(self.root.take(), start);
+        let (mut middle, right) = split(rest, end - start);
+        if let Some(middle) = middle.as_mut() {
+            f(middle);
+        }
+        self.root = join(join(left, middle), right);
+    }
+
+    // Sorts the array, keeping equal values in their order.
+    pub fn sort(&mut self) where V: Ord {
+        self.sort_by(|a, b| a.cmp(b));
+    }
+
+    // Sorts the array with a comparison function, keeping equal values in their order.
+    // It is a merge sort on the tree itself: the array is split in half, both halves sorted, and
+    // merged by moving whole runs of values from one half to the result with splits and joins,
+    // so that nearly sorted arrays are merged in few steps.
+    pub fn sort_by<F>(&mut self, mut compare: F) where F: FnMut(&V, &V) -> Ordering {
+        let tree = TreeArray { root: self.root.take() };
+        self.root = merge_sort(tree, &mut compare).root.take();
+    }
+
+    // Sorts the array by a key extracted from each value, keeping equal values in their order.
+    pub fn sort_by_key<K, F>(&mut self, mut f: F) where F: FnMut(&V) -> K, K: Ord {
+        self.sort_by(|a, b| f(a).cmp(&f(b)));
+    }
+
+    // Merges two sorted arrays into one sorted array, values of `self` coming first among equal
+    // ones. Whole runs of values are moved at once with splits and joins, so that merging arrays
+    // of very different lengths, or whose values seldom interleave, takes few steps.
+    pub fn merge(self, other: TreeArray<V, M>) -> TreeArray<V, M> where V: Ord {
+        self.merge_by(other, |a, b| a.cmp(b))
+    }
+
+    // Merges two arrays sorted with a comparison function into one.
+    pub fn merge_by<F>(self, other: TreeArray<V, M>, mut compare: F) -> TreeArray<V, M>
+        where F: FnMut(&V, &V) -> Ordering {
+        merge_runs(self, other, &mut compare)
+    }
+
+    // Tells whether the values are in non-decreasing order, in a single pass.
+    pub fn is_sorted(&self) -> bool where V: PartialOrd {
+        self.iter().is_sorted()
+    }
+
+    // Tells whether `compare` holds for each pair of consecutive values, as `slice::is_sorted_by`
+    // does.
+    pub fn is_sorted_by<F>(&self, mut compare: F) -> bool where F: FnMut(&V, &V) -> bool {
+        self.iter().is_sorted_by(|a, b| compare(a, b))
+    }
+
+    // Tells whether the keys extracted from the values are in non-decreasing order.
+    pub fn is_sorted_by_key<K, F>(&self, f: F) -> bool where F: FnMut(&V) -> K, K: PartialOrd {
+        self.iter().map(f).is_sorted()
+    }
+
+    // Concatenates two arrays into one, in logarithmic time.
+    pub fn join(mut self, mut other: TreeArray<V, M>) -> TreeArray<V, M> {
+        self.append(&mut other);
+        self
+    }
+
+    // Shortens the array to keep only its first `len` values, dropping the rest.
+    // It has no effect if the array is not longer than that.
+    pub fn truncate(&mut self, len: usize) {
+        let (left, right) = split(self.root.take(), len);
+        self.root = left;
+        drop_tree(right);
+    }
+
+    // Removes all values from the array.
+    pub fn clear(&mut self) {
+        drop_tree(self.root.take());
+    }
+}
+
+// Constructors that could not tell which measure is meant are for arrays without one; measured
+// arrays start from `default()`, or are collected. Methods handing out mutable references to
+// values are also only for arrays without a measure, which would not see the values change.
+impl<V> TreeArray<V> {
+    pub fn new() -> TreeArray<V> {
+        TreeArray { root: None }
+    }
+
+    // Fetches a mutable reference to the value at a given index in the array.
+    // The node is splayed to the root; changing its value does not affect the tree's shape.
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut V> {
+        if !self.splay_at(index) {
+            return None;
+        }
+        self.root.as_mut().map(|root| &mut root.value)
+    }
+
+    // Creates a cursor that can edit the array, pointing at a given index, or at the ghost
+    // position past the end if the index is the length of the array. Panics if the index is past
+    // that.
+    pub fn cursor_mut_at(&mut self, index: usize) -> CursorMut<'_, V> {
+        CursorMut::new(self, index)
+    }
+
+    // Iterates over mutable references to the values of the array in index order.
+    pub fn iter_mut(&mut self) -> IterMut<'_, V> {
+        IterMut::new(&mut self.root)
+    }
+
+    // Fetches mutable references to the values at several distinct indices at once.
+    // The result is None if an index is out of bounds or appears twice.
+    pub fn get_many_mut<const N: usize>(&mut self, indices: [usize; N]) -> Option<[&mut V; N]> {
+        let len = self.len();
+        for (i, &index) in indices.iter().enumerate() {
+            if index >= len || indices[..i].contains(&index) {
+                return None;
+            }
+        }
+        // Pairs of (index, position in the result), walked down the tree in index order.
+        let mut targets: Vec<(usize, usize)> = indices.iter().cloned().zip(0..N).collect();
+        targets.sort_unstable();
+        let mut found: [Option<&mut V>; N] = std::array::from_fn(|_| None);
+        // The borrow of each node is split between its value and its two subtrees, so that each
+        // target ends up with its own reference.
+        let mut stack = Vec::new();
+        if let Some(root) = self.root.as_mut() {
+            stack.push((root, 0, &targets[..]));
+        }
+        while let Some((node, start, targets)) = stack.pop() {
+            node.push_down();
+            let node_idx = start + node.rel_index();
+            let Node { value, left, right, .. } = &mut **node;
+            let before = targets.partition_point(|&(index, _)| index < node_idx);
+            let (left_targets, rest) = targets.split_at(before);
+            let right_targets = match rest.first() {
+                Some(&(index, slot)) if index == node_idx => {
+                    found[slot] = Some(value);
+                    &rest[1..]
+                },
+                _ => rest,
+            };
+            if let (Some(left), false) = (left.as_mut(), left_targets.is_empty()) {
+                stack.push((left, start, left_targets));
+            }
+            if let (Some(right), false) = (right.as_mut(), right_targets.is_empty()) {
+                stack.push((right, node_idx + 1, right_targets));
+            }
+        }
+        Some(found.map(|v| v.unwrap()))
+    }
+
+    // Fetches a mutable reference to the first value, splaying it to the root.
+    pub fn first_mut(&mut self) -> Option<&mut V> {
+        self.get_mut(0)
+    }
+
+    // Fetches a mutable reference to the last value, splaying it to the root.
+    pub fn last_mut(&mut self) -> Option<&mut V> {
+        match self.len() {
+            0 => None,
+            len => self.get_mut(len - 1),
+        }
+    }
+
+    // Overwrites every value of the array with clones of `value`, keeping the tree as it is.
+    pub fn fill(&mut self, value: V) where V: Clone {
+        self.iter_mut().for_each(|v| *v = value.clone());
+    }
+
+    // Overwrites every value of the array, in index order, with values generated by `f`.
+    pub fn fill_with<F>(&mut self, mut f: F) where F: FnMut() -> V {
+        self.iter_mut().for_each(|v| *v = f());
+    }
+
+    // Reorders the array so that the value at index `k` is the one that would be there if it were
+    // sorted, with values not greater than it before, and values not less than it after, and
+    // returns it. Panics if `k` is out of bounds.
+    pub fn select_nth_unstable(&mut self, k: usize) -> &mut V where V: Ord {
+        self.select_nth_unstable_by(k, |a, b| a.cmp(b))
+    }
+
+    // Reorders the array around its `k`-th value, as `select_nth_unstable` does, with a comparison
+    // function. It is a quickselect on the tree: values are split three ways around a pivot, in
+    // a pass that builds balanced trees, and only the part holding index `k` is split further;
+    // the parts are joined back in order. Pivots are picked at random, which takes expected
+    // linear time overall, whatever the order of the values.
+    pub fn select_nth_unstable_by<F>(&mut self, k: usize, mut compare: F) -> &mut V
+        where F: FnMut(&V, &V) -> Ordering {
+        let len = self.len();
+        assert!(k < len, "selection index (is {}) should be < len (is {})", k, len);
+        // The parts settled before and after the part being split.
+        let (mut before, mut after) = (TreeArray::new(), TreeArray::new());
+        let mut part = TreeArray { root: self.root.take() };
+        let mut at = k;
+        loop {
+            let at_random = (random() % part.len() as u64) as usize;
+            let pivot = part.remove(at_random).unwrap();
+            let (less, rest) = part.partition(|v| compare(v, &pivot) == Less);
+            let (mut equal, greater) = rest.partition(|v| compare(v, &pivot) == Equal);
+            equal.push(pivot);
+            if at < less.len() {
+                after = equal.join(greater).join(after);
+                part = less;
+            } else if at < less.len() + equal.len() {
+                self.root = before.join(less).join(equal).join(greater).join(after).root.take();
+                break;
+            } else {
+                at -= less.len() + equal.len();
+                before = before.join(less).join(equal);
+                part = greater;
+            }
+        }
+        self.get_mut(k).unwrap()
+    }
+
+    // Reorders the array around its `k`-th value by a key extracted from each value.
+    pub fn select_nth_unstable_by_key<K, F>(&mut self, k: usize, mut f: F) -> &mut V
+        where F: FnMut(&V) -> K, K: Ord {
+        self.select_nth_unstable_by(k, |a, b| f(a).cmp(&f(b)))
+    }
 }
 
-impl<V> Node<V> where V: std::fmt::Display {
-    pub fn new(v: V, l: Option<Box<Node<V>>>, r: Option<Box<Node<V>>>) -> Node<V> {
-        let left_size = match &l {
-            Some(left) => left.size,
-            None => 0,
-        };
-        let right_size = match &r {
-            Some(right) => right.size,
-            None => 0,
-        };
-        let size = 1 + left_size + right_size;
+// Prefix sums, for arrays measured by the sum of their values.
+impl<V> TreeArray<V, Sum<V>> where V: Clone + Default + Add<Output = V> + PartialOrd {
+    // Finds the first index at which the sum of the values up to and including it exceeds `x`,
+    // as a Fenwick tree selects, or None if the sum of all values does not.
+    // Values should not be negative, so that sums only grow. The value found is splayed to the
+    // root, so that lookups take logarithmic time amortized.
+    pub fn find_by_prefix_sum(&mut self, x: &V) -> Option<usize> {
+        self.locate_by_measure_mut(|sum| sum.0 > *x).map(|(index, _)| index)
+    }
+}
+
+// Indexing by offset, for arrays of values taking up some width: a value spans the offsets from
+// the total width of the values before it, up to that plus its own width, excluded.
+impl<V> TreeArray<V, TotalWidth> where V: Width {
+    // Total width of the values, in constant time.
+    pub fn total_width(&self) -> usize {
+        self.measure().0
+    }
+
+    // Index of the value spanning a given offset, or None if it is past the total width.
+    // The value is splayed to the root, so that lookups take logarithmic time amortized.
+    pub fn index_at_offset(&mut self, offset: usize) -> Option<usize> {
+        self.locate_by_measure_mut(|width| width.0 > offset).map(|(index, _)| index)
+    }
+
+    // Fetches the value spanning a given offset.
+    pub fn get_at_offset(&mut self, offset: usize) -> Option<&V> {
+        let index = self.index_at_offset(offset)?;
+        self.splay_get(index)
+    }
+
+    // Total width of the values before a given index. Panics if the index is past the length.
+    pub fn offset_of(&mut self, index: usize) -> usize {
+        self.measure_range(..index).0
+    }
+
+    // Inserts a value at a given offset, and returns its index. The offset should fall between
+    // two values, or at the total width; values of no width already there stay before it.
+    // Panics if the offset falls within a value, or past the total width.
+    pub fn insert_at_offset(&mut self, offset: usize, value: V) -> usize {
+        let index = self.index_at_offset(offset).unwrap_or(self.len());
+        let start = self.offset_of(index);
+        assert!(start == offset, "offset (is {}) should fall between values (at {})", offset, start);
+        self.insert(index, value);
+        index
+    }
+}
+
+// Searches for values having a flag, skipping the subtrees where no value has it.
+// A flag is given as a mask, matching values having any of its bits.
+impl<V> TreeArray<V, AnyFlags> where V: Flagged {
+    // Index of the first value having a flag.
+    pub fn first_index_where(&self, flag: u64) -> Option<usize> {
+        self.position_by_measure(|any| any.0 & flag != 0)
+    }
+
+    // Index of the first value after a given index having a flag.
+    pub fn next_index_where(&self, after: usize, flag: u64) -> Option<usize> {
+        self.position_by_measure_from(after.checked_add(1)?, |any| any.0 & flag != 0)
+    }
+}
+
+// Range extrema, read off the summaries of arrays measured by their smallest and largest values,
+// in logarithmic time amortized: the range is split out of the tree, which splays its ends. They
+// panic if the range is out of bounds, and find nothing in an empty one.
+impl<V> TreeArray<V, MinMax<V>> where V: Ord + Clone {
+    // Smallest value in a range; the first one if several are equal.
+    pub fn min_in_range<R>(&mut self, range: R) -> Option<&V> where R: RangeBounds<usize> {
+        let index = self.argmin_in_range(range)?;
+        self.splay_get(index)
+    }
+
+    // Largest value in a range; the last one if several are equal.
+    pub fn max_in_range<R>(&mut self, range: R) -> Option<&V> where R: RangeBounds<usize> {
+        let index = self.argmax_in_range(range)?;
+        self.splay_get(index)
+    }
+
+    // Index of the first smallest value in a range.
+    pub fn argmin_in_range<R>(&mut self, range: R) -> Option<usize> where R: RangeBounds<usize> {
+        let (start, end) = bounds(range, self.len());
+        self.measure_range(start..end).argmin().map(|i| start + i)
+    }
+
+    // Index of the last largest value in a range.
+    pub fn argmax_in_range<R>(&mut self, range: R) -> Option<usize> where R: RangeBounds<usize> {
+        let (start, end) = bounds(range, self.len());
+        self.measure_range(start..end).argmax().map(|i| start + i)
+    }
+}
+
+impl<V, M> Default for TreeArray<V, M> {
+    fn default() -> TreeArray<V, M> {
+        TreeArray { root: None }
+    }
+}
+
+// Cloning keeps the shape of the tree, and does not recurse.
+impl<V, M> Clone for TreeArray<V, M> where V: Clone, M: Clone {
+    fn clone(&self) -> TreeArray<V, M> {
+        TreeArray { root: clone_tree(&self.root) }
+    }
+}
+
+impl<V, M> Clone for Node<V, M> where V: Clone, M: Clone {
+    fn clone(&self) -> Node<V, M> {
         Node {
-            value: v,
-            size: size,
-            left: l,
-            right: r,
+            value: self.value.clone(),
+            size: self.size,
+            left: clone_tree(&self.left),
+            right: clone_tree(&self.right),
+            reversed: self.reversed,
+            summary: self.summary.clone(),
+            own: self.own.clone(),
         }
     }
+}
 
-    // Index of the current node in the slice of the list corresponding to the subtree in which
-    // it is root.
-    // For instance, in a tree like this:
-    //
-    //         b
-    //        / \
-    //       a   x
-    //          / \
-    //         c   d
-    //
-    // … node x has relative index 1 (in the slice cxd, which is a part of abcxd).
-    #[inline(always)]
-    pub fn rel_index(&mut self) -> usize {
-        match &self.left {
-            None => 0,
-            Some(left) => left.size,
+// Conversions from sequences build a balanced tree in linear time.
+impl<V> From<Vec<V>> for TreeArray<V> {
+    fn from(vec: Vec<V>) -> TreeArray<V> {
+        let len = vec.len();
+        TreeArray { root: build(&mut vec.into_iter(), len) }
+    }
+}
+
+impl<'a, V> From<&'a [V]> for TreeArray<V> where V: Clone {
+    fn from(slice: &'a [V]) -> TreeArray<V> {
+        TreeArray { root: build(&mut slice.iter().cloned(), slice.len()) }
+    }
+}
+
+impl<V> From<VecDeque<V>> for TreeArray<V> {
+    fn from(deque: VecDeque<V>) -> TreeArray<V> {
+        let len = deque.len();
+        TreeArray { root: build(&mut deque.into_iter(), len) }
+    }
+}
+
+impl<V> From<LinkedList<V>> for TreeArray<V> {
+    fn from(list: LinkedList<V>) -> TreeArray<V> {
+        let len = list.len();
+        TreeArray { root: build(&mut list.into_iter(), len) }
+    }
+}
+
+impl<V, M> From<TreeArray<V, M>> for VecDeque<V> where M: Measure<V> {
+    fn from(tree: TreeArray<V, M>) -> VecDeque<V> {
+        let mut deque = VecDeque::with_capacity(tree.len());
+        deque.extend(tree);
+        deque
+    }
+}
+
+impl<V, M> From<TreeArray<V, M>> for Vec<V> where M: Measure<V> {
+    fn from(tree: TreeArray<V, M>) -> Vec<V> {
+        tree.into_vec()
+    }
+}
+
+// Two arrays are equal when they hold equal values in the same order, whatever the shapes of
+// their trees.
+impl<V, W, M, N> PartialEq<TreeArray<W, N>> for TreeArray<V, M>
+    where V: PartialEq<W>, M: Measure<V>, N: Measure<W> {
+    fn eq(&self, other: &TreeArray<W, N>) -> bool {
+        self.len() == other.len() && self.iter().zip(other.iter()).all(|(a, b)| a == b)
+    }
+}
+
+impl<V, M> Eq for TreeArray<V, M> where V: Eq, M: Measure<V> {}
+
+impl<V, W, M> PartialEq<[W]> for TreeArray<V, M> where V: PartialEq<W>, M: Measure<V> {
+    fn eq(&self, other: &[W]) -> bool {
+        self.len() == other.len() && self.iter().zip(other.iter()).all(|(a, b)| a == b)
+    }
+}
+
+impl<'a, V, W, M> PartialEq<&'a [W]> for TreeArray<V, M> where V: PartialEq<W>, M: Measure<V> {
+    fn eq(&self, other: &&'a [W]) -> bool {
+        *self == **other
+    }
+}
+
+impl<V, W, M> PartialEq<Vec<W>> for TreeArray<V, M> where V: PartialEq<W>, M: Measure<V> {
+    fn eq(&self, other: &Vec<W>) -> bool {
+        *self == other[..]
+    }
+}
+
+impl<V, W, M, const N: usize> PartialEq<[W; N]> for TreeArray<V, M>
+    where V: PartialEq<W>, M: Measure<V> {
+    fn eq(&self, other: &[W; N]) -> bool {
+        *self == other[..]
+    }
+}
+
+// Arrays compare lexicographically, like slices.
+impl<V, M> PartialOrd for TreeArray<V, M> where V: PartialOrd, M: Measure<V> {
+    fn partial_cmp(&self, other: &TreeArray<V, M>) -> Option<Ordering> {
+        self.iter().partial_cmp(other.iter())
+    }
+}
+
+impl<V, M> Ord for TreeArray<V, M> where V: Ord, M: Measure<V> {
+    fn cmp(&self, other: &TreeArray<V, M>) -> Ordering {
+        self.iter().cmp(other.iter())
+    }
+}
+
+// Hashing covers the length and the values in order, so that equal arrays hash the same whatever
+// the shapes of their trees.
+impl<V, M> Hash for TreeArray<V, M> where V: Hash, M: Measure<V> {
+    fn hash<H>(&self, state: &mut H) where H: Hasher {
+        self.len().hash(state);
+        for value in self {
+            value.hash(state);
         }
     }
+}
 
-    #[inline(always)]
-    pub fn remove_left(&mut self) -> Option<Box<Node<V>>> {
-        let left_size = match &self.left {
-            Some(l) => l.size,
-            None => 0,
+// Formats as a list, like `[1, 2, 3]`.
+// The alternate form, `{:#?}`, shows the structure of the tree instead, one node per line,
+// each child indented under its parent, and pending reversals marked.
+impl<V, M> fmt::Debug for TreeArray<V, M> where V: fmt::Debug, M: Measure<V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !f.alternate() {
+            return f.debug_list().entries(self.iter()).finish();
+        }
+        let root = match &self.root {
+            None => return write!(f, "TreeArray {{}}"),
+            Some(root) => root,
         };
-        self.size -= left_size;
-        mem::replace(&mut self.left, None)
+        writeln!(f, "TreeArray {{")?;
+        // Nodes yet to be written, with their depth and the side they hang on.
+        let mut stack = vec![(&**root, 1, "")];
+        while let Some((node, depth, side)) = stack.pop() {
+            let mark = if node.reversed { " reversed" } else { "" };
+            writeln!(f, "{:indent$}{}[{:?}] size={}{}", "", side, node.value, node.size, mark,
+                     indent = 4 * depth)?;
+            if let Some(right) = &node.right {
+                stack.push((right, depth + 1, "right: "));
+            }
+            if let Some(left) = &node.left {
+                stack.push((left, depth + 1, "left: "));
+            }
+        }
+        write!(f, "}}")
     }
+}
 
-    #[inline(always)]
-    pub fn remove_right(&mut self) -> Option<Box<Node<V>>> {
-        let right_size = match &self.right {
-            Some(r) => r.size,
-            None => 0,
-        };
-        self.size -= right_size;
-        mem::replace(&mut self.right, None)
+impl<V, M> Drop for TreeArray<V, M> {
+    fn drop(&mut self) {
+        drop_tree(self.root.take());
     }
+}
 
-    pub fn to_str(&self) -> String {
-        let left = match &self.left {
-            Some(l) => l.to_str(),
-            None => String::from("nil"),
-        };
-        let right = match &self.right {
-            Some(r) => r.to_str(),
-            None => String::from("nil"),
-        };
-        std::format!("[{value} size={size}] left=({left}) right=({right})",
-            value = self.value, size = self.size, left = left, right = right)
+// Indexing walks down the tree without splaying it, so that it works behind a shared reference.
+impl<V, M> Index<usize> for TreeArray<V, M> where M: Measure<V> {
+    type Output = V;
+
+    fn index(&self, index: usize) -> &V {
+        match self.get(index) {
+            Some(v) => v,
+            None => panic!("index out of bounds: the len is {} but the index is {}", self.len(), index),
+        }
     }
 }
 
-pub struct TreeArray<V> where V: std::fmt::Display {
-    root: Option<Box<Node<V>>>
+impl<V> IndexMut<usize> for TreeArray<V> {
+    fn index_mut(&mut self, index: usize) -> &mut V {
+        let len = self.len();
+        match self.get_mut(index) {
+            Some(v) => v,
+            None => panic!("index out of bounds: the len is {} but the index is {}", len, index),
+        }
+    }
 }
 
-// A tree array is a (preferably balanced) binary tree representing a map from indices to
-// values, just like an array, where inserting a value increments indices on the right.
-// It relies on maintaining the number of nodes in the subtree on each node.
-impl<V> TreeArray<V> where V: std::fmt::Display {
-    pub fn new() -> TreeArray<V> {
-        TreeArray { root: None }
+// Drops all nodes of a tree without recursing, since splay trees can be arbitrarily deep.
+// Left children are rotated up until the node at hand has none, at which point it can be freed
+// on its own.
+pub(crate) fn drop_tree<V, M>(tree: Tree<V, M>) {
+    let mut cur = tree;
+    while let Some(mut node) = cur {
+        match node.left.take() {
+            Some(mut left) => {
+                node.left = left.right.take();
+                left.right = Some(node);
+                cur = Some(left);
+            },
+            None => {
+                cur = node.right.take();
+            },
+        }
     }
+}
 
-    // Fetches value at a given index in the array.
-    // The result is an option; it is None if nothing is found at that index.
-    pub fn get(&mut self, index: usize) -> Option<&V> {
-        match &mut self.root {
-            None => None,
-            Some(ref mut root) => {
-                splay(index, root);
-                if index == root.rel_index() {
-                    return Some(&root.value);
+// Resolves a range of indices into an array of length `len` to its start and end (excluded).
+// Panics, as slice indexing does, if the range is reversed or goes past the end.
+pub(crate) fn bounds<R>(range: R, len: usize) -> (usize, usize) where R: RangeBounds<usize> {
+    let start = match range.start_bound() {
+        Bound::Included(&start) => start,
+        Bound::Excluded(&start) => start.checked_add(1).expect("range start overflows"),
+        Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(&end) => end.checked_add(1).expect("range end overflows"),
+        Bound::Excluded(&end) => end,
+        Bound::Unbounded => len,
+    };
+    assert!(start <= end, "range start (is {}) should be <= range end (is {})", start, end);
+    assert!(end <= len, "range end (is {}) should be <= len (is {})", end, len);
+    (start, end)
+}
+
+// Clones a tree without recursing, since splay trees can be arbitrarily deep.
+// Nodes are visited in post-order: a node is cloned once the clones of its children are on top
+// of the `cloned` stack, right above left.
+fn clone_tree<V, M>(tree: &Tree<V, M>) -> Tree<V, M> where V: Clone, M: Clone {
+    enum Visit<'a, V: 'a, M: 'a> {
+        Enter(&'a Node<V, M>),
+        Exit(&'a Node<V, M>),
+    }
+    let mut todo = vec![Visit::Enter(tree.as_deref()?)];
+    let mut cloned: Vec<Tree<V, M>> = Vec::new();
+    while let Some(visit) = todo.pop() {
+        match visit {
+            Visit::Enter(node) => {
+                todo.push(Visit::Exit(node));
+                if let Some(right) = &node.right {
+                    todo.push(Visit::Enter(right));
                 }
-                None
-            }
+                if let Some(left) = &node.left {
+                    todo.push(Visit::Enter(left));
+                }
+            },
+            Visit::Exit(node) => {
+                let right = if node.right.is_some() { cloned.pop().unwrap() } else { None };
+                let left = if node.left.is_some() { cloned.pop().unwrap() } else { None };
+                cloned.push(Some(Box::new(Node {
+                    value: node.value.clone(),
+                    size: node.size,
+                    left,
+                    right,
+                    reversed: node.reversed,
+                    summary: node.summary.clone(),
+                    own: node.own.clone(),
+                })));
+            },
         }
     }
+    cloned.pop().unwrap()
+}
 
-    // Inserts value at a given index in the array.
-    pub fn insert(&mut self, index: usize, value: V) {
-        match &mut self.root {
-            &mut Some(ref mut root) => {
-                splay(index, root);
-                match index.cmp(&root.rel_index()) {
-                    // If equal, the current root will move to the right, and therefore become
-                    // of index index+1, which is higher than index.
-                    Less | Equal => {
-                        let left = root.remove_left();
-                        let new = Node::new(value, left, None);
-                        let prev = mem::replace(root, Box::new(new));
-                        root.size += prev.size;
-                        root.right = Some(prev);
-                    },
-                    Greater => {
-                        let right = root.remove_right();
-                        let new = Node::new(value, None, right);
-                        let prev = mem::replace(root, Box::new(new));
-                        root.size += prev.size;
-                        root.left = Some(prev);
-                    },
-                }
+// Sorts a tree array, as `sort_by` does. The recursion goes as deep as the number of halvings,
+// logarithmic in the length; short arrays are sorted through a vector.
+fn merge_sort<V, M, F>(mut tree: TreeArray<V, M>, compare: &mut F) -> TreeArray<V, M>
+    where M: Measure<V>, F: FnMut(&V, &V) -> Ordering {
+    let len = tree.len();
+    if len <= 32 {
+        let mut values = tree.into_vec();
+        values.sort_by(|a, b| compare(a, b));
+        return values.into_iter().collect();
+    }
+    let right = tree.split_off(len / 2);
+    let left = merge_sort(tree, compare);
+    let right = merge_sort(right, compare);
+    merge_runs(left, right, compare)
+}
+
+// Merges two sorted tree arrays, as `merge_by` does.
+// Runs are taken in turn: from the left array, the values not greater than the first of the
+// right array, and from the right array, those smaller than the first of the left array, which
+// keeps the merge stable.
+fn merge_runs<V, M, F>(mut left: TreeArray<V, M>, mut right: TreeArray<V, M>, compare: &mut F)
+    -> TreeArray<V, M> where M: Measure<V>, F: FnMut(&V, &V) -> Ordering {
+    let mut merged = TreeArray::default();
+    while let Some(first) = right.first() {
+        let n = left.split_point(|v| compare(v, first) != Greater);
+        let rest = left.split_off(n);
+        merged.append(&mut left);
+        left = rest;
+        let n = match left.first() {
+            Some(first) => right.split_point(|v| compare(v, first) == Less),
+            None => break,
+        };
+        let rest = right.split_off(n);
+        merged.append(&mut right);
+        right = rest;
+    }
+    merged.append(&mut left);
+    merged.append(&mut right);
+    merged
+}
+
+// Walks down a subtree, mirrored if `flip` is set, to the first node at which the predicate holds
+// of `before` combined with the summary of the values up to and including it, and gives its
+// index in the subtree along with the summary of the values before it.
+// If there is none, gives the summary of `before` and the whole subtree instead.
+fn descend<V, M, F>(mut node: &Node<V, M>, mut flip: bool, mut before: M, predicate: &mut F)
+    -> Result<(usize, M), M> where M: Measure<V>, F: FnMut(&M) -> bool {
+    let mut index = 0;
+    loop {
+        let (left, right, f) = node.sides(flip);
+        flip = f;
+        let through_left = match left {
+            Some(left) if flip => before.combine(&left.summary.reverse()),
+            Some(left) => before.combine(&left.summary),
+            None => before.clone(),
+        };
+        match left {
+            Some(left) if predicate(&through_left) => {
+                node = left;
+                continue;
             },
-            slot => {
-                let node = Node::new(value, None, None);
-                *slot = Some(Box::new(node));
-            }
+            _ => {},
         }
+        index += size_of(left);
+        let through_node = through_left.combine(&node.own);
+        if predicate(&through_node) {
+            return Ok((index, through_left));
+        }
+        before = through_node;
+        index += 1;
+        node = match right {
+            Some(right) => right,
+            None => return Err(before),
+        };
     }
+}
 
-    pub fn len(&self) -> usize {
-        match self.root {
-            None => 0,
-            Some(ref root) => root.size,
+// Summary of an optional subtree, mirrored if `flip` is set.
+fn oriented_summary<V, M>(node: &Tree<V, M>, flip: bool) -> M where M: Measure<V> {
+    match node {
+        Some(n) if flip => n.summary.reverse(),
+        Some(n) => n.summary.clone(),
+        None => M::identity(),
+    }
+}
+
+// Summary of the first `count` values of a subtree, mirrored if `flip` is set.
+fn summary_until<V, M>(mut node: &Tree<V, M>, mut flip: bool, mut count: usize) -> M
+    where M: Measure<V> {
+    let mut before = M::identity();
+    while let Some(n) = node {
+        if count == n.size {
+            return before.combine(&oriented_summary(node, flip));
         }
+        let (left, right, f) = n.sides(flip);
+        if count <= size_of(left) {
+            node = left;
+        } else {
+            before = before.combine(&oriented_summary(left, f)).combine(&n.own);
+            count -= size_of(left) + 1;
+            node = right;
+        }
+        flip = f;
+    }
+    before
+}
+
+// Summary of the values of a subtree from index `start` on, mirrored if `flip` is set.
+fn summary_from<V, M>(mut node: &Tree<V, M>, mut flip: bool, mut start: usize) -> M
+    where M: Measure<V> {
+    let mut after = M::identity();
+    while let Some(n) = node {
+        if start == 0 {
+            return oriented_summary(node, flip).combine(&after);
+        }
+        let (left, right, f) = n.sides(flip);
+        if start <= size_of(left) {
+            after = n.own.combine(&oriented_summary(right, f)).combine(&after);
+            node = left;
+        } else {
+            start -= size_of(left) + 1;
+            node = right;
+        }
+        flip = f;
+    }
+    after
+}
+
+// Summary of the values of an optional subtree.
+#[inline(always)]
+pub(crate) fn summary_of<V, M>(node: &Tree<V, M>) -> M where M: Measure<V> {
+    match node {
+        Some(n) => n.summary.clone(),
+        None => M::identity(),
+    }
+}
+
+// Number of nodes in an optional subtree.
+#[inline(always)]
+pub(crate) fn size_of<V, M>(node: &Tree<V, M>) -> usize {
+    match node {
+        Some(n) => n.size,
+        None => 0,
+    }
+}
+
+// Builds a perfectly balanced tree out of the next `n` values of an iterator, in linear time.
+// The recursion only goes as deep as the height of the tree, logarithmic in `n`.
+// Panics if the iterator yields fewer than `n` values.
+pub(crate) fn build<V, M, I>(values: &mut I, n: usize) -> Tree<V, M>
+    where M: Measure<V>, I: Iterator<Item = V> {
+    if n == 0 {
+        return None;
+    }
+    let left = build(values, n / 2);
+    let value = values.next().expect("too few values to build the tree");
+    let right = build(values, n - n / 2 - 1);
+    Some(Box::new(Node::new(value, left, right)))
+}
+
+// Splits a tree in two: the first `at` nodes, and the rest.
+// The node at index `at` is splayed to the root of the right part.
+fn split<V, M>(tree: Tree<V, M>, at: usize) -> (Tree<V, M>, Tree<V, M>) where M: Measure<V> {
+    match tree {
+        Some(mut root) => {
+            if at >= root.size {
+                return (Some(root), None);
+            }
+            splay(at, &mut root);
+            let left = root.remove_left();
+            (left, Some(root))
+        },
+        None => (None, None),
     }
+}
 
-    pub fn to_str(&self) -> String {
-        match &self.root {
-            None => String::from("nil"),
-            Some(r) => r.to_str(),
+// Concatenates two trees, all of `left` coming before all of `right`.
+// The largest index of `left` is splayed to its root, which has then no right child to hold
+// `right`.
+pub(crate) fn join<V, M>(left: Tree<V, M>, right: Tree<V, M>) -> Tree<V, M> where M: Measure<V> {
+    match left {
+        None => right,
+        Some(mut left) => {
+            let last = left.size - 1;
+            splay(last, &mut left);
+            left.right = right;
+            left.update();
+            Some(left)
         }
     }
 }
@@ -159,71 +1821,70 @@ impl<V> TreeArray<V> where V: std::fmt::Display {
 /// Performs a top-down splay operation on a tree rooted at `node`. This will
 /// modify the pointer to contain the new root of the tree once the splay
 /// operation is done. When finished, if `index` is in the tree, it will be at the
-/// root. Otherwise the closest key to the specified key will be at the root.
-fn splay<V>(index: usize, node: &mut Box<Node<V>>) where V: std::fmt::Display {
+/// root. Otherwise the closest index to the specified one will be at the root.
+///
+/// `index` is relative to the subtree: as we walk down, it is kept relative to
+/// the subtree currently rooted at `node`.
+fn splay<V, M>(index: usize, node: &mut Box<Node<V, M>>) where M: Measure<V> {
+    // Nodes before the target get hung, in order, on the right spine of
+    // `newleft`; nodes after it on the left spine of `newright`.
     let mut newleft = None;
     let mut newright = None;
+    let mut index = index;
+    // Number of nodes hung on each spine, and the total size they carry.
+    let (mut left_spine, mut left_size) = (0, 0);
+    let (mut right_spine, mut right_size) = (0, 0);
 
     // Explicitly grab a new scope so the loans on newleft/newright are
     // terminated before we move out of them.
     {
-        // Yes, these are backwards, that's intentional.
-        let mut l = &mut newright;
-        let mut r = &mut newleft;
-        let node_idx = node.rel_index();
+        let mut l = &mut newleft;
+        let mut r = &mut newright;
 
         loop {
+            node.push_down();
+            let node_idx = node.rel_index();
             match index.cmp(&node_idx) {
                 // Found it, yay!
                 Equal => { break }
 
                 Less => {
-                    let mut left = match node.remove_left() {
+                    let mut left = match node.left.take() {
                         Some(left) => left, None => break
                     };
+                    left.push_down();
+                    // The left subtree starts where the node's subtree starts,
+                    // so index stays the same relative to it.
+                    //
                     //               left.rel_index() (in LLL… substring)
                     //            |  ↓  |
                     // |----------LLLLLLLNRRRRRR-----|
-                    //      left_idx ⬏   ⬑ node_idx
-                    let left_idx = node_idx - 1 - match &left.right {
-                        Some(right) => right.size, None => 0
-                    };
-                    // Rotate this node right if necessary.
+                    // Rotate this node right if the index is in left-left.
                     //
                     //           L (=left)       N (=node)
-                    //  left-left           None   node-right
+                    //  left-left  left-right None   node-right
                     //  (index is in here)
-                    if index.cmp(&left_idx) == Less {
-                        // A bit odd, but avoids drop glue
+                    if index < left.rel_index() {
                         //          L (=left)           N (=node)
                         // left-left  None    left-right  node-right
                         mem::swap(&mut node.left, &mut left.right);
+                        node.update();
                         //          L (=node)           N (=left)
-                        // left-left  None    left-right  node-right
                         mem::swap(&mut left, node);
-                        left.size += match &left.right { Some(lr) => lr.size, None => 0, };
-                        node.size = left.size + 1;
                         //           L (=node)
                         // left-left            N (=left)
                         //            left-right  node-right
-                        let none = mem::replace(&mut node.right, Some(left));
-                        // left-left (=left)         L (=node)
-                        //                     None            N
-                        //                           left-right  node-right
-                        match mem::replace(&mut node.left, none) {
-                            Some(l) => { left = l; }
-                            None    => { break }
-                        }
+                        node.right = Some(left);
+                        left = match node.left.take() {
+                            Some(l) => l,
+                            None => break,
+                        };
                     }
 
-                    // L (=node)      N (=tmp)
-                    //            None (=r)
-                    //
-                    // (or)
-                    //
-                    // left-left (=node)             L (=tmp)
-                    //                     None (=r)           N
-                    //                               left-right  node-right
+                    // Hang the node (without its left subtree) on the
+                    // leftmost free slot of the right tree.
+                    right_spine += 1;
+                    right_size += 1 + size_of(&node.right);
                     *r = Some(mem::replace(node, left));
                     let tmp = r;
                     r = &mut tmp.as_mut().unwrap().left;
@@ -232,62 +1893,109 @@ fn splay<V>(index: usize, node: &mut Box<Node<V>>) where V: std::fmt::Display {
                 // If you look closely, you may have seen some similar code
                 // before
                 Greater => {
-                    match node.remove_right() {
-                        None => { break }
-                        // rotate left if necessary
-                        Some(mut right) => {
-                            //              right.rel_index() (in RRR… substring)
-                            //                    |  ↓ |
-                            // |----------LLLLLLLNRRRRRR-----|
-                            //          node_idx ⬏   ⬑ right_idx
-                            let right_idx = node_idx + 1 + right.rel_index();
-                            if index.cmp(&right_idx) == Greater {
-                                mem::swap(&mut node.right, &mut right.left);
-                                mem::swap(&mut right, node);
-                                right.size += match &right.left { Some(rl) => rl.size, None => 0, };
-                                node.size = right.size + 1;
-                                let none = mem::replace(&mut node.left,
-                                                         Some(right));
-                                match mem::replace(&mut node.right, none) {
-                                    Some(r) => { right = r; }
-                                    None    => { break }
-                                }
-                            }
-                            *l = Some(mem::replace(node, right));
-                            let tmp = l;
-                            l = &mut tmp.as_mut().unwrap().right;
-                        }
+                    let mut right = match node.right.take() {
+                        Some(right) => right, None => break
+                    };
+                    right.push_down();
+                    //              right.rel_index() (in RRR… substring)
+                    //                    |  ↓ |
+                    // |----------LLLLLLLNRRRRRR-----|
+                    //          node_idx ⬏   ⬑ right_idx
+                    let right_idx = node_idx + 1 + right.rel_index();
+                    // Rotate left if the index is in right-right.
+                    if index > right_idx {
+                        mem::swap(&mut node.right, &mut right.left);
+                        node.update();
+                        mem::swap(&mut right, node);
+                        node.left = Some(right);
+                        right = match node.right.take() {
+                            Some(r) => r,
+                            None => break,
+                        };
                     }
+
+                    // Hang the node (with its left subtree) on the rightmost
+                    // free slot of the left tree. Everything it carries comes
+                    // before the index.
+                    let skipped = 1 + size_of(&node.left);
+                    left_spine += 1;
+                    left_size += skipped;
+                    index -= skipped;
+                    *l = Some(mem::replace(node, right));
+                    let tmp = l;
+                    l = &mut tmp.as_mut().unwrap().right;
                 }
             }
         }
 
-        mem::swap(l, &mut node.left);
-        mem::swap(r, &mut node.right);
+        left_size += size_of(&node.left);
+        right_size += size_of(&node.right);
+        *l = node.left.take();
+        *r = node.right.take();
     }
 
-    node.left = newright;
-    node.right = newleft;
-    let mut node_size = 1;
-    match &mut node.right {
-        Some(ref mut r) => {
-            let right_size =
-                (match &r.right { None => 0, Some(rr) => rr.size }) +
-                (match &r.left  { None => 0, Some(rl) => rl.size }) + 1;
-            r.size = right_size;
-            node_size += right_size;
-        },
-        None => {},
-    };
-    match &mut node.left {
-        Some(ref mut l) => {
-            let left_size =
-                (match &l.right { None => 0, Some(lr) => lr.size }) +
-                (match &l.left  { None => 0, Some(ll) => ll.size }) + 1;
-            l.size = left_size;
-            node_size += left_size;
-        },
-        None => {},
-    };
-    node.size = node_size;
+    // The nodes hung on the spines still carry their old sizes and summaries. Walking down a
+    // spine, each node holds everything that remains below it on that spine. Sizes are counted
+    // down on the way; summaries cannot be taken apart that way, so the part each node adds is
+    // gathered on the way down, and combined from the bottom up. Measures that hold nothing,
+    // like the unit type, skip that.
+    let measured = mem::size_of::<M>() > 0;
+    {
+        let mut remaining = left_size;
+        let mut parts = Vec::new();
+        let mut cur = &mut newleft;
+        for _ in 0..left_spine {
+            let n = cur.as_mut().unwrap();
+            n.size = remaining;
+            remaining -= 1 + size_of(&n.left);
+            if measured {
+                parts.push(summary_of(&n.left).combine(&n.own));
+            }
+            cur = &mut n.right;
+        }
+        if measured {
+            let mut below = summary_of(cur);
+            for part in parts.iter_mut().rev() {
+                below = part.combine(&below);
+                *part = below.clone();
+            }
+            let mut cur = &mut newleft;
+            for summary in parts {
+                let n = cur.as_mut().unwrap();
+                n.summary = summary;
+                cur = &mut n.right;
+            }
+        }
+    }
+    {
+        let mut remaining = right_size;
+        let mut parts = Vec::new();
+        let mut cur = &mut newright;
+        for _ in 0..right_spine {
+            let n = cur.as_mut().unwrap();
+            n.size = remaining;
+            remaining -= 1 + size_of(&n.right);
+            if measured {
+                parts.push(n.own.combine(&summary_of(&n.right)));
+            }
+            cur = &mut n.left;
+        }
+        if measured {
+            let mut below = summary_of(cur);
+            for part in parts.iter_mut().rev() {
+                below = below.combine(part);
+                *part = below.clone();
+            }
+            let mut cur = &mut newright;
+            for summary in parts {
+                let n = cur.as_mut().unwrap();
+                n.summary = summary;
+                cur = &mut n.left;
+            }
+        }
+    }
+
+    node.left = newleft;
+    node.right = newright;
+    node.update();
 }
@@ -0,0 +1,486 @@
+use std::borrow::Cow;
+use std::fmt;
+#[cfg(feature = "unicode-segmentation")]
+use std::{iter, mem};
+use std::ops::RangeBounds;
+#[cfg(feature = "unicode-segmentation")]
+use unicode_segmentation::{GraphemeCursor, GraphemeIncomplete};
+use measure::Measure;
+use tree_array::{bounds, TreeArray};
+
+// Most bytes held by a chunk of text.
+const CHUNK: usize = 256;
+
+// A rope: UTF-8 text held in a tree array of chunks, each of at most a few hundred bytes, so
+// that edits anywhere in the text take logarithmic time, plus the size of a chunk.
+// The chunks are measured by their length in bytes, in chars and in UTF-16 code units, and by
+// how many line feeds they hold, so that the chunk holding a given char, byte, code unit or line
+// is found by walking down the tree. Lookups splay the chunk they find to the root, so that they
+// take logarithmic time amortized, which is why they need a mutable reference.
+// Text is indexed by char, and lines end after each line feed.
+pub struct TreeString {
+    chunks: TreeArray<String, TextInfo>,
+}
+
+// Summary of a run of chunks.
+#[derive(Clone, Copy, Debug, Default)]
+struct TextInfo {
+    bytes: usize,
+    chars: usize,
+    utf16: usize,
+    lines: usize,
+}
+
+impl Measure<String> for TextInfo {
+    fn identity() -> TextInfo {
+        TextInfo::default()
+    }
+
+    fn measure(chunk: &String) -> TextInfo {
+        TextInfo {
+            bytes: chunk.len(),
+            chars: chunk.chars().count(),
+            utf16: chunk.chars().map(char::len_utf16).sum(),
+            lines: chunk.bytes().filter(|&b| b == b'\n').count(),
+        }
+    }
+
+    fn combine(&self, other: &TextInfo) -> TextInfo {
+        TextInfo {
+            bytes: self.bytes + other.bytes,
+            chars: self.chars + other.chars,
+            utf16: self.utf16 + other.utf16,
+            lines: self.lines + other.lines,
+        }
+    }
+}
+
+impl TreeString {
+    pub fn new() -> TreeString {
+        TreeString { chunks: TreeArray::default() }
+    }
+
+    // Number of chars, in constant time.
+    pub fn len_chars(&self) -> usize {
+        self.chunks.measure().chars
+    }
+
+    // Number of bytes, in constant time.
+    pub fn len_bytes(&self) -> usize {
+        self.chunks.measure().bytes
+    }
+
+    // Number of UTF-16 code units, in constant time.
+    pub fn len_utf16(&self) -> usize {
+        self.chunks.measure().utf16
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    // Number of lines, in constant time: one more than there are line feeds.
+    pub fn len_lines(&self) -> usize {
+        self.chunks.measure().lines + 1
+    }
+
+    // Index of the first char of a line. The line after the last one starts at the end of the
+    // text. Panics if the line is past that.
+    pub fn line_to_char(&mut self, line: usize) -> usize {
+        let len = self.len_lines();
+        assert!(line <= len, "line index (is {}) should be <= len (is {})", line, len);
+        if line == 0 {
+            return 0;
+        }
+        match self.chunks.locate_by_measure_mut(|info| info.lines >= line) {
+            Some((i, before)) => {
+                let mut feeds = self.chunks[i].chars().enumerate().filter(|&(_, c)| c == '\n');
+                let (feed, _) = feeds.nth(line - before.lines - 1).unwrap();
+                before.chars + feed + 1
+            },
+            None => self.len_chars(),
+        }
+    }
+
+    // Index of the line holding the char at a given index, or the last line if the index is the
+    // number of chars. Panics if the index is past that.
+    pub fn char_to_line(&mut self, char_idx: usize) -> usize {
+        let len = self.len_chars();
+        assert!(char_idx <= len, "char index (is {}) should be <= len (is {})", char_idx, len);
+        match self.locate(char_idx) {
+            Some((i, before)) => {
+                let chars = self.chunks[i].chars().take(char_idx - before.chars);
+                before.lines + chars.filter(|&c| c == '\n').count()
+            },
+            None => self.len_lines() - 1,
+        }
+    }
+
+    // Index of the first byte of the char at a given index, or the number of bytes if the index
+    // is the number of chars. Panics if the index is past that.
+    pub fn char_to_byte(&mut self, char_idx: usize) -> usize {
+        let len = self.len_chars();
+        assert!(char_idx <= len, "char index (is {}) should be <= len (is {})", char_idx, len);
+        match self.locate(char_idx) {
+            Some((i, before)) => before.bytes + byte_of(&self.chunks[i], char_idx - before.chars),
+            None => self.len_bytes(),
+        }
+    }
+
+    // Index of the char holding the byte at a given index, or the number of chars if the index
+    // is the number of bytes. Panics if the index is past that.
+    pub fn byte_to_char(&mut self, byte_idx: usize) -> usize {
+        let len = self.len_bytes();
+        assert!(byte_idx <= len, "byte index (is {}) should be <= len (is {})", byte_idx, len);
+        match self.chunks.locate_by_measure_mut(|info| info.bytes > byte_idx) {
+            Some((i, before)) => {
+                let mut bytes = before.bytes;
+                let within = self.chunks[i].chars().take_while(|c| {
+                    bytes += c.len_utf8();
+                    bytes <= byte_idx
+                });
+                before.chars + within.count()
+            },
+            None => self.len_chars(),
+        }
+    }
+
+    // Index of the first UTF-16 code unit of the char at a given index, or the number of code
+    // units if the index is the number of chars. Panics if the index is past that.
+    pub fn char_to_utf16(&mut self, char_idx: usize) -> usize {
+        let len = self.len_chars();
+        assert!(char_idx <= len, "char index (is {}) should be <= len (is {})", char_idx, len);
+        match self.locate(char_idx) {
+            Some((i, before)) => {
+                let chars = self.chunks[i].chars().take(char_idx - before.chars);
+                before.utf16 + chars.map(char::len_utf16).sum::<usize>()
+            },
+            None => self.len_utf16(),
+        }
+    }
+
+    // Index of the char holding the UTF-16 code unit at a given index, or the number of chars if
+    // the index is the number of code units. Panics if the index is past that.
+    pub fn utf16_to_char(&mut self, utf16_idx: usize) -> usize {
+        let len = self.len_utf16();
+        assert!(utf16_idx <= len, "UTF-16 index (is {}) should be <= len (is {})", utf16_idx, len);
+        match self.chunks.locate_by_measure_mut(|info| info.utf16 > utf16_idx) {
+            Some((i, before)) => {
+                let mut units = before.utf16;
+                let within = self.chunks[i].chars().take_while(|c| {
+                    units += c.len_utf16();
+                    units <= utf16_idx
+                });
+                before.chars + within.count()
+            },
+            None => self.len_chars(),
+        }
+    }
+
+    // Text of a line, along with the line feed ending it, if any.
+    // Panics if the line is past the last one.
+    pub fn line(&mut self, line: usize) -> Cow<'_, str> {
+        let len = self.len_lines();
+        assert!(line < len, "line index (is {}) should be < len (is {})", line, len);
+        let (start, end) = (self.line_to_char(line), self.line_to_char(line + 1));
+        self.slice(start..end)
+    }
+
+    // Fetches the char at a given index.
+    pub fn char(&mut self, char_idx: usize) -> Option<char> {
+        let (i, before) = self.locate(char_idx)?;
+        self.chunks[i].chars().nth(char_idx - before.chars)
+    }
+
+    // Text of a range of chars. It is borrowed when it lies within a single chunk.
+    // Panics if the range is out of bounds.
+    pub fn slice<R>(&mut self, range: R) -> Cow<'_, str> where R: RangeBounds<usize> {
+        let (start, end) = bounds(range, self.len_chars());
+        let (i, before) = match self.locate(start) {
+            Some(found) if start < end => found,
+            _ => return Cow::Borrowed(""),
+        };
+        let mut text = String::new();
+        let (mut skip, mut remaining) = (start - before.chars, end - start);
+        for chunk in self.chunks.iter_range(i..) {
+            let rest = &chunk[byte_of(chunk, skip)..];
+            let n = rest.chars().count();
+            if n >= remaining {
+                let rest = &rest[..byte_of(rest, remaining)];
+                if text.is_empty() {
+                    return Cow::Borrowed(rest);
+                }
+                text.push_str(rest);
+                break;
+            }
+            text.push_str(rest);
+            remaining -= n;
+            skip = 0;
+        }
+        Cow::Owned(text)
+    }
+
+    // Inserts text at a given char index. Panics if the index is past the number of chars.
+    pub fn insert_str(&mut self, char_idx: usize, text: &str) {
+        let len = self.len_chars();
+        assert!(char_idx <= len, "char index (is {}) should be <= len (is {})", char_idx, len);
+        let i = self.split_at_char(char_idx);
+        let chunks = split_chunks(text);
+        let n = chunks.len();
+        self.chunks.insert_all(i, chunks);
+        self.merge(i + n);
+        self.merge(i);
+    }
+
+    // Removes a range of chars. Panics if the range is out of bounds.
+    pub fn remove_range<R>(&mut self, range: R) where R: RangeBounds<usize> {
+        let (start, end) = bounds(range, self.len_chars());
+        let i = self.split_at_char(start);
+        let j = self.split_at_char(end);
+        self.chunks.remove_range(i..j);
+        self.merge(i);
+    }
+
+    // Depth of the tree of chunks, to check that lookups keep it shallow.
+    #[cfg(test)]
+    pub(crate) fn depth(&self) -> usize {
+        self.chunks.depth()
+    }
+
+    // Iterates over the chunks of text, in order.
+    pub fn chunks(&self) -> impl Iterator<Item = &str> {
+        self.chunks.iter().map(|chunk| chunk.as_str())
+    }
+
+    pub fn chars(&self) -> impl Iterator<Item = char> + '_ {
+        self.chunks().flat_map(str::chars)
+    }
+
+    pub fn bytes(&self) -> impl Iterator<Item = u8> + '_ {
+        self.chunks().flat_map(str::bytes)
+    }
+
+    // Finds the chunk holding the char at a given index, along with the summary of the chunks
+    // before it. The chunk is splayed to the root, so that reading it right after is quick.
+    fn locate(&mut self, char_idx: usize) -> Option<(usize, TextInfo)> {
+        self.chunks.locate_by_measure_mut(|info| info.chars > char_idx)
+    }
+
+    // Makes a chunk start at a given char index, splitting the chunk holding it if need be, and
+    // returns the index of that chunk.
+    fn split_at_char(&mut self, char_idx: usize) -> usize {
+        let (i, before) = match self.locate(char_idx) {
+            Some(found) => found,
+            None => return self.chunks.len(),
+        };
+        if char_idx == before.chars {
+            return i;
+        }
+        let mut head = self.chunks.replace(i, String::new()).unwrap();
+        let tail = head.split_off(byte_of(&head, char_idx - before.chars));
+        self.chunks.set(i, head);
+        self.chunks.insert(i + 1, tail);
+        i + 1
+    }
+
+    // Merges the chunk at a given index into the one before it, if they fit in a single chunk.
+    fn merge(&mut self, i: usize) {
+        if i == 0 || i >= self.chunks.len() {
+            return;
+        }
+        let tail = self.chunks.splay_get(i).unwrap().len();
+        if self.chunks.splay_get(i - 1).unwrap().len() + tail > CHUNK {
+            return;
+        }
+        let tail = self.chunks.remove(i).unwrap();
+        let mut head = self.chunks.replace(i - 1, String::new()).unwrap();
+        head.push_str(&tail);
+        self.chunks.set(i - 1, head);
+    }
+}
+
+// Grapheme clusters, as users perceive characters, like an emoji with its modifiers, or a letter
+// with its combining accents. Boundaries are found one chunk at a time, with the chunks around
+// handed over as the segmentation needs them.
+#[cfg(feature = "unicode-segmentation")]
+impl TreeString {
+    // Index of the char starting the grapheme cluster after the one holding a given char, or the
+    // number of chars at the end of the text. Panics if the index is past that.
+    pub fn next_grapheme_boundary(&mut self, char_idx: usize) -> usize {
+        let byte = self.char_to_byte(char_idx);
+        let (mut i, before) = match self.chunks.locate_by_measure_mut(|info| info.bytes > byte) {
+            Some(found) => found,
+            None => return self.len_chars(),
+        };
+        let mut start = before.bytes;
+        let mut cursor = GraphemeCursor::new(byte, self.len_bytes(), true);
+        loop {
+            match cursor.next_boundary(self.chunks.splay_get(i).unwrap(), start) {
+                Ok(Some(boundary)) => return self.byte_to_char(boundary),
+                Ok(None) => return self.len_chars(),
+                Err(GraphemeIncomplete::NextChunk) => {
+                    start += self.chunks[i].len();
+                    i += 1;
+                },
+                Err(GraphemeIncomplete::PreContext(end)) => self.provide_context(&mut cursor, end),
+                Err(e) => panic!("unexpected grapheme segmentation request: {:?}", e),
+            }
+        }
+    }
+
+    // Index of the char starting the grapheme cluster before the one holding a given char, or
+    // zero at the start of the text. Panics if the index is past the number of chars.
+    pub fn prev_grapheme_boundary(&mut self, char_idx: usize) -> usize {
+        let byte = self.char_to_byte(char_idx);
+        let (mut i, before) = match byte.checked_sub(1) {
+            Some(last) => self.chunks.locate_by_measure_mut(|info| info.bytes > last).unwrap(),
+            None => return 0,
+        };
+        let mut start = before.bytes;
+        let mut cursor = GraphemeCursor::new(byte, self.len_bytes(), true);
+        loop {
+            match cursor.prev_boundary(self.chunks.splay_get(i).unwrap(), start) {
+                Ok(Some(boundary)) => return self.byte_to_char(boundary),
+                Ok(None) => return 0,
+                Err(GraphemeIncomplete::PrevChunk) => {
+                    i -= 1;
+                    start -= self.chunks.splay_get(i).unwrap().len();
+                },
+                Err(GraphemeIncomplete::PreContext(end)) => self.provide_context(&mut cursor, end),
+                Err(e) => panic!("unexpected grapheme segmentation request: {:?}", e),
+            }
+        }
+    }
+
+    // Iterates over the grapheme clusters of the text, in order. The chunks are gone through
+    // once, each handed to the cursor in turn; clusters spanning chunks are pieced together.
+    pub fn graphemes(&self) -> impl Iterator<Item = Cow<'_, str>> {
+        let mut chunks = self.chunks();
+        let (mut prev, mut chunk) = ("", chunks.next().unwrap_or(""));
+        // Byte offsets of the chunk, and of the start of the next cluster.
+        let (mut start, mut from) = (0, 0);
+        let mut cursor = GraphemeCursor::new(0, self.len_bytes(), true);
+        iter::from_fn(move || {
+            let mut grapheme = Cow::Borrowed("");
+            loop {
+                let (end, boundary) = match cursor.next_boundary(chunk, start) {
+                    Ok(Some(boundary)) => (boundary, true),
+                    Ok(None) => return None,
+                    Err(GraphemeIncomplete::NextChunk) => (start + chunk.len(), false),
+                    Err(GraphemeIncomplete::PreContext(end)) if end == start => {
+                        cursor.provide_context(prev, start - prev.len());
+                        continue;
+                    },
+                    Err(GraphemeIncomplete::PreContext(end)) => {
+                        let (i, before) = self.chunks.locate_by_measure(|info| info.bytes >= end)
+                            .unwrap();
+                        cursor.provide_context(&self.chunks[i], before.bytes);
+                        continue;
+                    },
+                    Err(e) => panic!("unexpected grapheme segmentation request: {:?}", e),
+                };
+                let piece = &chunk[from.max(start) - start..end - start];
+                grapheme = match grapheme {
+                    Cow::Borrowed("") => Cow::Borrowed(piece),
+                    text if piece.is_empty() => text,
+                    mut text => {
+                        text.to_mut().push_str(piece);
+                        text
+                    },
+                };
+                if boundary {
+                    from = end;
+                    return Some(grapheme);
+                }
+                start = end;
+                prev = mem::replace(&mut chunk, chunks.next().unwrap_or(""));
+            }
+        })
+    }
+
+    // Hands the chunk ending at a given byte to a grapheme cursor, which needs to look back.
+    fn provide_context(&mut self, cursor: &mut GraphemeCursor, end: usize) {
+        let (i, before) = self.chunks.locate_by_measure_mut(|info| info.bytes >= end).unwrap();
+        cursor.provide_context(&self.chunks[i], before.bytes);
+    }
+}
+
+// Byte offset of the char at a given index in some text, or its length if there are not that
+// many chars.
+fn byte_of(text: &str, char_idx: usize) -> usize {
+    text.char_indices().nth(char_idx).map_or(text.len(), |(byte, _)| byte)
+}
+
+// Cuts text into chunks, on char boundaries, collected into a balanced tree.
+fn split_chunks(mut text: &str) -> TreeArray<String, TextInfo> {
+    let mut chunks = Vec::new();
+    while !text.is_empty() {
+        let mut end = text.len().min(CHUNK);
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        chunks.push(text[..end].to_string());
+        text = &text[end..];
+    }
+    chunks.into_iter().collect()
+}
+
+impl Default for TreeString {
+    fn default() -> TreeString {
+        TreeString::new()
+    }
+}
+
+impl Clone for TreeString {
+    fn clone(&self) -> TreeString {
+        TreeString { chunks: self.chunks.clone() }
+    }
+}
+
+impl<'a> From<&'a str> for TreeString {
+    fn from(text: &'a str) -> TreeString {
+        TreeString { chunks: split_chunks(text) }
+    }
+}
+
+impl From<String> for TreeString {
+    fn from(text: String) -> TreeString {
+        TreeString::from(text.as_str())
+    }
+}
+
+impl PartialEq for TreeString {
+    fn eq(&self, other: &TreeString) -> bool {
+        self.len_bytes() == other.len_bytes() && self.bytes().eq(other.bytes())
+    }
+}
+
+impl Eq for TreeString {}
+
+impl PartialEq<str> for TreeString {
+    fn eq(&self, other: &str) -> bool {
+        self.len_bytes() == other.len() && self.bytes().eq(other.bytes())
+    }
+}
+
+impl<'a> PartialEq<&'a str> for TreeString {
+    fn eq(&self, other: &&'a str) -> bool {
+        *self == **other
+    }
+}
+
+impl fmt::Display for TreeString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for chunk in self.chunks() {
+            f.write_str(chunk)?;
+        }
+        Ok(())
+    }
+}
+
+// Formats as a string literal, like `"text"`.
+impl fmt::Debug for TreeString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.to_string(), f)
+    }
+}